@@ -1,21 +1,30 @@
 use std::io;
+use std::process::Stdio;
 
-use anyhow::Result;
+use anyhow::{Context as _, Result, anyhow};
+use base64::{Engine as _, engine::general_purpose::STANDARD};
 use regex_lite::Regex;
 use reqwest::StatusCode;
 use reqwest::blocking::{Request, Response};
-use reqwest::header::{AUTHORIZATION, HeaderValue, WWW_AUTHENTICATE};
+use reqwest::header::{
+    AUTHORIZATION, HeaderName, HeaderValue, PROXY_AUTHENTICATE, PROXY_AUTHORIZATION,
+    WWW_AUTHENTICATE,
+};
 
 use crate::cli::AuthType;
 use crate::middleware::{Context, Middleware};
 use crate::netrc;
-use crate::utils::clone_request;
+use crate::ntlm;
+use crate::utils::{clone_request, shell_command, unescape};
 
 #[derive(Debug, PartialEq, Eq)]
 pub enum Auth {
     Bearer(String),
     Basic(String, Option<String>),
     Digest(String, String),
+    Ntlm(String, String),
+    /// Wait for the server's challenge and use the best scheme it supports.
+    Any(String, String),
 }
 
 impl Auth {
@@ -25,24 +34,110 @@ impl Auth {
                 let (username, password) = parse_auth(auth, host)?;
                 Ok(Auth::Basic(username, password))
             }
-            AuthType::Digest => {
+            AuthType::Digest | AuthType::Ntlm | AuthType::Any => {
                 let (username, password) = parse_auth(auth, host)?;
-                Ok(Auth::Digest(
+                Ok(Auth::with_password(
+                    auth_type,
                     username,
-                    password.unwrap_or_else(|| "".into()),
+                    password.unwrap_or_default(),
                 ))
             }
             AuthType::Bearer => Ok(Auth::Bearer(auth.into())),
         }
     }
 
-    pub fn from_netrc(auth_type: AuthType, entry: netrc::Entry) -> Option<Auth> {
+    fn with_password(auth_type: AuthType, username: String, password: String) -> Auth {
         match auth_type {
-            AuthType::Basic => Some(Auth::Basic(entry.login?, Some(entry.password))),
-            AuthType::Bearer => Some(Auth::Bearer(entry.password)),
-            AuthType::Digest => Some(Auth::Digest(entry.login?, entry.password)),
+            AuthType::Basic => Auth::Basic(username, Some(password)),
+            AuthType::Digest => Auth::Digest(username, password),
+            AuthType::Ntlm => Auth::Ntlm(username, password),
+            AuthType::Any => Auth::Any(username, password),
+            AuthType::Bearer => unreachable!("bearer auth has no username"),
         }
     }
+
+    /// Build credentials from `--auth-cmd`, optionally combined with a username from `--auth`.
+    pub fn from_command(auth: Option<&str>, command: &str, auth_type: AuthType) -> Result<Auth> {
+        match auth_type {
+            AuthType::Basic | AuthType::Digest | AuthType::Ntlm | AuthType::Any => {
+                let username = match auth {
+                    Some(auth) => match auth.split_once(':') {
+                        Some((username, "")) => username.to_string(),
+                        Some(_) => {
+                            return Err(anyhow!(
+                                "--auth-cmd can't be combined with a password in --auth"
+                            ));
+                        }
+                        None => auth.to_string(),
+                    },
+                    None => {
+                        return Err(anyhow!(
+                            "--auth-cmd needs a username for {} auth, pass it with --auth=USER",
+                            auth_type_name(auth_type)
+                        ));
+                    }
+                };
+                let password = run_credential_command(command)?;
+                Ok(Auth::with_password(auth_type, username, password))
+            }
+            AuthType::Bearer => {
+                if auth.is_some() {
+                    return Err(anyhow!(
+                        "--auth-cmd can't be combined with a token in --auth"
+                    ));
+                }
+                Ok(Auth::Bearer(run_credential_command(command)?))
+            }
+        }
+    }
+
+    pub fn from_netrc(auth_type: AuthType, entry: netrc::Entry) -> Result<Option<Auth>> {
+        let password = || match entry.password {
+            netrc::Password::Plain(password) => Ok(password),
+            netrc::Password::Command(command) => run_credential_command(&command),
+        };
+        Ok(match (auth_type, entry.login) {
+            (AuthType::Bearer, _) => Some(Auth::Bearer(password()?)),
+            (_, Some(login)) => Some(Auth::with_password(auth_type, login, password()?)),
+            (_, None) => None,
+        })
+    }
+}
+
+fn auth_type_name(auth_type: AuthType) -> &'static str {
+    match auth_type {
+        AuthType::Basic => "basic",
+        AuthType::Bearer => "bearer",
+        AuthType::Digest => "digest",
+        AuthType::Ntlm => "ntlm",
+        AuthType::Any => "any",
+    }
+}
+
+/// Run a credential helper and return the first line of its output.
+///
+/// The command is run through the shell. stdin and stderr are inherited so that
+/// helpers like `pass` or `gpg` can ask for a passphrase.
+pub fn run_credential_command(command: &str) -> Result<String> {
+    log::debug!("Running credential command {command:?}");
+    let output = shell_command(command)
+        .stdin(Stdio::inherit())
+        .stderr(Stdio::inherit())
+        .output()
+        .with_context(|| format!("Failed to run credential command {command:?}"))?;
+    if !output.status.success() {
+        return Err(anyhow!(
+            "Credential command {:?} failed ({})",
+            command,
+            output.status
+        ));
+    }
+    let output = String::from_utf8(output.stdout)
+        .with_context(|| format!("Credential command {command:?} printed invalid UTF-8"))?;
+    match output.lines().next() {
+        Some(secret) if !secret.is_empty() => Ok(secret.to_string()),
+        _ => Err(anyhow!("Credential command {:?} printed nothing", command)),
+    }
 }
 
 pub fn parse_auth(auth: &str, host: &str) -> io::Result<(String, Option<String>)> {
@@ -60,40 +155,296 @@ pub fn parse_auth(auth: &str, host: &str) -> io::Result<(String, Option<String>)
     }
 }
 
-pub struct DigestAuthMiddleware<'a> {
+/// A single challenge from a `WWW-Authenticate` header.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Challenge {
+    /// The auth scheme, in lowercase.
+    pub scheme: String,
+    /// An opaque token, as sent by NTLM and Negotiate.
+    pub token68: Option<String>,
+    pub params: Vec<(String, String)>,
+}
+
+/// Parse the challenges in a `WWW-Authenticate` header value.
+///
+/// A header can hold several comma-separated challenges, each of which can have
+/// comma-separated parameters, so a token only starts a new challenge if it isn't
+/// followed by `=`. Anything unparseable ends the list.
+///
+/// See https://www.rfc-editor.org/rfc/rfc9110#section-11.6.1
+pub fn parse_challenges(header: &str) -> Vec<Challenge> {
+    const TOKEN: &str = r"[!#$%&'*+\-.^_`|~0-9A-Za-z]+";
+    let scheme_re = Regex::new(&format!("^{TOKEN}")).unwrap();
+    let param_re = Regex::new(&format!(
+        r#"^({TOKEN})[ \t]*=[ \t]*(?:"((?:[^"\\]|\\.)*)"|({TOKEN}))[ \t]*(?:,|$)"#
+    ))
+    .unwrap();
+    let token68_re = Regex::new(r"^([A-Za-z0-9\-._~+/]+=*)[ \t]*(?:,|$)").unwrap();
+
+    let mut challenges = Vec::new();
+    let mut rest = header;
+    loop {
+        rest = rest.trim_start_matches([' ', '\t', ',']);
+        let Some(scheme) = scheme_re.find(rest) else {
+            break;
+        };
+        let mut challenge = Challenge {
+            scheme: scheme.as_str().to_ascii_lowercase(),
+            token68: None,
+            params: Vec::new(),
+        };
+        rest = rest[scheme.end()..].trim_start_matches([' ', '\t']);
+
+        if !param_re.is_match(rest) {
+            if let Some(cap) = token68_re.captures(rest) {
+                challenge.token68 = Some(cap[1].to_string());
+                rest = &rest[cap.get(0).unwrap().end()..];
+            }
+        }
+        while let Some(cap) = param_re.captures(rest) {
+            let value = match cap.get(2) {
+                Some(quoted) => unescape(quoted.as_str(), "\"\\"),
+                None => cap[3].to_string(),
+            };
+            challenge.params.push((cap[1].to_ascii_lowercase(), value));
+            rest = rest[cap.get(0).unwrap().end()..].trim_start_matches([' ', '\t', ',']);
+        }
+        challenges.push(challenge);
+    }
+    challenges
+}
+
+/// An auth scheme that can answer a challenge.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Scheme {
+    Basic,
+    Digest,
+    Ntlm,
+    /// Negotiate with raw NTLM tokens, which Windows servers accept in place
+    /// of SPNEGO. Kerberos is not supported.
+    Negotiate,
+}
+
+impl Scheme {
+    fn name(self) -> &'static str {
+        match self {
+            Scheme::Basic => "Basic",
+            Scheme::Digest => "Digest",
+            Scheme::Ntlm => "NTLM",
+            Scheme::Negotiate => "Negotiate",
+        }
+    }
+}
+
+/// Whether challenges come from the server or from a proxy.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Target {
+    Server,
+    Proxy,
+}
+
+impl Target {
+    fn status(self) -> StatusCode {
+        match self {
+            Target::Server => StatusCode::UNAUTHORIZED,
+            Target::Proxy => StatusCode::PROXY_AUTHENTICATION_REQUIRED,
+        }
+    }
+
+    fn challenge_header(self) -> HeaderName {
+        match self {
+            Target::Server => WWW_AUTHENTICATE,
+            Target::Proxy => PROXY_AUTHENTICATE,
+        }
+    }
+
+    fn authorization_header(self) -> HeaderName {
+        match self {
+            Target::Server => AUTHORIZATION,
+            Target::Proxy => PROXY_AUTHORIZATION,
+        }
+    }
+}
+
+/// Answers `401 Unauthorized` (or `407 Proxy Authentication Required`)
+/// responses using the best scheme the server offers.
+pub struct ChallengeAuthMiddleware<'a> {
     username: &'a str,
     password: &'a str,
+    /// The schemes to pick from, most preferred first.
+    schemes: &'static [Scheme],
+    target: Target,
 }
 
-impl<'a> DigestAuthMiddleware<'a> {
-    pub fn new(username: &'a str, password: &'a str) -> Self {
-        DigestAuthMiddleware { username, password }
+impl<'a> ChallengeAuthMiddleware<'a> {
+    /// Returns `None` for auth types that don't wait for a challenge.
+    pub fn new(auth: &'a Auth) -> Option<Self> {
+        let (username, password, schemes): (_, _, &[Scheme]) = match auth {
+            Auth::Digest(username, password) => (username, password, &[Scheme::Digest]),
+            Auth::Ntlm(username, password) => {
+                (username, password, &[Scheme::Ntlm, Scheme::Negotiate])
+            }
+            Auth::Any(username, password) => (
+                username,
+                password,
+                // Like curl's --anyauth. Negotiate could be Kerberos-only,
+                // in which case our NTLM tokens would be rejected
+                &[
+                    Scheme::Digest,
+                    Scheme::Ntlm,
+                    Scheme::Negotiate,
+                    Scheme::Basic,
+                ],
+            ),
+            Auth::Basic(..) | Auth::Bearer(..) => return None,
+        };
+        Some(ChallengeAuthMiddleware {
+            username,
+            password,
+            schemes,
+            target: Target::Server,
+        })
+    }
+
+    /// Answer a proxy's challenges. Basic proxy auth is handled by reqwest, so
+    /// this returns `None` unless `auth` is digest auth.
+    pub fn for_proxy(auth: &'a Auth) -> Option<Self> {
+        match auth {
+            Auth::Digest(username, password) => Some(ChallengeAuthMiddleware {
+                username,
+                password,
+                schemes: &[Scheme::Digest],
+                target: Target::Proxy,
+            }),
+            _ => None,
+        }
+    }
+
+    fn select<'c>(&self, challenges: &'c [Challenge]) -> Option<(Scheme, &'c Challenge)> {
+        self.schemes.iter().find_map(|&scheme| {
+            challenges
+                .iter()
+                .find(|challenge| challenge.scheme.eq_ignore_ascii_case(scheme.name()))
+                .map(|challenge| (scheme, challenge))
+        })
+    }
+
+    fn challenges(&self, response: &Response) -> Vec<Challenge> {
+        response
+            .headers()
+            .get_all(self.target.challenge_header())
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(parse_challenges)
+            .collect()
+    }
+
+    fn digest(&self, challenge: &Challenge, request: &Request) -> Result<String> {
+        // Requests to a proxy use the absolute URL as their request target
+        let uri = match self.target {
+            Target::Server => request.url().path(),
+            Target::Proxy => request.url().as_str(),
+        };
+        let mut context = digest_auth::AuthContext::new(self.username, self.password, uri);
+        if let Some(cnonc) = std::env::var_os("XH_TEST_DIGEST_AUTH_CNONCE") {
+            context.set_custom_cnonce(cnonc.to_string_lossy().to_string());
+        }
+        let params: Vec<String> = challenge
+            .params
+            .iter()
+            .map(|(name, value)| {
+                let value = value.replace('\\', "\\\\").replace('"', "\\\"");
+                format!("{name}=\"{value}\"")
+            })
+            .collect();
+        let mut prompt = digest_auth::parse(&format!("Digest {}", params.join(", ")))?;
+        Ok(prompt.respond(&context)?.to_header_string())
+    }
+
+    /// Run the NTLM handshake up to the point where the final request can be
+    /// sent. NTLM authenticates the connection rather than the request, so the
+    /// handshake has to happen on a single kept-alive connection.
+    fn ntlm(
+        &self,
+        ctx: &mut Context,
+        scheme: Scheme,
+        response: &mut Response,
+        request: &mut Request,
+    ) -> Result<Option<String>> {
+        let mut negotiate_request = clone_request(request)?;
+        negotiate_request.headers_mut().insert(
+            self.target.authorization_header(),
+            HeaderValue::from_str(&format!(
+                "{} {}",
+                scheme.name(),
+                STANDARD.encode(ntlm::negotiate_message())
+            ))?,
+        );
+        self.print(ctx, response, &mut negotiate_request)?;
+        discard_body(response);
+        *response = self.next(ctx, negotiate_request)?;
+
+        let token = (response.status() == self.target.status())
+            .then(|| self.challenges(response))
+            .into_iter()
+            .flatten()
+            .find(|challenge| challenge.scheme.eq_ignore_ascii_case(scheme.name()))
+            .and_then(|challenge| challenge.token68);
+        let Some(token) = token else {
+            return Ok(None);
+        };
+        let challenge = STANDARD
+            .decode(token)
+            .context("Invalid NTLM challenge message")
+            .and_then(|message| ntlm::Challenge::parse(&message))?;
+        let message = ntlm::authenticate_message(&challenge, self.username, self.password);
+        Ok(Some(format!(
+            "{} {}",
+            scheme.name(),
+            STANDARD.encode(message)
+        )))
     }
 }
 
-impl Middleware for DigestAuthMiddleware<'_> {
+impl Middleware for ChallengeAuthMiddleware<'_> {
     fn handle(&mut self, mut ctx: Context, mut request: Request) -> Result<Response> {
         let mut response = self.next(&mut ctx, clone_request(&mut request)?)?;
-        match response.headers().get(WWW_AUTHENTICATE) {
-            Some(wwwauth) if response.status() == StatusCode::UNAUTHORIZED => {
-                let mut context = digest_auth::AuthContext::new(
-                    self.username,
-                    self.password,
-                    request.url().path(),
-                );
-                if let Some(cnonc) = std::env::var_os("XH_TEST_DIGEST_AUTH_CNONCE") {
-                    context.set_custom_cnonce(cnonc.to_string_lossy().to_string());
+        if response.status() != self.target.status() {
+            return Ok(response);
+        }
+        let challenges = self.challenges(&response);
+        let Some((scheme, challenge)) = self.select(&challenges) else {
+            return Ok(response);
+        };
+        log::debug!("Answering {} auth challenge", scheme.name());
+
+        let answer = match scheme {
+            Scheme::Basic => format!(
+                "Basic {}",
+                STANDARD.encode(format!("{}:{}", self.username, self.password))
+            ),
+            Scheme::Digest => self.digest(challenge, &request)?,
+            Scheme::Ntlm | Scheme::Negotiate => {
+                match self.ntlm(&mut ctx, scheme, &mut response, &mut request)? {
+                    Some(answer) => answer,
+                    None => return Ok(response),
                 }
-                let mut prompt = digest_auth::parse(wwwauth.to_str()?)?;
-                let answer = prompt.respond(&context)?.to_header_string();
-                request
-                    .headers_mut()
-                    .insert(AUTHORIZATION, HeaderValue::from_str(&answer)?);
-                self.print(&mut ctx, &mut response, &mut request)?;
-                Ok(self.next(&mut ctx, request)?)
             }
-            _ => Ok(response),
-        }
+        };
+        request.headers_mut().insert(
+            self.target.authorization_header(),
+            HeaderValue::from_str(&answer)?,
+        );
+        self.print(&mut ctx, &mut response, &mut request)?;
+        discard_body(&mut response);
+        self.next(&mut ctx, request)
+    }
+}
+
+/// Read the rest of an intermediate response so that its connection can be reused.
+fn discard_body(response: &mut Response) {
+    if let Err(err) = response.copy_to(&mut io::sink()) {
+        log::debug!("Failed to read intermediate response body: {err}");
     }
 }
 
@@ -116,4 +467,73 @@ mod tests {
             assert_eq!(output, (user.as_str(), pass.as_deref()));
         }
     }
+
+    #[test]
+    fn challenges() {
+        let challenges = parse_challenges(
+            r#"Negotiate, NTLM TlRMTVNTUAACAAAA==, Digest realm="a \"b\", c", qop="auth, auth-int",algorithm=MD5, Basic realm=x"#,
+        );
+        let schemes: Vec<_> = challenges.iter().map(|c| c.scheme.as_str()).collect();
+        assert_eq!(schemes, ["negotiate", "ntlm", "digest", "basic"]);
+        assert_eq!(challenges[0].token68, None);
+        assert!(challenges[0].params.is_empty());
+        assert_eq!(challenges[1].token68.as_deref(), Some("TlRMTVNTUAACAAAA=="));
+        assert_eq!(
+            challenges[2].params,
+            [
+                ("realm".into(), r#"a "b", c"#.into()),
+                ("qop".into(), "auth, auth-int".into()),
+                ("algorithm".into(), "MD5".into()),
+            ]
+        );
+        assert_eq!(challenges[3].params, [("realm".into(), "x".into())]);
+
+        assert!(parse_challenges("").is_empty());
+        assert_eq!(parse_challenges("Bearer")[0].scheme, "bearer");
+    }
+
+    #[test]
+    fn challenge_selection() {
+        let challenges = parse_challenges(r#"Basic realm="x", Digest realm="x", nonce="y""#);
+        let auth = Auth::Any("user".into(), "pass".into());
+        let middleware = ChallengeAuthMiddleware::new(&auth).unwrap();
+        assert_eq!(middleware.select(&challenges).unwrap().0, Scheme::Digest);
+
+        let auth = Auth::Ntlm("user".into(), "pass".into());
+        let middleware = ChallengeAuthMiddleware::new(&auth).unwrap();
+        assert!(middleware.select(&challenges).is_none());
+
+        assert!(ChallengeAuthMiddleware::new(&Auth::Bearer("token".into())).is_none());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn credential_command() {
+        assert_eq!(
+            run_credential_command("printf 'secret\\nsecond line\\n'").unwrap(),
+            "secret"
+        );
+        assert!(run_credential_command("true").is_err());
+        assert!(run_credential_command("echo secret; exit 1").is_err());
+
+        assert_eq!(
+            Auth::from_command(Some("user"), "echo pass", AuthType::Basic).unwrap(),
+            Auth::Basic("user".into(), Some("pass".into()))
+        );
+        assert_eq!(
+            Auth::from_command(Some("user:"), "echo pass", AuthType::Digest).unwrap(),
+            Auth::Digest("user".into(), "pass".into())
+        );
+        assert_eq!(
+            Auth::from_command(Some("user"), "echo pass", AuthType::Ntlm).unwrap(),
+            Auth::Ntlm("user".into(), "pass".into())
+        );
+        assert_eq!(
+            Auth::from_command(None, "echo token", AuthType::Bearer).unwrap(),
+            Auth::Bearer("token".into())
+        );
+        assert!(Auth::from_command(None, "echo pass", AuthType::Basic).is_err());
+        assert!(Auth::from_command(Some("user:pass"), "echo pass", AuthType::Basic).is_err());
+        assert!(Auth::from_command(Some("token"), "echo token", AuthType::Bearer).is_err());
+    }
 }
@@ -0,0 +1,225 @@
+//! Downloading every URL listed in an --input-file.
+
+use std::collections::HashSet;
+use std::fs;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::sync::Mutex;
+use std::thread;
+
+use anyhow::{Context, Result, anyhow};
+use indicatif::{HumanBytes, ProgressBar, ProgressStyle};
+use reqwest::blocking::{Request, Response};
+use reqwest::header::{HeaderMap, HeaderValue, RANGE};
+use reqwest::{StatusCode, Url};
+
+use crate::auth::Auth;
+use crate::cli::construct_url;
+use crate::download::{download_file, get_file_name, get_file_size};
+use crate::middleware::ClientWithMiddleware;
+use crate::transfer::TransferLimits;
+use crate::utils::reason_phrase;
+
+const PROGRESS_TEMPLATE: &str = "{spinner:.green} {pos}/{len} files {wide_msg}";
+const UNCOLORED_PROGRESS_TEMPLATE: &str = "{spinner} {pos}/{len} files {wide_msg}";
+
+pub struct BatchOptions<'a> {
+    pub output_dir: &'a Path,
+    pub parallel: usize,
+    pub color: bool,
+    pub quiet: bool,
+    pub limits: &'a TransferLimits,
+}
+
+enum Outcome {
+    Downloaded(PathBuf, u64),
+    Skipped(PathBuf),
+    Failed(Url, anyhow::Error),
+}
+
+/// Read the URLs from `path`, or from stdin if it's "-".
+pub fn read_urls(path: &Path, default_scheme: Option<&str>) -> Result<Vec<Url>> {
+    let text = if path == Path::new("-") {
+        let mut text = String::new();
+        io::stdin().read_to_string(&mut text)?;
+        text
+    } else {
+        fs::read_to_string(path)
+            .with_context(|| format!("couldn't read input file {}", path.display()))?
+    };
+    text.lines()
+        .enumerate()
+        .map(|(index, line)| (index, line.trim()))
+        .filter(|(_, line)| !line.is_empty() && !line.starts_with('#'))
+        .map(|(index, line)| {
+            construct_url(line, default_scheme)
+                .with_context(|| format!("{}:{}: invalid URL {line:?}", path.display(), index + 1))
+        })
+        .collect()
+}
+
+/// Send each request along with its auth, using up to `options.parallel`
+/// threads and a client from `new_client` for every request.
+pub fn download_all<'a, F, T>(
+    requests: Vec<(Request, Option<&'a Auth>)>,
+    new_client: F,
+    options: &BatchOptions,
+) -> Result<ExitCode>
+where
+    F: Fn(Option<&'a Auth>) -> ClientWithMiddleware<'a, T> + Sync,
+    T: FnMut(&mut Response, &mut Request) -> Result<()> + 'a,
+{
+    fs::create_dir_all(options.output_dir)
+        .with_context(|| format!("couldn't create {}", options.output_dir.display()))?;
+    // Files written during the batch aren't ours to resume
+    let resumable = fs::read_dir(options.output_dir)
+        .and_then(|entries| entries.map(|entry| Ok(entry?.path())).collect())
+        .with_context(|| format!("couldn't read {}", options.output_dir.display()))?;
+    let resumable = Mutex::new(resumable);
+
+    let outcomes: Vec<Mutex<Option<Outcome>>> = requests.iter().map(|_| Mutex::new(None)).collect();
+    let queue = Mutex::new(requests.into_iter().enumerate());
+
+    let pb = if options.quiet {
+        ProgressBar::hidden()
+    } else {
+        let style = ProgressStyle::default_bar().template(if options.color {
+            PROGRESS_TEMPLATE
+        } else {
+            UNCOLORED_PROGRESS_TEMPLATE
+        })?;
+        ProgressBar::new(outcomes.len() as u64).with_style(style)
+    };
+
+    thread::scope(|scope| {
+        for _ in 0..options.parallel.min(outcomes.len()) {
+            scope.spawn(|| {
+                loop {
+                    let Some((index, (request, auth))) = queue.lock().unwrap().next() else {
+                        break;
+                    };
+                    let url = request.url().clone();
+                    pb.set_message(url.to_string());
+                    let mut client = new_client(auth);
+                    let outcome = download_one(&mut client, request, &resumable, options)
+                        .unwrap_or_else(|err| Outcome::Failed(url, err));
+                    *outcomes[index].lock().unwrap() = Some(outcome);
+                    pb.inc(1);
+                }
+            });
+        }
+    });
+    pb.finish_and_clear();
+
+    let outcomes: Vec<Outcome> = outcomes
+        .into_iter()
+        .map(|outcome| outcome.into_inner().unwrap().unwrap())
+        .collect();
+    if !options.quiet {
+        print_summary(&outcomes);
+    }
+    if outcomes
+        .iter()
+        .any(|outcome| matches!(outcome, Outcome::Failed(..)))
+    {
+        Ok(ExitCode::FAILURE)
+    } else {
+        Ok(ExitCode::SUCCESS)
+    }
+}
+
+fn download_one<'a, T>(
+    client: &mut ClientWithMiddleware<'a, T>,
+    mut request: Request,
+    resumable: &Mutex<HashSet<PathBuf>>,
+    options: &BatchOptions,
+) -> Result<Outcome>
+where
+    T: FnMut(&mut Response, &mut Request) -> Result<()> + 'a,
+{
+    let url = request.url().clone();
+    // The response decides the final name, but resuming has to rely on the URL.
+    // Claiming the file first keeps two URLs with the same name from both
+    // resuming it.
+    let guessed_path = options
+        .output_dir
+        .join(get_file_name(&HeaderMap::new(), &url));
+    let claimed = resumable.lock().unwrap().remove(&guessed_path);
+    let resume = if claimed {
+        get_file_size(Some(&guessed_path)).filter(|&size| size > 0)
+    } else {
+        None
+    };
+    if let Some(size) = resume {
+        request
+            .headers_mut()
+            .insert(RANGE, HeaderValue::from_str(&format!("bytes={size}-"))?);
+    }
+
+    let response = client.execute(request)?;
+    let status = response.status();
+    if status == StatusCode::RANGE_NOT_SATISFIABLE && resume.is_some() {
+        return Ok(Outcome::Skipped(guessed_path));
+    }
+    if status.is_client_error() || status.is_server_error() {
+        return Err(anyhow!(
+            "HTTP {} {}",
+            status.as_u16(),
+            reason_phrase(&response)
+        ));
+    }
+
+    // Unless the file was claimed, a new one is created next to any existing
+    // files instead of overwriting them
+    let path = download_file(
+        response,
+        claimed.then_some(guessed_path),
+        Some(options.output_dir),
+        &url,
+        resume,
+        options.color,
+        true,
+        None,
+        None,
+        None,
+        options.limits,
+    )?;
+    let size = get_file_size(Some(&path)).unwrap_or_default();
+    Ok(Outcome::Downloaded(path, size))
+}
+
+fn print_summary(outcomes: &[Outcome]) {
+    let rows: Vec<(&str, String, String)> = outcomes
+        .iter()
+        .map(|outcome| match outcome {
+            Outcome::Downloaded(path, size) => (
+                "Downloaded",
+                path.display().to_string(),
+                HumanBytes(*size).to_string(),
+            ),
+            Outcome::Skipped(path) => (
+                "Skipped",
+                path.display().to_string(),
+                "already complete".to_string(),
+            ),
+            Outcome::Failed(url, err) => ("Failed", url.to_string(), format!("{err:#}")),
+        })
+        .collect();
+
+    let width = rows
+        .iter()
+        .map(|(_, name, _)| name.len())
+        .max()
+        .unwrap_or(0);
+    for (result, name, details) in &rows {
+        eprintln!("{result:<10}  {name:width$}  {details}");
+    }
+    let count = |wanted: &str| rows.iter().filter(|(result, ..)| *result == wanted).count();
+    eprintln!(
+        "{} downloaded, {} skipped, {} failed",
+        count("Downloaded"),
+        count("Skipped"),
+        count("Failed")
+    );
+}
@@ -11,20 +11,29 @@
 //! when possible, but on Windows we often need a BufferedStandardStream
 //! instead to use the terminal APIs.
 //!
+//! Output for the terminal can also go through a pager, which still counts
+//! as a terminal so that it gets colors and formatting.
+//!
 //! Most of this code is boilerplate.
 
 use std::{
-    env::var_os,
+    env::{var, var_os},
+    error::Error,
+    fmt,
     io::{self, Write},
+    mem,
     path::Path,
+    process::{Child, Stdio},
 };
 
+use termcolor::{ColorSpec, WriteColor};
+
 use crate::{
     cli::Pretty,
-    utils::{test_default_color, test_pretend_term},
+    utils::{shell_command, test_default_color, test_pretend_term},
 };
 
-pub use imp::Buffer;
+use imp::Output;
 
 #[cfg(not(windows))]
 mod imp {
@@ -32,7 +41,7 @@ mod imp {
 
     use termcolor::{Ansi, WriteColor};
 
-    pub struct Buffer {
+    pub struct Output {
         inner: Ansi<BufWriter<Inner>>,
         terminal: bool,
         redirect: bool,
@@ -42,9 +51,10 @@ mod imp {
         File(std::fs::File),
         Stdout(std::io::Stdout),
         Stderr(std::io::Stderr),
+        Pager(super::Pager),
     }
 
-    impl Buffer {
+    impl Output {
         pub fn stdout() -> Self {
             Self {
                 inner: Ansi::new(BufWriter::new(Inner::Stdout(std::io::stdout()))),
@@ -77,6 +87,14 @@ mod imp {
             }
         }
 
+        pub fn pager(pager: super::Pager) -> Self {
+            Self {
+                inner: Ansi::new(BufWriter::new(Inner::Pager(pager))),
+                terminal: true,
+                redirect: false,
+            }
+        }
+
         pub fn is_terminal(&self) -> bool {
             self.terminal
         }
@@ -85,6 +103,13 @@ mod imp {
             self.redirect
         }
 
+        pub fn pager_mut(&mut self) -> Option<&mut super::Pager> {
+            match self.inner.get_mut().get_mut() {
+                Inner::Pager(pager) => Some(pager),
+                _ => None,
+            }
+        }
+
         #[cfg(test)]
         pub fn is_stdout(&self) -> bool {
             matches!(self.inner.get_ref().get_ref(), Inner::Stdout(_))
@@ -107,6 +132,7 @@ mod imp {
                 Inner::File(w) => w.write(buf),
                 Inner::Stdout(w) => w.write(buf),
                 Inner::Stderr(w) => w.write(buf),
+                Inner::Pager(w) => w.write(buf),
             }
         }
 
@@ -115,6 +141,7 @@ mod imp {
                 Inner::File(w) => w.write_all(buf),
                 Inner::Stdout(w) => w.write_all(buf),
                 Inner::Stderr(w) => w.write_all(buf),
+                Inner::Pager(w) => w.write_all(buf),
             }
         }
 
@@ -123,11 +150,12 @@ mod imp {
                 Inner::File(w) => w.flush(),
                 Inner::Stdout(w) => w.flush(),
                 Inner::Stderr(w) => w.flush(),
+                Inner::Pager(w) => w.flush(),
             }
         }
     }
 
-    impl Write for Buffer {
+    impl Write for Output {
         fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
             self.inner.write(buf)
         }
@@ -143,7 +171,7 @@ mod imp {
         }
     }
 
-    impl WriteColor for Buffer {
+    impl WriteColor for Output {
         fn supports_color(&self) -> bool {
             true
         }
@@ -166,18 +194,19 @@ mod imp {
 
     use crate::utils::test_default_color;
 
-    pub enum Buffer {
+    pub enum Output {
         // Only escape codes make sense when the output isn't going directly
         // to a terminal, so we use Ansi for some cases.
         File(Ansi<BufWriter<std::fs::File>>),
         Redirect(Ansi<BufWriter<std::io::Stdout>>),
         Stdout(BufferedStandardStream),
         Stderr(BufferedStandardStream),
+        Pager(Ansi<BufWriter<super::Pager>>),
     }
 
-    impl Buffer {
+    impl Output {
         pub fn stdout() -> Self {
-            Buffer::Stdout(BufferedStandardStream::stdout(if test_default_color() {
+            Output::Stdout(BufferedStandardStream::stdout(if test_default_color() {
                 ColorChoice::AlwaysAnsi
             } else {
                 ColorChoice::Always
@@ -185,7 +214,7 @@ mod imp {
         }
 
         pub fn stderr() -> Self {
-            Buffer::Stderr(BufferedStandardStream::stderr(if test_default_color() {
+            Output::Stderr(BufferedStandardStream::stderr(if test_default_color() {
                 ColorChoice::AlwaysAnsi
             } else {
                 ColorChoice::Always
@@ -193,114 +222,215 @@ mod imp {
         }
 
         pub fn redirect() -> Self {
-            Buffer::Redirect(Ansi::new(BufWriter::new(std::io::stdout())))
+            Output::Redirect(Ansi::new(BufWriter::new(std::io::stdout())))
         }
 
         pub fn file(file: std::fs::File) -> Self {
-            Buffer::File(Ansi::new(BufWriter::new(file)))
+            Output::File(Ansi::new(BufWriter::new(file)))
+        }
+
+        pub fn pager(pager: super::Pager) -> Self {
+            Output::Pager(Ansi::new(BufWriter::new(pager)))
         }
 
         pub fn is_terminal(&self) -> bool {
-            matches!(self, Buffer::Stdout(_) | Buffer::Stderr(_))
+            matches!(
+                self,
+                Output::Stdout(_) | Output::Stderr(_) | Output::Pager(_)
+            )
         }
 
         pub fn is_redirect(&self) -> bool {
-            matches!(self, Buffer::Redirect(_))
+            matches!(self, Output::Redirect(_))
+        }
+
+        pub fn pager_mut(&mut self) -> Option<&mut super::Pager> {
+            match self {
+                Output::Pager(w) => Some(w.get_mut().get_mut()),
+                _ => None,
+            }
         }
 
         #[cfg(test)]
         pub fn is_stdout(&self) -> bool {
-            matches!(self, Buffer::Stdout(_))
+            matches!(self, Output::Stdout(_))
         }
 
         #[cfg(test)]
         pub fn is_stderr(&self) -> bool {
-            matches!(self, Buffer::Stderr(_))
+            matches!(self, Output::Stderr(_))
         }
 
         #[cfg(test)]
         pub fn is_file(&self) -> bool {
-            matches!(self, Buffer::File(_))
+            matches!(self, Output::File(_))
         }
     }
 
-    impl Write for Buffer {
+    impl Write for Output {
         fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
             match self {
-                Buffer::File(w) => w.write(buf),
-                Buffer::Redirect(w) => w.write(buf),
-                Buffer::Stdout(w) | Buffer::Stderr(w) => w.write(buf),
+                Output::File(w) => w.write(buf),
+                Output::Redirect(w) => w.write(buf),
+                Output::Pager(w) => w.write(buf),
+                Output::Stdout(w) | Output::Stderr(w) => w.write(buf),
             }
         }
 
         fn write_all(&mut self, buf: &[u8]) -> std::io::Result<()> {
             match self {
-                Buffer::File(w) => w.get_mut().write_all(buf),
-                Buffer::Redirect(w) => w.get_mut().write_all(buf),
-                Buffer::Stdout(w) | Buffer::Stderr(w) => w.write_all(buf),
+                Output::File(w) => w.get_mut().write_all(buf),
+                Output::Redirect(w) => w.get_mut().write_all(buf),
+                Output::Pager(w) => w.get_mut().write_all(buf),
+                Output::Stdout(w) | Output::Stderr(w) => w.write_all(buf),
             }
         }
 
         fn flush(&mut self) -> std::io::Result<()> {
             match self {
-                Buffer::File(w) => w.flush(),
-                Buffer::Redirect(w) => w.flush(),
-                Buffer::Stdout(w) | Buffer::Stderr(w) => w.flush(),
+                Output::File(w) => w.flush(),
+                Output::Redirect(w) => w.flush(),
+                Output::Pager(w) => w.flush(),
+                Output::Stdout(w) | Output::Stderr(w) => w.flush(),
             }
         }
     }
 
-    impl WriteColor for Buffer {
+    impl WriteColor for Output {
         fn supports_color(&self) -> bool {
             match self {
-                Buffer::File(w) => w.supports_color(),
-                Buffer::Redirect(w) => w.supports_color(),
-                Buffer::Stdout(w) | Buffer::Stderr(w) => w.supports_color(),
+                Output::File(w) => w.supports_color(),
+                Output::Redirect(w) => w.supports_color(),
+                Output::Pager(w) => w.supports_color(),
+                Output::Stdout(w) | Output::Stderr(w) => w.supports_color(),
             }
         }
 
         fn set_color(&mut self, spec: &termcolor::ColorSpec) -> std::io::Result<()> {
             match self {
-                Buffer::File(w) => w.set_color(spec),
-                Buffer::Redirect(w) => w.set_color(spec),
-                Buffer::Stdout(w) | Buffer::Stderr(w) => w.set_color(spec),
+                Output::File(w) => w.set_color(spec),
+                Output::Redirect(w) => w.set_color(spec),
+                Output::Pager(w) => w.set_color(spec),
+                Output::Stdout(w) | Output::Stderr(w) => w.set_color(spec),
             }
         }
 
         fn reset(&mut self) -> std::io::Result<()> {
             match self {
-                Buffer::File(w) => w.reset(),
-                Buffer::Redirect(w) => w.reset(),
-                Buffer::Stdout(w) | Buffer::Stderr(w) => w.reset(),
+                Output::File(w) => w.reset(),
+                Output::Redirect(w) => w.reset(),
+                Output::Pager(w) => w.reset(),
+                Output::Stdout(w) | Output::Stderr(w) => w.reset(),
             }
         }
 
         fn is_synchronous(&self) -> bool {
             match self {
-                Buffer::File(w) => w.is_synchronous(),
-                Buffer::Redirect(w) => w.is_synchronous(),
-                Buffer::Stdout(w) | Buffer::Stderr(w) => w.is_synchronous(),
+                Output::File(w) => w.is_synchronous(),
+                Output::Redirect(w) => w.is_synchronous(),
+                Output::Pager(w) => w.is_synchronous(),
+                Output::Stdout(w) | Output::Stderr(w) => w.is_synchronous(),
             }
         }
     }
 }
 
+pub struct Buffer {
+    output: Output,
+    /// How many more lines may be written, for --head-lines.
+    lines_left: Option<usize>,
+}
+
 impl Buffer {
-    pub fn new(download: bool, output: Option<&Path>, is_stdout_tty: bool) -> io::Result<Self> {
+    pub fn new(
+        download: bool,
+        output: Option<&Path>,
+        is_stdout_tty: bool,
+        pager: bool,
+    ) -> io::Result<Self> {
         log::trace!("is_stdout_tty: {is_stdout_tty}");
-        Ok(if download {
-            Buffer::stderr()
+        let output = if download {
+            Output::stderr()
         } else if let Some(output) = output {
             log::trace!("creating file {output:?}");
             let file = std::fs::File::create(output)?;
-            Buffer::file(file)
+            Output::file(file)
         } else if is_stdout_tty {
-            Buffer::stdout()
+            match screen_size() {
+                Some(screen_size) if pager => Output::pager(Pager::new(screen_size)),
+                _ => Output::stdout(),
+            }
         } else {
-            Buffer::redirect()
+            Output::redirect()
+        };
+        Ok(Buffer {
+            output,
+            lines_left: None,
         })
     }
 
+    pub fn is_terminal(&self) -> bool {
+        self.output.is_terminal()
+    }
+
+    /// Show what's written right away instead of holding it back for the
+    /// pager, for output that may take a long time to fill the screen.
+    /// Nothing changes if the pager is already running, in which case this
+    /// returns false.
+    pub fn bypass_pager(&mut self) -> io::Result<bool> {
+        self.output.flush()?;
+        match self.output.pager_mut() {
+            Some(pager) => pager.stop_waiting(),
+            None => Ok(true),
+        }
+    }
+
+    pub fn is_redirect(&self) -> bool {
+        self.output.is_redirect()
+    }
+
+    #[cfg(test)]
+    pub fn is_stdout(&self) -> bool {
+        self.output.is_stdout()
+    }
+
+    #[cfg(test)]
+    pub fn is_stderr(&self) -> bool {
+        self.output.is_stderr()
+    }
+
+    #[cfg(test)]
+    pub fn is_file(&self) -> bool {
+        self.output.is_file()
+    }
+
+    /// Stop writing after this many more lines. Writing anything but empty
+    /// lines after that fails with [`LineLimitReached`].
+    pub fn set_line_limit(&mut self, lines: Option<usize>) {
+        self.lines_left = lines;
+    }
+
+    /// How much of `buf` fits within the line limit.
+    fn allowed_len(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let Some(lines_left) = &mut self.lines_left else {
+            return Ok(buf.len());
+        };
+        if *lines_left == 0 {
+            // Trailing newlines don't count as more output
+            if buf.iter().all(|&byte| byte == b'\n') {
+                return Ok(buf.len());
+            }
+            return Err(io::Error::other(LineLimitReached));
+        }
+        for index in memchr::memchr_iter(b'\n', buf) {
+            *lines_left -= 1;
+            if *lines_left == 0 {
+                return Ok(index + 1);
+            }
+        }
+        Ok(buf.len())
+    }
+
     pub fn print(&mut self, s: &str) -> io::Result<()> {
         self.write_all(s.as_bytes())
     }
@@ -329,3 +459,243 @@ impl Buffer {
         }
     }
 }
+
+impl Write for Buffer {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let len = self.allowed_len(buf)?;
+        self.output.write(&buf[..len])
+    }
+
+    fn write_all(&mut self, buf: &[u8]) -> io::Result<()> {
+        let len = self.allowed_len(buf)?;
+        self.output.write_all(&buf[..len])?;
+        if len < buf.len() {
+            // Fails unless only newlines are left
+            self.allowed_len(&buf[len..])?;
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.output.flush()
+    }
+}
+
+impl WriteColor for Buffer {
+    fn supports_color(&self) -> bool {
+        self.output.supports_color()
+    }
+
+    fn set_color(&mut self, spec: &ColorSpec) -> io::Result<()> {
+        self.output.set_color(spec)
+    }
+
+    fn reset(&mut self) -> io::Result<()> {
+        self.output.reset()
+    }
+
+    fn is_synchronous(&self) -> bool {
+        self.output.is_synchronous()
+    }
+}
+
+/// The error for when --head-lines cuts off the output.
+#[derive(Debug)]
+pub struct LineLimitReached;
+
+impl fmt::Display for LineLimitReached {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("line limit reached")
+    }
+}
+
+impl Error for LineLimitReached {}
+
+/// Output that's held back until it doesn't fit on the screen anymore, and
+/// is then sent through a pager. Short output is written to stdout as usual.
+pub struct Pager {
+    rows: usize,
+    columns: usize,
+    state: PagerState,
+}
+
+enum PagerState {
+    Waiting { held: Vec<u8>, lines: usize },
+    Paging(Child),
+    Direct(io::Stdout),
+}
+
+impl Pager {
+    fn new((rows, columns): (usize, usize)) -> Self {
+        Pager {
+            rows,
+            columns,
+            state: PagerState::Waiting {
+                held: Vec::new(),
+                lines: 0,
+            },
+        }
+    }
+
+    /// Whether the held output fills the screen. Escape codes and wide
+    /// characters make this a rough guess for long lines.
+    fn screen_full(&self) -> bool {
+        match &self.state {
+            PagerState::Waiting { held, lines } => {
+                *lines >= self.rows || held.len() >= self.rows * self.columns
+            }
+            _ => false,
+        }
+    }
+
+    fn start_paging(&mut self) -> io::Result<()> {
+        let PagerState::Waiting { held, .. } =
+            mem::replace(&mut self.state, PagerState::Direct(io::stdout()))
+        else {
+            return Ok(());
+        };
+        let command = pager_command();
+        match spawn_pager(&command) {
+            Ok(child) => self.state = PagerState::Paging(child),
+            Err(err) => log::warn!("Could not run pager {command:?}: {err}"),
+        }
+        self.write_all(&held)
+    }
+
+    fn stop_waiting(&mut self) -> io::Result<bool> {
+        match self.state {
+            PagerState::Waiting { .. } => self.finish().map(|()| true),
+            PagerState::Paging(_) => Ok(false),
+            PagerState::Direct(_) => Ok(true),
+        }
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        match mem::replace(&mut self.state, PagerState::Direct(io::stdout())) {
+            PagerState::Waiting { held, .. } => {
+                let mut stdout = io::stdout();
+                stdout.write_all(&held)?;
+                stdout.flush()
+            }
+            PagerState::Paging(mut child) => {
+                // Closing stdin tells the pager that there's no more output
+                drop(child.stdin.take());
+                child.wait()?;
+                Ok(())
+            }
+            PagerState::Direct(mut stdout) => stdout.flush(),
+        }
+    }
+}
+
+impl Write for Pager {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match &mut self.state {
+            PagerState::Waiting { held, lines } => {
+                held.extend_from_slice(buf);
+                *lines += memchr::memchr_iter(b'\n', buf).count();
+                if self.screen_full() {
+                    self.start_paging()?;
+                }
+                Ok(buf.len())
+            }
+            PagerState::Paging(child) => child
+                .stdin
+                .as_mut()
+                .expect("pager has stdin")
+                .write(buf)
+                .map_err(pager_closed),
+            PagerState::Direct(stdout) => stdout.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match &mut self.state {
+            // Keep holding on to it, it may not fit on the screen yet
+            PagerState::Waiting { .. } => Ok(()),
+            PagerState::Paging(child) => child
+                .stdin
+                .as_mut()
+                .expect("pager has stdin")
+                .flush()
+                .map_err(pager_closed),
+            PagerState::Direct(stdout) => stdout.flush(),
+        }
+    }
+}
+
+impl Drop for Pager {
+    fn drop(&mut self) {
+        if let Err(err) = self.finish() {
+            log::debug!("Failed to finish paging: {err}");
+        }
+    }
+}
+
+/// The error for when the user quits the pager before all the output is in.
+#[derive(Debug)]
+pub struct PagerClosed;
+
+impl fmt::Display for PagerClosed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("the pager was closed")
+    }
+}
+
+impl Error for PagerClosed {}
+
+fn pager_closed(err: io::Error) -> io::Error {
+    if err.kind() == io::ErrorKind::BrokenPipe {
+        io::Error::new(io::ErrorKind::BrokenPipe, PagerClosed)
+    } else {
+        err
+    }
+}
+
+/// Whether the error came from the user quitting the pager, which isn't
+/// worth reporting.
+pub fn is_pager_closed(err: &anyhow::Error) -> bool {
+    has_io_error::<PagerClosed>(err)
+}
+
+pub fn is_line_limit_reached(err: &anyhow::Error) -> bool {
+    has_io_error::<LineLimitReached>(err)
+}
+
+fn has_io_error<E: Error + 'static>(err: &anyhow::Error) -> bool {
+    err.chain().any(|err| {
+        err.downcast_ref::<io::Error>()
+            .and_then(io::Error::get_ref)
+            .is_some_and(|err| err.is::<E>())
+    })
+}
+
+/// The pager from $XH_PAGER or $PAGER, like git and man do.
+fn pager_command() -> String {
+    ["XH_PAGER", "PAGER"]
+        .into_iter()
+        .filter_map(|name| var(name).ok())
+        .find(|command| !command.trim().is_empty())
+        .unwrap_or_else(|| "less".to_owned())
+}
+
+fn spawn_pager(command: &str) -> io::Result<Child> {
+    // The command may have arguments and quoting, so leave it to the shell
+    let mut pager = shell_command(command);
+    if var_os("LESS").is_none() {
+        // Show colors instead of escape codes
+        pager.env("LESS", "R");
+    }
+    pager.stdin(Stdio::piped()).spawn()
+}
+
+/// The height and width of the terminal, which $LINES and $COLUMNS override.
+fn screen_size() -> Option<(usize, usize)> {
+    let from_env = |name| var(name).ok()?.parse().ok().filter(|&n: &usize| n > 0);
+    let size = terminal_size::terminal_size();
+    let rows = from_env("LINES").or(size.map(|(_, height)| usize::from(height.0)))?;
+    let columns = from_env("COLUMNS")
+        .or(size.map(|(width, _)| usize::from(width.0)))
+        .unwrap_or(80);
+    Some((rows, columns))
+}
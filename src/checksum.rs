@@ -0,0 +1,350 @@
+//! Verifying downloads against a checksum from --checksum or from the
+//! Repr-Digest, Digest and Content-MD5 response headers.
+
+use std::fmt;
+use std::io::{self, Write};
+use std::str::FromStr;
+
+use anyhow::{Result, anyhow};
+use base64::{Engine as _, engine::general_purpose::STANDARD};
+use md5::Md5;
+use reqwest::header::{CONTENT_ENCODING, HeaderMap};
+use sha2::{Digest, Sha256, Sha512};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Algorithm {
+    Md5,
+    Sha256,
+    Sha512,
+}
+
+impl Algorithm {
+    fn from_name(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().replace('-', "").as_str() {
+            "md5" => Some(Algorithm::Md5),
+            "sha256" => Some(Algorithm::Sha256),
+            "sha512" => Some(Algorithm::Sha512),
+            _ => None,
+        }
+    }
+
+    fn output_len(self) -> usize {
+        match self {
+            Algorithm::Md5 => 16,
+            Algorithm::Sha256 => 32,
+            Algorithm::Sha512 => 64,
+        }
+    }
+}
+
+impl fmt::Display for Algorithm {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Algorithm::Md5 => "md5",
+            Algorithm::Sha256 => "sha256",
+            Algorithm::Sha512 => "sha512",
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Checksum {
+    pub algorithm: Algorithm,
+    pub expected: Vec<u8>,
+    /// Where the checksum came from, for error messages.
+    pub source: &'static str,
+}
+
+impl FromStr for Checksum {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Checksum> {
+        let (name, hex) = s
+            .split_once(':')
+            .ok_or_else(|| anyhow!("Expected ALGORITHM:HEX, e.g. sha256:3a7bd3e2..."))?;
+        let algorithm = Algorithm::from_name(name).ok_or_else(|| {
+            anyhow!("Unknown checksum algorithm {name:?}, expected sha256, sha512 or md5")
+        })?;
+        let expected = decode_hex(hex.trim())
+            .filter(|expected| expected.len() == algorithm.output_len())
+            .ok_or_else(|| {
+                anyhow!(
+                    "Invalid {algorithm} checksum, expected {} hexadecimal digits",
+                    algorithm.output_len() * 2
+                )
+            })?;
+        Ok(Checksum {
+            algorithm,
+            expected,
+            source: "--checksum",
+        })
+    }
+}
+
+fn decode_hex(hex: &str) -> Option<Vec<u8>> {
+    if hex.len() % 2 != 0 || !hex.is_ascii() {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).ok())
+        .collect()
+}
+
+fn encode_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+impl Checksum {
+    /// Checksums of the complete file announced by the server.
+    ///
+    /// Content-MD5 only covers the body of this response, so it's ignored for
+    /// partial responses. If the body is compressed the checksums describe the
+    /// compressed data rather than the file, so they're all ignored.
+    pub fn from_headers(headers: &HeaderMap, partial: bool) -> Vec<Checksum> {
+        let mut checksums = Vec::new();
+        if headers.contains_key(CONTENT_ENCODING) {
+            return checksums;
+        }
+
+        // RFC 9530: sha-256=:base64:, sha-512=:base64:
+        for value in header_values(headers, "repr-digest") {
+            for (name, value) in dictionary_items(value) {
+                let value = value.strip_prefix(':').and_then(|v| v.strip_suffix(':'));
+                checksums.extend(Self::from_base64(name, value, "Repr-Digest"));
+            }
+        }
+        // RFC 3230: SHA-256=base64, MD5=base64
+        for value in header_values(headers, "digest") {
+            for (name, value) in dictionary_items(value) {
+                checksums.extend(Self::from_base64(name, Some(value), "Digest"));
+            }
+        }
+        if !partial {
+            for value in header_values(headers, "content-md5") {
+                checksums.extend(Self::from_base64("md5", Some(value), "Content-MD5"));
+            }
+        }
+        checksums
+    }
+
+    fn from_base64(name: &str, value: Option<&str>, source: &'static str) -> Option<Checksum> {
+        let algorithm = Algorithm::from_name(name)?;
+        let expected = STANDARD.decode(value?.trim()).ok()?;
+        if expected.len() != algorithm.output_len() {
+            log::warn!("Ignoring malformed {source} header");
+            return None;
+        }
+        Some(Checksum {
+            algorithm,
+            expected,
+            source,
+        })
+    }
+}
+
+fn header_values<'a>(headers: &'a HeaderMap, name: &str) -> impl Iterator<Item = &'a str> {
+    headers
+        .get_all(name)
+        .into_iter()
+        .filter_map(|value| value.to_str().ok())
+}
+
+fn dictionary_items(value: &str) -> impl Iterator<Item = (&str, &str)> {
+    value
+        .split(',')
+        .filter_map(|item| item.trim().split_once('='))
+        .map(|(name, value)| (name.trim(), value.trim()))
+}
+
+enum Hasher {
+    Md5(Md5),
+    Sha256(Sha256),
+    Sha512(Sha512),
+}
+
+impl Hasher {
+    fn new(algorithm: Algorithm) -> Self {
+        match algorithm {
+            Algorithm::Md5 => Hasher::Md5(Md5::new()),
+            Algorithm::Sha256 => Hasher::Sha256(Sha256::new()),
+            Algorithm::Sha512 => Hasher::Sha512(Sha512::new()),
+        }
+    }
+
+    fn update(&mut self, data: &[u8]) {
+        match self {
+            Hasher::Md5(hasher) => hasher.update(data),
+            Hasher::Sha256(hasher) => hasher.update(data),
+            Hasher::Sha512(hasher) => hasher.update(data),
+        }
+    }
+
+    fn finalize(self) -> Vec<u8> {
+        match self {
+            Hasher::Md5(hasher) => hasher.finalize().to_vec(),
+            Hasher::Sha256(hasher) => hasher.finalize().to_vec(),
+            Hasher::Sha512(hasher) => hasher.finalize().to_vec(),
+        }
+    }
+}
+
+/// Hashes everything written to it and compares the result against the
+/// expected checksums.
+pub struct Verifier {
+    checks: Vec<(Checksum, Hasher)>,
+}
+
+impl Verifier {
+    pub fn new(checksums: Vec<Checksum>) -> Self {
+        let checks = checksums
+            .into_iter()
+            .map(|checksum| {
+                let hasher = Hasher::new(checksum.algorithm);
+                (checksum, hasher)
+            })
+            .collect();
+        Verifier { checks }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.checks.is_empty()
+    }
+
+    pub fn verify(self) -> Result<()> {
+        for (checksum, hasher) in self.checks {
+            let actual = hasher.finalize();
+            if actual != checksum.expected {
+                return Err(anyhow!(
+                    "{} checksum mismatch (from {}): expected {}, got {}",
+                    checksum.algorithm,
+                    checksum.source,
+                    encode_hex(&checksum.expected),
+                    encode_hex(&actual)
+                ));
+            }
+            log::info!(
+                "{} checksum from {} matches",
+                checksum.algorithm,
+                checksum.source
+            );
+        }
+        Ok(())
+    }
+}
+
+impl Write for Verifier {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        for (_, hasher) in &mut self.checks {
+            hasher.update(buf);
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// A writer that feeds a [`Verifier`] with the data it passes through.
+pub struct HashingWriter<W> {
+    inner: W,
+    verifier: Verifier,
+}
+
+impl<W: Write> HashingWriter<W> {
+    pub fn new(inner: W, verifier: Verifier) -> Self {
+        HashingWriter { inner, verifier }
+    }
+
+    pub fn into_verifier(self) -> Verifier {
+        self.verifier
+    }
+}
+
+impl<W: Write> Write for HashingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.verifier.write_all(&buf[..written])?;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use reqwest::header::HeaderValue;
+
+    const HELLO_SHA256: &str = "2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824";
+
+    #[test]
+    fn parse_checksum() {
+        let checksum: Checksum = format!("SHA-256:{HELLO_SHA256}").parse().unwrap();
+        assert_eq!(checksum.algorithm, Algorithm::Sha256);
+        assert_eq!(encode_hex(&checksum.expected), HELLO_SHA256);
+
+        for (input, error) in [
+            ("2cf24dba", "Expected ALGORITHM:HEX"),
+            ("sha1:2cf24dba", "Unknown checksum algorithm \"sha1\""),
+            (
+                "md5:2cf24dba",
+                "Invalid md5 checksum, expected 32 hexadecimal digits",
+            ),
+        ] {
+            let err = input.parse::<Checksum>().unwrap_err().to_string();
+            assert!(err.starts_with(error), "{err}");
+        }
+    }
+
+    #[test]
+    fn checksums_from_headers() {
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            "repr-digest",
+            HeaderValue::from_static(
+                "sha-256=:LPJNul+wow4m6DsqxbninhsWHlwfp0JecwQzYpOLmCQ=:, unknown=:AA==:",
+            ),
+        );
+        headers.insert(
+            "digest",
+            HeaderValue::from_static("MD5=XUFAKrxLKna5cZ2REBfFkg=="),
+        );
+        headers.insert(
+            "content-md5",
+            HeaderValue::from_static("XUFAKrxLKna5cZ2REBfFkg=="),
+        );
+
+        let sources = |partial| -> Vec<_> {
+            Checksum::from_headers(&headers, partial)
+                .iter()
+                .map(|checksum| (checksum.algorithm, checksum.source))
+                .collect()
+        };
+        assert_eq!(
+            sources(false),
+            [
+                (Algorithm::Sha256, "Repr-Digest"),
+                (Algorithm::Md5, "Digest"),
+                (Algorithm::Md5, "Content-MD5")
+            ]
+        );
+        assert_eq!(
+            sources(true),
+            [
+                (Algorithm::Sha256, "Repr-Digest"),
+                (Algorithm::Md5, "Digest")
+            ]
+        );
+
+        let mut verifier = Verifier::new(Checksum::from_headers(&headers, false));
+        verifier.write_all(b"hello").unwrap();
+        verifier.verify().unwrap();
+
+        headers.insert("content-encoding", HeaderValue::from_static("gzip"));
+        assert!(Checksum::from_headers(&headers, false).is_empty());
+    }
+}
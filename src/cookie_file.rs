@@ -0,0 +1,199 @@
+//! The Netscape cookies.txt format, as used by curl, wget and browser extensions.
+//!
+//! Each line holds one cookie as seven tab-separated fields:
+//! domain, include subdomains, path, secure, expiry, name and value.
+//! A domain prefixed with `#HttpOnly_` marks an HttpOnly cookie, other lines
+//! starting with `#` are comments.
+//!
+//! See https://curl.se/docs/http-cookies.html
+
+use std::fs;
+use std::io::{self, Write};
+use std::path::Path;
+
+use anyhow::{Context, Result, anyhow};
+use cookie_store::{Cookie, CookieDomain, CookieExpiration, CookieStore, RawCookie};
+use time::OffsetDateTime;
+use url::Url;
+
+const HTTP_ONLY_PREFIX: &str = "#HttpOnly_";
+
+/// Add the cookies from the file at `path` to `store`. Expired cookies are skipped.
+pub fn load(path: &Path, store: &mut CookieStore) -> Result<()> {
+    let text = fs::read_to_string(path)
+        .with_context(|| format!("couldn't read cookie file {}", path.display()))?;
+    parse(&text, store).with_context(|| format!("invalid cookie file {}", path.display()))
+}
+
+fn parse(text: &str, store: &mut CookieStore) -> Result<()> {
+    let now = OffsetDateTime::now_utc().unix_timestamp();
+    for (index, line) in text.lines().enumerate() {
+        let line = line.trim_end_matches('\r');
+        let (line, http_only) = match line.strip_prefix(HTTP_ONLY_PREFIX) {
+            Some(line) => (line, true),
+            None if line.trim().is_empty() || line.starts_with('#') => continue,
+            None => (line, false),
+        };
+
+        let mut fields: Vec<&str> = line.splitn(7, '\t').collect();
+        // Some tools leave out the tab before an empty value
+        if fields.len() == 6 {
+            fields.push("");
+        }
+        let [
+            domain,
+            include_subdomains,
+            path,
+            secure,
+            expires,
+            name,
+            value,
+        ] = fields[..]
+        else {
+            return Err(anyhow!("line {}: expected 7 fields", index + 1));
+        };
+        let expires: i64 = expires
+            .parse()
+            .with_context(|| format!("line {}: invalid expiry {expires:?}", index + 1))?;
+        if expires != 0 && expires <= now {
+            continue;
+        }
+
+        let host = domain.trim_start_matches('.');
+        let secure = secure == "TRUE";
+        let scheme = if secure { "https" } else { "http" };
+        let url = Url::parse(&format!("{scheme}://{host}{path}"))
+            .with_context(|| format!("line {}: invalid domain {domain:?}", index + 1))?;
+
+        let mut cookie = RawCookie::build((name.to_string(), value.to_string()))
+            .path(path.to_string())
+            .secure(secure)
+            .http_only(http_only);
+        if include_subdomains == "TRUE" {
+            cookie = cookie.domain(host.to_string());
+        }
+        if expires != 0 {
+            cookie = cookie.expires(OffsetDateTime::from_unix_timestamp(expires)?);
+        }
+        store
+            .insert_raw(&cookie.build(), &url)
+            .with_context(|| format!("line {}: invalid cookie", index + 1))?;
+    }
+    Ok(())
+}
+
+/// Write `cookies` to the file at `path`, replacing its contents.
+///
+/// The cookies are written to a temporary file first so that the file is never
+/// left half-written.
+pub fn save<'a>(path: &Path, cookies: impl Iterator<Item = &'a Cookie<'static>>) -> Result<()> {
+    let dir = match path.parent() {
+        Some(parent_path) if !parent_path.as_os_str().is_empty() => parent_path,
+        _ => Path::new("."),
+    };
+    let mut file = io::BufWriter::new(
+        tempfile::NamedTempFile::new_in(dir)
+            .with_context(|| format!("couldn't create cookie file {}", path.display()))?,
+    );
+    write(&mut file, cookies)?;
+    file.into_inner()
+        .map_err(io::IntoInnerError::into_error)?
+        .persist(path)
+        .with_context(|| format!("couldn't create cookie file {}", path.display()))?;
+    Ok(())
+}
+
+pub fn write<'a>(
+    out: &mut impl Write,
+    cookies: impl Iterator<Item = &'a Cookie<'static>>,
+) -> io::Result<()> {
+    writeln!(out, "# Netscape HTTP Cookie File")?;
+    writeln!(
+        out,
+        "# This file was generated by xh. Edit at your own risk."
+    )?;
+    writeln!(out)?;
+    for cookie in cookies {
+        let (domain, include_subdomains) = match &cookie.domain {
+            CookieDomain::HostOnly(host) => (host.clone(), "FALSE"),
+            CookieDomain::Suffix(suffix) => (format!(".{suffix}"), "TRUE"),
+            CookieDomain::NotPresent | CookieDomain::Empty => continue,
+        };
+        let expires = match cookie.expires {
+            CookieExpiration::AtUtc(expires) => expires.unix_timestamp(),
+            CookieExpiration::SessionEnd => 0,
+        };
+        writeln!(
+            out,
+            "{}{}\t{}\t{}\t{}\t{}\t{}\t{}",
+            if cookie.http_only() == Some(true) {
+                HTTP_ONLY_PREFIX
+            } else {
+                ""
+            },
+            domain,
+            include_subdomains,
+            &*cookie.path,
+            if cookie.secure() == Some(true) {
+                "TRUE"
+            } else {
+                "FALSE"
+            },
+            expires,
+            cookie.name(),
+            cookie.value(),
+        )?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let text = concat!(
+            "# Netscape HTTP Cookie File\n",
+            "\n",
+            "example.com\tFALSE\t/\tFALSE\t0\tsession\tabc\n",
+            ".example.com\tTRUE\t/api\tTRUE\t4102444800\tpersistent\tdef\n",
+            "#HttpOnly_example.com\tFALSE\t/\tFALSE\t0\thidden\tghi\n",
+            "example.com\tFALSE\t/\tFALSE\t0\tempty\n",
+            "example.com\tFALSE\t/\tFALSE\t1\texpired\tjkl\n",
+        );
+        let mut store = CookieStore::default();
+        parse(text, &mut store).unwrap();
+
+        let url = Url::parse("https://www.example.com/api").unwrap();
+        let mut values: Vec<_> = store.get_request_values(&url).collect();
+        values.sort();
+        assert_eq!(values, [("persistent", "def")]);
+        assert!(store.get("example.com", "/", "hidden").unwrap().http_only() == Some(true));
+        assert_eq!(store.get("example.com", "/", "empty").unwrap().value(), "");
+        assert!(store.get_any("example.com", "/", "expired").is_none());
+
+        let mut out = Vec::new();
+        write(&mut out, store.iter_unexpired()).unwrap();
+        let mut lines: Vec<_> = std::str::from_utf8(&out).unwrap().lines().collect();
+        lines.sort();
+        assert_eq!(
+            lines[3..],
+            [
+                "#HttpOnly_example.com\tFALSE\t/\tFALSE\t0\thidden\tghi",
+                ".example.com\tTRUE\t/api\tTRUE\t4102444800\tpersistent\tdef",
+                "example.com\tFALSE\t/\tFALSE\t0\tempty\t",
+                "example.com\tFALSE\t/\tFALSE\t0\tsession\tabc",
+            ]
+        );
+    }
+
+    #[test]
+    fn invalid_lines() {
+        let mut store = CookieStore::default();
+        let err = parse("example.com\tFALSE\t/\n", &mut store).unwrap_err();
+        assert_eq!(err.to_string(), "line 1: expected 7 fields");
+        let err = parse("example.com\tFALSE\t/\tFALSE\tsoon\ta\tb\n", &mut store).unwrap_err();
+        assert_eq!(err.to_string(), "line 1: invalid expiry \"soon\"");
+    }
+}
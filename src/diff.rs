@@ -0,0 +1,418 @@
+//! Comparing two responses, for --diff-url, --diff-session, --diff-file and
+//! `xh diff`.
+//!
+//! JSON bodies (and bodies that decode to JSON, like MessagePack) are
+//! compared value by value, other bodies line by line.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::{self, Read};
+use std::path::Path;
+
+use anyhow::{Context, Result};
+use indicatif::HumanBytes;
+use reqwest::blocking::Response;
+use serde_json::Value;
+use similar::{ChangeTag, TextDiff};
+use termcolor::{Color, ColorSpec, WriteColor};
+
+use crate::decoder::{decompress, get_compression_type};
+use crate::serialization::{self, BinaryFormat};
+use crate::transfer::TransferLimits;
+use crate::utils::reason_phrase;
+
+/// Headers that are different every time, so comparing them is just noise.
+const IGNORED_HEADERS: &[&str] = &["date", "age"];
+
+/// What a response is compared to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DiffTarget {
+    Url(url::Url),
+    Session(std::ffi::OsString),
+    File(std::path::PathBuf),
+}
+
+/// A response, or the parts of it that were saved to a file.
+#[derive(Debug, Default)]
+pub struct Snapshot {
+    /// E.g. "HTTP/1.1 200 OK".
+    status: Option<String>,
+    /// With lowercase names. Files with only a body don't have these.
+    headers: Option<Vec<(String, String)>>,
+    body: Vec<u8>,
+}
+
+impl Snapshot {
+    pub fn from_response(response: &mut Response, limits: &TransferLimits) -> Result<Self> {
+        let status = format!(
+            "{:?} {} {}",
+            response.version(),
+            response.status().as_u16(),
+            reason_phrase(response)
+        );
+        let headers = response
+            .headers()
+            .iter()
+            .map(|(name, value)| {
+                let value = String::from_utf8_lossy(value.as_bytes()).into_owned();
+                (name.as_str().to_owned(), value)
+            })
+            .collect();
+        limits.check_length(response.content_length())?;
+        let compression_type = get_compression_type(response.headers());
+        let mut body = Vec::new();
+        decompress(&mut limits.wrap(response), compression_type).read_to_end(&mut body)?;
+        Ok(Snapshot {
+            status: Some(status),
+            headers: Some(headers),
+            body,
+        })
+    }
+
+    /// Load a response saved with e.g. `xh -p hb URL > FILE`, or just a body.
+    pub fn load(path: &Path) -> Result<Self> {
+        let contents =
+            fs::read(path).with_context(|| format!("Failed to read {}", path.display()))?;
+        Ok(Snapshot::parse(contents))
+    }
+
+    fn parse(contents: Vec<u8>) -> Self {
+        if !contents.starts_with(b"HTTP/") {
+            return Snapshot {
+                body: contents,
+                ..Snapshot::default()
+            };
+        }
+        // The body may contain the other kind of blank line, so split at
+        // whichever comes first
+        let separator = [&b"\n\n"[..], b"\r\n\r\n"]
+            .into_iter()
+            .filter_map(|separator| {
+                memchr::memmem::find(&contents, separator).map(|end| (end, separator.len()))
+            })
+            .min();
+        let (head, body) = match separator {
+            Some((end, len)) => (&contents[..end], &contents[end + len..]),
+            None => (&contents[..], &[][..]),
+        };
+        let head = String::from_utf8_lossy(head);
+        let mut lines = head.lines();
+        let status = lines.next().map(|line| line.trim_end().to_owned());
+        let headers = lines
+            .filter_map(|line| line.split_once(':'))
+            .map(|(name, value)| (name.trim().to_ascii_lowercase(), value.trim().to_owned()))
+            .collect();
+        Snapshot {
+            status,
+            headers: Some(headers),
+            body: body.to_vec(),
+        }
+    }
+
+    fn content_type(&self) -> Option<&str> {
+        self.headers
+            .as_ref()?
+            .iter()
+            .find_map(|(name, value)| (name == "content-type").then_some(value.as_str()))
+    }
+
+    /// The body as JSON, if it is JSON or decodes to it.
+    fn json(&self) -> Option<Value> {
+        match self
+            .content_type()
+            .and_then(BinaryFormat::from_content_type)
+        {
+            Some(format) => serialization::decode(format, &self.body).ok(),
+            None => serde_json::from_slice(&self.body).ok(),
+        }
+    }
+}
+
+enum Line {
+    Removed(String),
+    Added(String),
+    Unchanged(String),
+    Hunk(String),
+}
+
+/// Print the differences between two responses. Returns false and prints
+/// nothing if there aren't any.
+pub fn print_diff(
+    left: &Snapshot,
+    right: &Snapshot,
+    labels: (&str, &str),
+    color: bool,
+    out: &mut impl WriteColor,
+) -> io::Result<bool> {
+    let mut lines = Vec::new();
+    if let (Some(left), Some(right)) = (&left.status, &right.status) {
+        // Only the status matters, not the HTTP version
+        let status = |line: &str| line.split_once(' ').map(|(_, status)| status.to_owned());
+        if status(left) != status(right) {
+            lines.push(Line::Removed(format!("-{left}")));
+            lines.push(Line::Added(format!("+{right}")));
+        }
+    }
+    if let (Some(left), Some(right)) = (&left.headers, &right.headers) {
+        diff_headers(left, right, &mut lines);
+    }
+    if left.body != right.body {
+        diff_bodies(left, right, &mut lines);
+    }
+    if lines.is_empty() {
+        return Ok(false);
+    }
+
+    write_line(
+        out,
+        color,
+        ColorSpec::new().set_bold(true),
+        &format!("--- {}", labels.0),
+    )?;
+    write_line(
+        out,
+        color,
+        ColorSpec::new().set_bold(true),
+        &format!("+++ {}", labels.1),
+    )?;
+    for line in lines {
+        match line {
+            Line::Removed(text) => {
+                write_line(out, color, ColorSpec::new().set_fg(Some(Color::Red)), &text)?
+            }
+            Line::Added(text) => write_line(
+                out,
+                color,
+                ColorSpec::new().set_fg(Some(Color::Green)),
+                &text,
+            )?,
+            Line::Unchanged(text) => write_line(out, color, &ColorSpec::new(), &text)?,
+            Line::Hunk(text) => write_line(
+                out,
+                color,
+                ColorSpec::new().set_fg(Some(Color::Cyan)),
+                &text,
+            )?,
+        }
+    }
+    Ok(true)
+}
+
+fn write_line(
+    out: &mut impl WriteColor,
+    color: bool,
+    spec: &ColorSpec,
+    text: &str,
+) -> io::Result<()> {
+    if color && !spec.is_none() {
+        out.set_color(spec)?;
+        out.write_all(text.as_bytes())?;
+        out.reset()?;
+    } else {
+        out.write_all(text.as_bytes())?;
+    }
+    out.write_all(b"\n")
+}
+
+fn diff_headers(left: &[(String, String)], right: &[(String, String)], lines: &mut Vec<Line>) {
+    let mut headers: BTreeMap<&str, (Vec<&str>, Vec<&str>)> = BTreeMap::new();
+    for (name, value) in left {
+        headers.entry(name).or_default().0.push(value);
+    }
+    for (name, value) in right {
+        headers.entry(name).or_default().1.push(value);
+    }
+    for (name, (left, right)) in headers {
+        if left == right || IGNORED_HEADERS.contains(&name) {
+            continue;
+        }
+        for value in left.iter().filter(|value| !right.contains(value)) {
+            lines.push(Line::Removed(format!("-{name}: {value}")));
+        }
+        for value in right.iter().filter(|value| !left.contains(value)) {
+            lines.push(Line::Added(format!("+{name}: {value}")));
+        }
+    }
+}
+
+fn diff_bodies(left: &Snapshot, right: &Snapshot, lines: &mut Vec<Line>) {
+    if let (Some(left), Some(right)) = (left.json(), right.json()) {
+        diff_json(&mut String::new(), &left, &right, lines);
+        return;
+    }
+    if left.body.contains(&b'\0') || right.body.contains(&b'\0') {
+        let binary = |body: &[u8]| format!("[binary body of {}]", HumanBytes(body.len() as u64));
+        lines.push(Line::Removed(format!("-{}", binary(&left.body))));
+        lines.push(Line::Added(format!("+{}", binary(&right.body))));
+        return;
+    }
+    let (left, right) = (
+        String::from_utf8_lossy(&left.body),
+        String::from_utf8_lossy(&right.body),
+    );
+    let diff = TextDiff::from_lines(&*left, &*right);
+    for hunk in diff.unified_diff().context_radius(3).iter_hunks() {
+        lines.push(Line::Hunk(hunk.header().to_string()));
+        for change in hunk.iter_changes() {
+            let text = format!("{}{}", change.tag(), change.value().trim_end_matches('\n'));
+            lines.push(match change.tag() {
+                ChangeTag::Delete => Line::Removed(text),
+                ChangeTag::Insert => Line::Added(text),
+                ChangeTag::Equal => Line::Unchanged(text),
+            });
+        }
+    }
+}
+
+/// Compare two JSON values, with object keys in any order. Changes are
+/// shown with the path to the value, like in jq.
+fn diff_json(path: &mut String, left: &Value, right: &Value, lines: &mut Vec<Line>) {
+    let shown_path = |path: &str| {
+        if path.is_empty() {
+            ".".to_owned()
+        } else {
+            path.to_owned()
+        }
+    };
+    match (left, right) {
+        (Value::Object(left), Value::Object(right)) => {
+            for (key, left_value) in left {
+                let len = path.len();
+                push_key(path, key);
+                match right.get(key) {
+                    Some(right_value) => diff_json(path, left_value, right_value, lines),
+                    None => lines.push(Line::Removed(format!("-{path}: {left_value}"))),
+                }
+                path.truncate(len);
+            }
+            for (key, right_value) in right {
+                if !left.contains_key(key) {
+                    let len = path.len();
+                    push_key(path, key);
+                    lines.push(Line::Added(format!("+{path}: {right_value}")));
+                    path.truncate(len);
+                }
+            }
+        }
+        (Value::Array(left), Value::Array(right)) => {
+            for index in 0..left.len().max(right.len()) {
+                let len = path.len();
+                if path.is_empty() {
+                    path.push('.');
+                }
+                path.push_str(&format!("[{index}]"));
+                match (left.get(index), right.get(index)) {
+                    (Some(left), Some(right)) => diff_json(path, left, right, lines),
+                    (Some(left), None) => lines.push(Line::Removed(format!("-{path}: {left}"))),
+                    (None, Some(right)) => lines.push(Line::Added(format!("+{path}: {right}"))),
+                    (None, None) => unreachable!(),
+                }
+                path.truncate(len);
+            }
+        }
+        (left, right) if left == right => (),
+        (left, right) => {
+            let path = shown_path(path);
+            lines.push(Line::Removed(format!("-{path}: {left}")));
+            lines.push(Line::Added(format!("+{path}: {right}")));
+        }
+    }
+}
+
+fn push_key(path: &mut String, key: &str) {
+    let is_identifier = key.chars().next().is_some_and(|c| !c.is_ascii_digit())
+        && key.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+    if is_identifier {
+        path.push('.');
+        path.push_str(key);
+    } else {
+        path.push_str(&format!(".[{}]", Value::from(key)));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use termcolor::NoColor;
+
+    fn diff(left: Snapshot, right: Snapshot) -> String {
+        let mut out = NoColor::new(Vec::new());
+        print_diff(&left, &right, ("a", "b"), false, &mut out).unwrap();
+        String::from_utf8(out.into_inner()).unwrap()
+    }
+
+    fn body(body: &str) -> Snapshot {
+        Snapshot::parse(body.as_bytes().to_vec())
+    }
+
+    #[test]
+    fn json_values() {
+        let left = json!({"a": 1, "b": [1, 2, 3], "c": {"d": "e"}, "weird key": true});
+        let right = json!({"weird key": false, "c": {"d": "e"}, "b": [1, 5], "a": 1, "f": null});
+        assert_eq!(
+            diff(body(&left.to_string()), body(&right.to_string())),
+            concat!(
+                "--- a\n",
+                "+++ b\n",
+                "-.b[1]: 2\n",
+                "+.b[1]: 5\n",
+                "-.b[2]: 3\n",
+                "-.[\"weird key\"]: true\n",
+                "+.[\"weird key\"]: false\n",
+                "+.f: null\n",
+            )
+        );
+        // Only the order of the keys is different
+        assert_eq!(
+            diff(body(r#"{"a":1,"b":2}"#), body(r#"{"b": 2, "a": 1}"#)),
+            ""
+        );
+        assert_eq!(
+            diff(body("[1]"), body("{}")),
+            "--- a\n+++ b\n-.: [1]\n+.: {}\n"
+        );
+    }
+
+    #[test]
+    fn text_lines() {
+        assert_eq!(
+            diff(body("a\nb\nc\n"), body("a\nB\nc\n")),
+            "--- a\n+++ b\n@@ -1,3 +1,3 @@\n a\n-b\n+B\n c\n"
+        );
+    }
+
+    #[test]
+    fn saved_response() {
+        let saved = Snapshot::parse(
+            b"HTTP/1.1 200 OK\nContent-Type: text/plain\nDate: yesterday\nX-A: 1\n\nhello\n"
+                .to_vec(),
+        );
+        assert_eq!(saved.status.as_deref(), Some("HTTP/1.1 200 OK"));
+        assert_eq!(saved.body, b"hello\n");
+        let crlf = Snapshot::parse(b"HTTP/1.1 200 OK\r\nX-A: 1\r\n\r\none\n\ntwo\n".to_vec());
+        assert_eq!(crlf.status.as_deref(), Some("HTTP/1.1 200 OK"));
+        assert_eq!(crlf.headers, Some(vec![("x-a".to_owned(), "1".to_owned())]));
+        assert_eq!(crlf.body, b"one\n\ntwo\n");
+        let current = Snapshot {
+            status: Some("HTTP/2.0 404 Not Found".to_owned()),
+            headers: Some(vec![
+                ("content-type".to_owned(), "text/plain".to_owned()),
+                ("date".to_owned(), "today".to_owned()),
+                ("x-b".to_owned(), "2".to_owned()),
+            ]),
+            body: b"hello\n".to_vec(),
+        };
+        assert_eq!(
+            diff(saved, current),
+            concat!(
+                "--- a\n",
+                "+++ b\n",
+                "-HTTP/1.1 200 OK\n",
+                "+HTTP/2.0 404 Not Found\n",
+                "-x-a: 1\n",
+                "+x-b: 2\n",
+            )
+        );
+    }
+}
@@ -1,20 +1,30 @@
+use std::ffi::OsString;
 use std::fs::{self, File, OpenOptions};
-use std::io::{self, ErrorKind, IsTerminal};
+use std::io::{self, ErrorKind, IsTerminal, Read, Seek, SeekFrom, Write};
 use std::path::{Path, PathBuf};
-use std::time::Instant;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
 
+use crate::checksum::{Checksum, HashingWriter, Verifier};
 use crate::content_disposition;
 use crate::decoder::{decompress, get_compression_type};
-use crate::utils::{HeaderValueExt, copy_largebuf, test_pretend_term};
+use crate::redirect::{is_cross_domain_redirect, remove_sensitive_headers};
+use crate::transfer::TransferLimits;
+use crate::utils::{HeaderValueExt, copy_largebuf, reason_phrase, test_pretend_term};
 use anyhow::{Context, Result, anyhow};
 use indicatif::{HumanBytes, ProgressBar, ProgressStyle};
 use mime2ext::mime2ext;
 use regex_lite::Regex;
 use reqwest::{
     StatusCode,
-    blocking::Response,
-    header::{CONTENT_DISPOSITION, CONTENT_LENGTH, CONTENT_RANGE, CONTENT_TYPE, HeaderMap},
+    blocking::{Client, Request, Response},
+    header::{
+        ACCEPT_RANGES, CONTENT_DISPOSITION, CONTENT_ENCODING, CONTENT_LENGTH, CONTENT_RANGE,
+        CONTENT_TYPE, ETAG, HeaderMap, HeaderValue, IF_RANGE, LAST_MODIFIED, RANGE,
+    },
 };
+use serde::{Deserialize, Serialize};
 
 fn get_content_length(headers: &HeaderMap) -> Option<u64> {
     headers
@@ -25,13 +35,9 @@ fn get_content_length(headers: &HeaderMap) -> Option<u64> {
 
 // This function is system-agnostic, so it's ok for it to use Strings instead
 // of PathBufs
-fn get_file_name(response: &Response, orig_url: &reqwest::Url) -> String {
-    fn from_header(response: &Response) -> Option<String> {
-        let header = response
-            .headers()
-            .get(CONTENT_DISPOSITION)?
-            .to_utf8_str()
-            .ok()?;
+pub fn get_file_name(headers: &HeaderMap, orig_url: &reqwest::Url) -> String {
+    fn from_header(headers: &HeaderMap) -> Option<String> {
+        let header = headers.get(CONTENT_DISPOSITION)?.to_utf8_str().ok()?;
         content_disposition::parse_filename_from_content_disposition(header)
     }
 
@@ -43,12 +49,12 @@ fn get_file_name(response: &Response, orig_url: &reqwest::Url) -> String {
         Some(last_seg.to_string())
     }
 
-    fn guess_extension(response: &Response) -> Option<&'static str> {
-        let mimetype = response.headers().get(CONTENT_TYPE)?.to_str().ok()?;
+    fn guess_extension(headers: &HeaderMap) -> Option<&'static str> {
+        let mimetype = headers.get(CONTENT_TYPE)?.to_str().ok()?;
         mime2ext(mimetype)
     }
 
-    let filename = from_header(response)
+    let filename = from_header(headers)
         .or_else(|| from_url(orig_url))
         .unwrap_or_else(|| "index".to_string());
 
@@ -63,7 +69,7 @@ fn get_file_name(response: &Response, orig_url: &reqwest::Url) -> String {
     let mut filename = filename.trim().trim_start_matches('.').to_string();
 
     if !filename.contains('.') {
-        if let Some(extension) = guess_extension(response) {
+        if let Some(extension) = guess_extension(headers) {
             filename.push('.');
             filename.push_str(extension);
         }
@@ -72,6 +78,18 @@ fn get_file_name(response: &Response, orig_url: &reqwest::Url) -> String {
     filename
 }
 
+fn generated_path(
+    response: &Response,
+    orig_url: &reqwest::Url,
+    output_dir: Option<&Path>,
+) -> PathBuf {
+    let file_name = get_file_name(response.headers(), orig_url);
+    match output_dir {
+        Some(output_dir) => output_dir.join(file_name),
+        None => file_name.into(),
+    }
+}
+
 pub fn get_file_size(path: Option<&Path>) -> Option<u64> {
     Some(fs::metadata(path?).ok()?.len())
 }
@@ -163,9 +181,47 @@ const UNCOLORED_BAR_TEMPLATE: &str =
 const SPINNER_TEMPLATE: &str = "{spinner:.green} {bytes} {bytes_per_sec} {wide_msg}";
 const UNCOLORED_SPINNER_TEMPLATE: &str = "{spinner} {bytes} {bytes_per_sec} {wide_msg}";
 
+fn progress_bar(total_length: Option<u64>, color: bool) -> Result<ProgressBar> {
+    if let Some(total_length) = total_length {
+        let style = ProgressStyle::default_bar()
+            .template(if color {
+                BAR_TEMPLATE
+            } else {
+                UNCOLORED_BAR_TEMPLATE
+            })?
+            .progress_chars("#>-");
+        Ok(ProgressBar::new(total_length).with_style(style))
+    } else {
+        let style = ProgressStyle::default_bar().template(if color {
+            SPINNER_TEMPLATE
+        } else {
+            UNCOLORED_SPINNER_TEMPLATE
+        })?;
+        Ok(ProgressBar::new_spinner().with_style(style))
+    }
+}
+
+fn print_done(downloaded_length: u64, starting_time: Instant) {
+    let time_taken = starting_time.elapsed();
+    if !time_taken.is_zero() {
+        eprintln!(
+            "Done. {} in {:.5}s ({}/s)",
+            HumanBytes(downloaded_length),
+            time_taken.as_secs_f64(),
+            HumanBytes((downloaded_length as f64 / time_taken.as_secs_f64()) as u64)
+        );
+    } else {
+        eprintln!("Done. {}", HumanBytes(downloaded_length));
+    }
+}
+
+/// Download the response body, returning the path it was saved to.
+#[allow(clippy::too_many_arguments)]
 pub fn download_file(
     mut response: Response,
     file_name: Option<PathBuf>,
+    // Where to put the file if its name is generated.
+    output_dir: Option<&Path>,
     // If we fall back on taking the filename from the URL it has to be the
     // original URL, before redirects. That's less surprising and matches
     // HTTPie. Hence this argument.
@@ -173,12 +229,64 @@ pub fn download_file(
     mut resume: Option<u64>,
     color: bool,
     quiet: bool,
-) -> Result<()> {
+    segmented: Option<Segmented>,
+    retry: Option<Retry>,
+    checksum: Option<Checksum>,
+    limits: &TransferLimits,
+) -> Result<PathBuf> {
     if resume.is_some() && response.status() != StatusCode::PARTIAL_CONTENT {
         resume = None;
     }
 
-    let mut buffer: Box<dyn io::Write>;
+    let mut checksums: Vec<Checksum> = checksum.iter().cloned().collect();
+    checksums.extend(Checksum::from_headers(response.headers(), resume.is_some()));
+    let mut verifier = Verifier::new(checksums);
+
+    if let Some(segmented) = segmented {
+        let dest_is_file = file_name.is_some()
+            || output_dir.is_some()
+            || test_pretend_term()
+            || io::stdout().is_terminal();
+        match range_length(&response) {
+            Some(total_length) if resume.is_none() && dest_is_file => {
+                limits.check_length(Some(total_length))?;
+                let dest_name = match file_name {
+                    Some(file_name) => file_name,
+                    None => open_new_file(generated_path(&response, orig_url, output_dir))?.0,
+                };
+                download_segments(
+                    segmented,
+                    response,
+                    &dest_name,
+                    total_length,
+                    verifier,
+                    limits,
+                    color,
+                    quiet,
+                )?;
+                return Ok(dest_name);
+            }
+            Some(_) => (),
+            // A 206 means the server does support them, it's continuing an
+            // earlier download
+            None if response.status() == StatusCode::OK
+                && response
+                    .headers()
+                    .get(ACCEPT_RANGES)
+                    .is_none_or(|value| value != "bytes") =>
+            {
+                log::warn!("The server doesn't support range requests, downloading in one piece")
+            }
+            None => (),
+        }
+    }
+
+    // Check before the file is created or truncated
+    limits.check_length(
+        get_content_length(response.headers()).map(|length| length + resume.unwrap_or(0)),
+    )?;
+
+    let buffer: Box<dyn io::Write>;
     let dest_name: PathBuf;
 
     if let Some(file_name) = file_name {
@@ -192,8 +300,8 @@ pub fn download_file(
 
         dest_name = file_name;
         buffer = Box::new(open_opts.open(&dest_name)?);
-    } else if test_pretend_term() || io::stdout().is_terminal() {
-        let (new_name, handle) = open_new_file(get_file_name(&response, orig_url).into())?;
+    } else if output_dir.is_some() || test_pretend_term() || io::stdout().is_terminal() {
+        let (new_name, handle) = open_new_file(generated_path(&response, orig_url, output_dir))?;
         dest_name = new_name;
         buffer = Box::new(handle);
     } else {
@@ -201,8 +309,16 @@ pub fn download_file(
         buffer = Box::new(io::stdout());
     }
 
-    let starting_length: u64;
-    let total_length: Option<u64>;
+    if let Some(resume) = resume {
+        if !verifier.is_empty() {
+            // The checksum covers the part that was downloaded before as well
+            io::copy(&mut File::open(&dest_name)?.take(resume), &mut verifier)?;
+        }
+    }
+    let mut buffer = HashingWriter::new(buffer, verifier);
+
+    let mut starting_length: u64;
+    let mut total_length: Option<u64>;
     if let Some(resume) = resume {
         let header = response
             .headers()
@@ -221,66 +337,467 @@ pub fn download_file(
 
     let pb = if quiet {
         None
-    } else if let Some(total_length) = total_length {
-        eprintln!(
-            "Downloading {} to {:?}",
-            HumanBytes(total_length - starting_length),
-            dest_name
-        );
-        let style = ProgressStyle::default_bar()
-            .template(if color {
-                BAR_TEMPLATE
-            } else {
-                UNCOLORED_BAR_TEMPLATE
-            })?
-            .progress_chars("#>-");
-        Some(ProgressBar::new(total_length).with_style(style))
     } else {
-        eprintln!("Downloading to {dest_name:?}");
-        let style = ProgressStyle::default_bar().template(if color {
-            SPINNER_TEMPLATE
-        } else {
-            UNCOLORED_SPINNER_TEMPLATE
-        })?;
-        Some(ProgressBar::new_spinner().with_style(style))
+        match total_length {
+            Some(total_length) => eprintln!(
+                "Downloading {} to {:?}",
+                HumanBytes(total_length - starting_length),
+                dest_name
+            ),
+            None => eprintln!("Downloading to {dest_name:?}"),
+        }
+        Some(progress_bar(total_length, color)?)
     };
     if let Some(pb) = &pb {
         pb.set_position(starting_length);
         pb.reset_eta();
     }
 
-    match pb {
-        Some(ref pb) => {
-            let compression_type = get_compression_type(response.headers());
-            copy_largebuf(
-                &mut decompress(&mut pb.wrap_read(response), compression_type),
+    let compression_type = get_compression_type(response.headers());
+    // Ranges refer to the compressed data, so only uncompressed bodies can be resumed
+    let mut retry = retry.filter(|_| compression_type.is_none());
+    let validator = if_range_validator(response.headers());
+    loop {
+        let result = match pb {
+            Some(ref pb) => copy_largebuf(
+                &mut decompress(
+                    &mut pb.wrap_read(limits.wrap(&mut response)),
+                    compression_type,
+                ),
+                &mut buffer,
+                false,
+            ),
+            None => copy_largebuf(
+                &mut decompress(&mut limits.wrap(&mut response), compression_type),
                 &mut buffer,
                 false,
-            )?;
-            let downloaded_length = pb.position() - starting_length;
-            pb.finish_and_clear();
-            let time_taken = starting_time.elapsed();
-            if !time_taken.is_zero() {
-                eprintln!(
-                    "Done. {} in {:.5}s ({}/s)",
-                    HumanBytes(downloaded_length),
-                    time_taken.as_secs_f64(),
-                    HumanBytes((downloaded_length as f64 / time_taken.as_secs_f64()) as u64)
+            ),
+        };
+        let Some(retry) = retry.as_mut().filter(|retry| retry.retries > 0) else {
+            result?;
+            break;
+        };
+        let written = get_file_size(Some(&dest_name)).unwrap_or(0);
+        match result {
+            Ok(()) if total_length.is_none_or(|total_length| written >= total_length) => break,
+            Ok(()) => log::warn!(
+                "Connection closed after {} of {}, resuming download",
+                HumanBytes(written),
+                HumanBytes(total_length.unwrap_or_default())
+            ),
+            Err(err) if is_connection_drop(&err) => {
+                log::warn!("{err}, resuming download")
+            }
+            Err(err) => return Err(err.into()),
+        }
+        retry.retries -= 1;
+
+        response = retry.resume(response.url(), written, validator.as_ref())?;
+        match response.status() {
+            StatusCode::PARTIAL_CONTENT => {
+                let header = response
+                    .headers()
+                    .get(CONTENT_RANGE)
+                    .ok_or_else(|| anyhow!("Missing Content-Range header"))?
+                    .to_str()
+                    .map_err(|_| anyhow!("Bad Content-Range header"))?;
+                total_length = Some(total_for_content_range(header, written)?);
+            }
+            StatusCode::OK => {
+                // If-Range didn't match, so the file changed and the server sent all of it
+                log::warn!("The file changed on the server, restarting the download");
+                let checksums = checksum
+                    .iter()
+                    .cloned()
+                    .chain(Checksum::from_headers(response.headers(), false))
+                    .collect();
+                buffer = HashingWriter::new(
+                    Box::new(File::create(&dest_name)?),
+                    Verifier::new(checksums),
                 );
-            } else {
-                eprintln!("Done. {}", HumanBytes(downloaded_length));
+                starting_length = 0;
+                total_length = get_content_length(response.headers());
             }
+            status => {
+                return Err(anyhow!(
+                    "Couldn't resume download: HTTP {} {}",
+                    status.as_u16(),
+                    reason_phrase(&response)
+                ));
+            }
+        }
+        limits.check_length(total_length)?;
+        if let Some(pb) = &pb {
+            if let Some(total_length) = total_length {
+                pb.set_length(total_length);
+            }
+            pb.set_position(get_file_size(Some(&dest_name)).unwrap_or(0));
         }
+    }
+
+    if let Some(pb) = pb {
+        let downloaded_length = pb.position() - starting_length;
+        pb.finish_and_clear();
+        print_done(downloaded_length, starting_time);
+    }
+
+    verify_download(buffer.into_verifier(), &dest_name)?;
+    Ok(dest_name)
+}
+
+/// Whether a failed read of the body is worth resuming with `--retry`.
+fn is_connection_drop(err: &io::Error) -> bool {
+    matches!(
+        err.kind(),
+        io::ErrorKind::ConnectionReset
+            | io::ErrorKind::ConnectionAborted
+            | io::ErrorKind::UnexpectedEof
+            | io::ErrorKind::TimedOut
+    ) || err
+        .get_ref()
+        .and_then(|err| err.downcast_ref::<reqwest::Error>())
+        .is_some_and(|err| {
+            // Other errors, like a body that fails to decode, would only happen again
+            err.is_timeout() || ((err.is_request() || err.is_body()) && has_io_source(err))
+        })
+}
+
+fn has_io_source(err: &reqwest::Error) -> bool {
+    let mut source = std::error::Error::source(err);
+    while let Some(err) = source {
+        if err.is::<io::Error>() {
+            return true;
+        }
+        source = err.source();
+    }
+    false
+}
+
+/// The validator to send in If-Range, so that a changed file isn't resumed.
+///
+/// Weak ETags can't be used for range requests, so those fall back to Last-Modified.
+fn if_range_validator(headers: &HeaderMap) -> Option<HeaderValue> {
+    headers
+        .get(ETAG)
+        .filter(|etag| !etag.as_bytes().starts_with(b"W/"))
+        .or_else(|| headers.get(LAST_MODIFIED))
+        .cloned()
+}
+
+/// What's needed to resume a download after the connection drops, for `--retry`.
+pub struct Retry {
+    pub retries: u32,
+    pub client: Client,
+    /// The original request, used as a template for the range requests.
+    pub request: Request,
+}
+
+impl Retry {
+    fn resume(
+        &self,
+        url: &reqwest::Url,
+        from: u64,
+        validator: Option<&HeaderValue>,
+    ) -> Result<Response> {
+        let mut request = self
+            .request
+            .try_clone()
+            .ok_or_else(|| anyhow!("Request can't be resumed"))?;
+        if is_cross_domain_redirect(url, request.url()) {
+            remove_sensitive_headers(request.headers_mut());
+        }
+        *request.url_mut() = url.clone();
+        request
+            .headers_mut()
+            .insert(RANGE, HeaderValue::from_str(&format!("bytes={from}-"))?);
+        if let Some(validator) = validator {
+            request.headers_mut().insert(IF_RANGE, validator.clone());
+        }
+        Ok(self.client.execute(request)?)
+    }
+}
+
+/// Check the downloaded file against its checksums, and delete it if it doesn't match.
+fn verify_download(verifier: Verifier, dest_name: &Path) -> Result<()> {
+    if let Err(err) = verifier.verify() {
+        if dest_name == Path::new("<stdout>") {
+            return Err(err);
+        }
+        fs::remove_file(dest_name)?;
+        return Err(err.context(format!(
+            "Downloaded file {dest_name:?} is corrupt and was deleted"
+        )));
+    }
+    Ok(())
+}
+
+/// What's needed to fetch the rest of a `--segments` download.
+pub struct Segmented {
+    pub segments: u16,
+    pub client: Client,
+    /// The original request, used as a template for the range requests.
+    pub request: Request,
+    /// Whether to pick up the parts listed in an existing state file.
+    pub resume: bool,
+}
+
+/// The progress of a segmented download, kept next to the file so that
+/// `--continue` knows which parts are still missing.
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+struct SegmentState {
+    length: u64,
+    /// The ETag or Last-Modified header, to detect that the file changed.
+    validator: Option<String>,
+    segments: Vec<Segment>,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+struct Segment {
+    start: u64,
+    end: u64,
+    done: u64,
+}
+
+impl SegmentState {
+    fn new(length: u64, validator: Option<String>, count: u16) -> Self {
+        let count = u64::from(count).clamp(1, length.max(1));
+        let size = length.div_ceil(count);
+        let segments = (0..count)
+            .map(|i| Segment {
+                start: i * size,
+                end: ((i + 1) * size).min(length),
+                done: 0,
+            })
+            .filter(|segment| segment.start < segment.end)
+            .collect();
+        SegmentState {
+            length,
+            validator,
+            segments,
+        }
+    }
+
+    fn save(&self, path: &Path, done: &[AtomicU64]) -> Result<()> {
+        let state = SegmentState {
+            length: self.length,
+            validator: self.validator.clone(),
+            segments: self
+                .segments
+                .iter()
+                .zip(done)
+                .map(|(segment, done)| Segment {
+                    done: done.load(Ordering::Relaxed),
+                    ..*segment
+                })
+                .collect(),
+        };
+        // Replace the file in one go, it'd be useless if we were interrupted
+        // while writing it
+        let dir = match path.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => parent,
+            _ => Path::new("."),
+        };
+        let write = || -> Result<()> {
+            let mut file = tempfile::NamedTempFile::new_in(dir)?;
+            file.write_all(&serde_json::to_vec(&state)?)?;
+            file.persist(path)?;
+            Ok(())
+        };
+        write().with_context(|| format!("couldn't write {}", path.display()))
+    }
+}
+
+pub fn segment_state_path(file_name: &Path) -> PathBuf {
+    let mut path = OsString::from(file_name);
+    path.push(".xh-segments");
+    path.into()
+}
+
+/// The length of the response if the same URL can be fetched in parts.
+fn range_length(response: &Response) -> Option<u64> {
+    let headers = response.headers();
+    if response.status() != StatusCode::OK
+        || headers.get(ACCEPT_RANGES)? != "bytes"
+        || headers.contains_key(CONTENT_ENCODING)
+    {
+        return None;
+    }
+    get_content_length(headers).filter(|&length| length > 0)
+}
+
+#[allow(clippy::too_many_arguments)]
+fn download_segments(
+    segmented: Segmented,
+    response: Response,
+    dest_name: &Path,
+    total_length: u64,
+    mut verifier: Verifier,
+    limits: &TransferLimits,
+    color: bool,
+    quiet: bool,
+) -> Result<()> {
+    let validator = [ETAG, LAST_MODIFIED]
+        .iter()
+        .find_map(|name| response.headers().get(name)?.to_str().ok())
+        .map(String::from);
+
+    let mut request = segmented.request;
+    if is_cross_domain_redirect(response.url(), request.url()) {
+        remove_sensitive_headers(request.headers_mut());
+    }
+    *request.url_mut() = response.url().clone();
+    // The parts are fetched separately, so this response isn't needed any further
+    drop(response);
+
+    let state_path = segment_state_path(dest_name);
+    let previous_state = if segmented.resume {
+        fs::read(&state_path)
+            .ok()
+            .and_then(|state| serde_json::from_slice::<SegmentState>(&state).ok())
+            .filter(|state| state.length == total_length && state.validator == validator)
+    } else {
+        None
+    };
+    let state = match previous_state {
+        Some(state) => state,
         None => {
-            let compression_type = get_compression_type(response.headers());
-            copy_largebuf(
-                &mut decompress(&mut response, compression_type),
-                &mut buffer,
-                false,
-            )?;
+            let file = File::create(dest_name)?;
+            file.set_len(total_length)?;
+            SegmentState::new(total_length, validator, segmented.segments)
         }
+    };
+    let done: Vec<AtomicU64> = state
+        .segments
+        .iter()
+        .map(|segment| AtomicU64::new(segment.done))
+        .collect();
+    state.save(&state_path, &done)?;
+
+    let starting_length: u64 = state.segments.iter().map(|segment| segment.done).sum();
+    let starting_time = Instant::now();
+    let pb = if quiet {
+        ProgressBar::hidden()
+    } else {
+        eprintln!(
+            "Downloading {} to {:?} in {} segments",
+            HumanBytes(total_length - starting_length),
+            dest_name,
+            state.segments.len()
+        );
+        progress_bar(Some(total_length), color)?
+    };
+    pb.set_position(starting_length);
+    pb.reset_eta();
+
+    let failed = AtomicBool::new(false);
+    let result = thread::scope(|scope| {
+        let handles: Vec<_> = state
+            .segments
+            .iter()
+            .zip(&done)
+            .map(|(segment, done)| {
+                // Requests aren't Sync, so each thread gets its own copy
+                let request = request.try_clone().unwrap();
+                let (client, pb, failed) = (&segmented.client, &pb, &failed);
+                scope.spawn(move || {
+                    let result = fetch_segment(
+                        client, request, limits, dest_name, segment, done, pb, failed,
+                    );
+                    if result.is_err() {
+                        failed.store(true, Ordering::Relaxed);
+                    }
+                    result
+                })
+            })
+            .collect();
+        while !handles.iter().all(|handle| handle.is_finished()) {
+            thread::sleep(Duration::from_millis(100));
+            if let Err(err) = state.save(&state_path, &done) {
+                failed.store(true, Ordering::Relaxed);
+                return Err(err);
+            }
+        }
+        handles
+            .into_iter()
+            .try_for_each(|handle| handle.join().unwrap())
+    });
+    pb.finish_and_clear();
+
+    if let Err(err) = result {
+        state.save(&state_path, &done)?;
+        return Err(err.context(format!(
+            "Download of {dest_name:?} is incomplete, use --continue to resume it"
+        )));
+    }
+    fs::remove_file(&state_path)?;
+    if !quiet {
+        print_done(total_length - starting_length, starting_time);
+    }
+
+    if !verifier.is_empty() {
+        // The parts arrive out of order, so the file is hashed afterwards
+        io::copy(&mut File::open(dest_name)?, &mut verifier)?;
     }
+    verify_download(verifier, dest_name)
+}
 
+#[allow(clippy::too_many_arguments)]
+fn fetch_segment(
+    client: &Client,
+    mut request: Request,
+    limits: &TransferLimits,
+    dest_name: &Path,
+    segment: &Segment,
+    done: &AtomicU64,
+    pb: &ProgressBar,
+    failed: &AtomicBool,
+) -> Result<()> {
+    let start = segment.start + done.load(Ordering::Relaxed);
+    let last = segment.end - 1;
+    if start > last {
+        return Ok(());
+    }
+
+    request.headers_mut().insert(
+        RANGE,
+        HeaderValue::from_str(&format!("bytes={start}-{last}"))?,
+    );
+    let response = client.execute(request)?.error_for_status()?;
+    let content_range = response
+        .headers()
+        .get(CONTENT_RANGE)
+        .and_then(|value| value.to_str().ok());
+    if response.status() != StatusCode::PARTIAL_CONTENT
+        || !content_range.is_some_and(|value| value.starts_with(&format!("bytes {start}-{last}/")))
+    {
+        return Err(anyhow!(
+            "The server didn't return the requested range bytes={start}-{last}"
+        ));
+    }
+
+    let mut response = limits.wrap(response);
+    let mut file = OpenOptions::new().write(true).open(dest_name)?;
+    file.seek(SeekFrom::Start(start))?;
+    let mut remaining = segment.end - start;
+    let mut buf = vec![0; 128 * 1024];
+    while remaining > 0 && !failed.load(Ordering::Relaxed) {
+        let n = match response.read(&mut buf) {
+            Ok(0) => break,
+            Ok(n) => n.min(remaining as usize),
+            Err(err) if err.kind() == ErrorKind::Interrupted => continue,
+            Err(err) => return Err(err.into()),
+        };
+        file.write_all(&buf[..n])?;
+        remaining -= n as u64;
+        done.fetch_add(n as u64, Ordering::Relaxed);
+        pb.inc(n as u64);
+    }
+    if remaining > 0 && !failed.load(Ordering::Relaxed) {
+        return Err(anyhow!(
+            "Connection closed with {} left in bytes={start}-{last}",
+            HumanBytes(remaining)
+        ));
+    }
     Ok(())
 }
 
@@ -303,4 +820,19 @@ mod tests {
             assert_eq!(total_for_content_range(header, start).ok(), result);
         }
     }
+
+    #[test]
+    fn segments_cover_the_file() {
+        let ranges = |length, count| -> Vec<(u64, u64)> {
+            SegmentState::new(length, None, count)
+                .segments
+                .iter()
+                .map(|segment| (segment.start, segment.end))
+                .collect()
+        };
+        assert_eq!(ranges(10, 3), [(0, 4), (4, 8), (8, 10)]);
+        assert_eq!(ranges(9, 3), [(0, 3), (3, 6), (6, 9)]);
+        assert_eq!(ranges(2, 8), [(0, 1), (1, 2)]);
+        assert_eq!(ranges(10, 1), [(0, 10)]);
+    }
 }
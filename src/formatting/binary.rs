@@ -0,0 +1,169 @@
+//! Showing binary bodies in the terminal, for --binary.
+
+use std::io::{self, Read};
+
+use base64::{Engine as _, engine::general_purpose::STANDARD};
+use termcolor::{Color, ColorSpec, WriteColor};
+
+use crate::cli::BinaryDisplay;
+
+/// Write a binary body as a hex dump or as base64.
+///
+/// `offset` is the position in the body of the first byte that `reader`
+/// returns, and at most `limit` bytes are shown.
+pub fn dump_binary(
+    format: BinaryDisplay,
+    reader: &mut impl Read,
+    mut offset: u64,
+    limit: Option<u64>,
+    color: bool,
+    out: &mut impl WriteColor,
+) -> io::Result<()> {
+    // A base64 line of 76 characters holds 57 bytes
+    let line_length: usize = match format {
+        BinaryDisplay::Hexdump => 16,
+        BinaryDisplay::Base64 => 57,
+        BinaryDisplay::Suppress => unreachable!("suppressed bodies aren't dumped"),
+    };
+    let mut line = vec![0; line_length];
+    let mut shown = 0;
+    loop {
+        let wanted = match limit {
+            Some(limit) => (limit - shown).min(line_length as u64) as usize,
+            None => line_length,
+        };
+        if wanted == 0 {
+            if read_full(reader, &mut [0])? > 0 {
+                out.write_all(b"[more data not shown, see --binary-limit]\n")?;
+            }
+            break;
+        }
+        let read = read_full(reader, &mut line[..wanted])?;
+        if read == 0 {
+            break;
+        }
+        match format {
+            BinaryDisplay::Hexdump => write_hex_line(out, offset, &line[..read], color)?,
+            _ => writeln!(out, "{}", STANDARD.encode(&line[..read]))?,
+        }
+        out.flush()?;
+        offset += read as u64;
+        shown += read as u64;
+        if read < wanted {
+            break;
+        }
+    }
+    Ok(())
+}
+
+/// Fill `buf` unless the reader runs out first, and return how much was read.
+fn read_full(reader: &mut impl Read, buf: &mut [u8]) -> io::Result<usize> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        match reader.read(&mut buf[filled..]) {
+            Ok(0) => break,
+            Ok(n) => filled += n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(filled)
+}
+
+/// Write a line in the format of xxd, with bytes colored by kind like `xxd -R`.
+fn write_hex_line(
+    out: &mut impl WriteColor,
+    offset: u64,
+    bytes: &[u8],
+    color: bool,
+) -> io::Result<()> {
+    write!(out, "{offset:08x}: ")?;
+    for index in 0..16 {
+        match bytes.get(index) {
+            Some(&byte) => write_colored(out, byte, &format!("{byte:02x}"), color)?,
+            None => out.write_all(b"  ")?,
+        }
+        if index % 2 == 1 {
+            out.write_all(b" ")?;
+        }
+    }
+    out.write_all(b" ")?;
+    for &byte in bytes {
+        let shown = if byte.is_ascii_graphic() || byte == b' ' {
+            char::from(byte)
+        } else {
+            '.'
+        };
+        write_colored(out, byte, shown.encode_utf8(&mut [0; 4]), color)?;
+    }
+    out.write_all(b"\n")
+}
+
+fn write_colored(out: &mut impl WriteColor, byte: u8, text: &str, color: bool) -> io::Result<()> {
+    if !color {
+        return out.write_all(text.as_bytes());
+    }
+    let fg = match byte {
+        0 => None,
+        0xff => Some(Color::Blue),
+        byte if byte.is_ascii_graphic() => Some(Color::Green),
+        byte if byte.is_ascii_whitespace() => Some(Color::Yellow),
+        _ => Some(Color::Red),
+    };
+    out.set_color(ColorSpec::new().set_fg(fg))?;
+    out.write_all(text.as_bytes())?;
+    out.reset()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use termcolor::NoColor;
+
+    fn dump(format: BinaryDisplay, body: &[u8], offset: u64, limit: Option<u64>) -> String {
+        let mut out = NoColor::new(Vec::new());
+        dump_binary(format, &mut &*body, offset, limit, false, &mut out).unwrap();
+        String::from_utf8(out.into_inner()).unwrap()
+    }
+
+    #[test]
+    fn hexdump() {
+        assert_eq!(
+            dump(
+                BinaryDisplay::Hexdump,
+                b"Hello, world!\n\x00\x01\xff",
+                0x20,
+                None
+            ),
+            concat!(
+                "00000020: 4865 6c6c 6f2c 2077 6f72 6c64 210a 0001  Hello, world!...\n",
+                "00000030: ff                                       .\n",
+            )
+        );
+    }
+
+    #[test]
+    fn limit() {
+        assert_eq!(
+            dump(BinaryDisplay::Hexdump, b"\x00\x01\x02\x03", 0, Some(3)),
+            concat!(
+                "00000000: 0001 02                                  ...\n",
+                "[more data not shown, see --binary-limit]\n",
+            )
+        );
+        assert_eq!(
+            dump(BinaryDisplay::Base64, b"\x00\x01\x02", 0, Some(3)),
+            "AAEC\n"
+        );
+    }
+
+    #[test]
+    fn base64_lines() {
+        let body = [0xff; 60];
+        let dumped = dump(BinaryDisplay::Base64, &body, 0, None);
+        let lines: Vec<_> = dumped.lines().collect();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0].len(), 76);
+        assert_eq!(lines[1], "////");
+    }
+}
@@ -0,0 +1,102 @@
+//! Rendering CSV responses as aligned tables.
+
+use unicode_width::UnicodeWidthStr;
+
+/// Split CSV text into rows of fields, or return `None` if a quoted field
+/// is never closed.
+pub fn parse_csv(text: &str) -> Option<Vec<Vec<String>>> {
+    let mut rows = Vec::new();
+    let mut row = Vec::new();
+    let mut field = String::new();
+    let mut chars = text.chars().peekable();
+    let mut quoted = false;
+    while let Some(c) = chars.next() {
+        match (quoted, c) {
+            (true, '"') if chars.peek() == Some(&'"') => {
+                chars.next();
+                field.push('"');
+            }
+            (true, '"') => quoted = false,
+            (true, c) => field.push(c),
+            (false, '"') if field.is_empty() => quoted = true,
+            (false, ',') => row.push(std::mem::take(&mut field)),
+            (false, '\r') if chars.peek() == Some(&'\n') => (),
+            (false, '\n') => {
+                row.push(std::mem::take(&mut field));
+                rows.push(std::mem::take(&mut row));
+            }
+            (false, c) => field.push(c),
+        }
+    }
+    if quoted {
+        return None;
+    }
+    if !field.is_empty() || !row.is_empty() {
+        row.push(field);
+        rows.push(row);
+    }
+    Some(rows)
+}
+
+/// Lay out rows as columns separated by two spaces. Line breaks inside fields
+/// are replaced by spaces so that each row stays on one line.
+pub fn format_table(rows: &[Vec<String>]) -> Vec<String> {
+    let rows: Vec<Vec<String>> = rows
+        .iter()
+        .map(|row| {
+            row.iter()
+                .map(|field| field.replace("\r\n", " ").replace(['\r', '\n'], " "))
+                .collect()
+        })
+        .collect();
+
+    let mut widths: Vec<usize> = Vec::new();
+    for row in &rows {
+        for (index, field) in row.iter().enumerate() {
+            let width = field.width();
+            match widths.get_mut(index) {
+                Some(max) => *max = (*max).max(width),
+                None => widths.push(width),
+            }
+        }
+    }
+
+    rows.iter()
+        .map(|row| {
+            let mut line = String::new();
+            for (index, field) in row.iter().enumerate() {
+                if index > 0 {
+                    line.push_str("  ");
+                }
+                line.push_str(field);
+                if index + 1 < row.len() {
+                    line.extend(std::iter::repeat_n(' ', widths[index] - field.width()));
+                }
+            }
+            line
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse() {
+        assert_eq!(
+            parse_csv("a,\"b,\"\"c\"\"\"\r\n1,\"two\nlines\"\n,").unwrap(),
+            [vec!["a", "b,\"c\""], vec!["1", "two\nlines"], vec!["", ""]]
+        );
+        assert_eq!(parse_csv("a,\"b\n"), None);
+    }
+
+    #[test]
+    fn table() {
+        let rows = parse_csv("name,size\nxh,5\nhttpie,10\n").unwrap();
+        assert_eq!(
+            format_table(&rows),
+            ["name    size", "xh      5", "httpie  10"]
+        );
+    }
+}
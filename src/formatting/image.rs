@@ -0,0 +1,262 @@
+//! Showing image bodies in the terminal.
+//!
+//! Terminals that speak the kitty or iTerm2 graphics protocol get the image
+//! itself, everything else gets a note with its format and dimensions. Sixel
+//! isn't supported: it needs the image decoded to pixels, and the only way to
+//! tell whether a terminal can show it is to query the terminal.
+
+use std::env;
+use std::io::{self, Write};
+
+use base64::{Engine as _, engine::general_purpose::STANDARD};
+use imagesize::ImageType;
+use indicatif::HumanBytes;
+
+/// The largest an inline image is shown, in terminal cells.
+const MAX_COLUMNS: u64 = 80;
+const MAX_ROWS: u64 = 24;
+
+/// How much of the body is read to find the format and dimensions. Those are
+/// usually near the start, but JPEG metadata can push them back a bit.
+pub const HEADER_SIZE: u64 = 256 * 1024;
+
+/// The largest image that's shown inline. Anything bigger only gets a note,
+/// so that it doesn't have to be held in memory.
+pub const MAX_INLINE_SIZE: u64 = 10 * 1024 * 1024;
+
+/// A typical terminal cell size in pixels, to guess how many cells an image
+/// covers at its natural size.
+const CELL_WIDTH: u64 = 8;
+const CELL_HEIGHT: u64 = 16;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImageProtocol {
+    Kitty,
+    Iterm2,
+}
+
+pub(crate) fn detect_protocol() -> Option<ImageProtocol> {
+    let var = |name| env::var(name).unwrap_or_default();
+    // Escape sequences would have to be wrapped to get through a multiplexer
+    if env::var_os("TMUX").is_some() || env::var_os("STY").is_some() {
+        return None;
+    }
+    if var("TERM") == "xterm-kitty"
+        || env::var_os("KITTY_WINDOW_ID").is_some()
+        || var("TERM_PROGRAM") == "ghostty"
+    {
+        Some(ImageProtocol::Kitty)
+    } else if matches!(var("TERM_PROGRAM").as_str(), "iTerm.app" | "WezTerm")
+        || var("LC_TERMINAL") == "iTerm2"
+    {
+        Some(ImageProtocol::Iterm2)
+    } else {
+        None
+    }
+}
+
+pub struct ImageInfo {
+    kind: ImageType,
+    width: u64,
+    height: u64,
+}
+
+impl ImageInfo {
+    /// Read the format and dimensions from the image's header, if it's an
+    /// image we know about.
+    pub fn read(body: &[u8]) -> Option<Self> {
+        let kind = imagesize::image_type(body).ok()?;
+        let size = imagesize::blob_size(body).ok()?;
+        Some(ImageInfo {
+            kind,
+            width: size.width as u64,
+            height: size.height as u64,
+        })
+    }
+
+    pub fn can_show(&self, protocol: ImageProtocol) -> bool {
+        match protocol {
+            // Kitty only takes PNG, anything else has to be sent as pixels
+            ImageProtocol::Kitty => self.kind == ImageType::Png,
+            ImageProtocol::Iterm2 => matches!(
+                self.kind,
+                ImageType::Png
+                    | ImageType::Jpeg
+                    | ImageType::Gif
+                    | ImageType::Webp
+                    | ImageType::Bmp
+                    | ImageType::Tiff
+                    | ImageType::Ico
+                    | ImageType::Heif(_)
+            ),
+        }
+    }
+
+    /// A note like the one for binary data, e.g. "PNG image, 640x480, 12.5 KiB".
+    pub fn note(&self, length: u64) -> String {
+        let text = format!(
+            "NOTE: {} image, {}x{}, {}",
+            format_name(&self.kind),
+            self.width,
+            self.height,
+            HumanBytes(length)
+        );
+        let border = format!("+{}+\n", "-".repeat(text.len() + 2));
+        format!("{border}| {text} |\n{border}\n")
+    }
+
+    /// Scale the image down to fit in the size cap. Only one side is given,
+    /// the terminal works out the other one from the aspect ratio.
+    fn fit(&self) -> Option<Fit> {
+        let columns = self.width.div_ceil(CELL_WIDTH);
+        let rows = self.height.div_ceil(CELL_HEIGHT);
+        if columns <= MAX_COLUMNS && rows <= MAX_ROWS {
+            None
+        } else if columns * MAX_ROWS >= rows * MAX_COLUMNS {
+            Some(Fit::Columns(MAX_COLUMNS))
+        } else {
+            Some(Fit::Rows(MAX_ROWS))
+        }
+    }
+}
+
+enum Fit {
+    Columns(u64),
+    Rows(u64),
+}
+
+/// Write the escape sequence that shows the image, leaving the cursor on
+/// its last line.
+pub fn write_inline_image(
+    protocol: ImageProtocol,
+    image: &ImageInfo,
+    body: &[u8],
+    out: &mut impl Write,
+) -> io::Result<()> {
+    let encoded = STANDARD.encode(body);
+    match protocol {
+        ImageProtocol::Kitty => {
+            // https://sw.kovidgoyal.net/kitty/graphics-protocol/
+            // Payloads are sent in chunks of at most 4096 bytes, and q=2
+            // stops the terminal from replying on our stdin
+            let mut control = String::from("a=T,f=100,q=2");
+            match image.fit() {
+                Some(Fit::Columns(columns)) => control.push_str(&format!(",c={columns}")),
+                Some(Fit::Rows(rows)) => control.push_str(&format!(",r={rows}")),
+                None => (),
+            }
+            let mut chunks = encoded.as_bytes().chunks(4096).peekable();
+            while let Some(chunk) = chunks.next() {
+                let more = u8::from(chunks.peek().is_some());
+                write!(out, "\x1b_G{control},m={more};")?;
+                out.write_all(chunk)?;
+                out.write_all(b"\x1b\\")?;
+                control.clear();
+                control.push_str("q=2");
+            }
+        }
+        ImageProtocol::Iterm2 => {
+            // https://iterm2.com/documentation-images.html
+            write!(out, "\x1b]1337;File=inline=1;size={}", body.len())?;
+            match image.fit() {
+                Some(Fit::Columns(columns)) => write!(out, ";width={columns}")?,
+                Some(Fit::Rows(rows)) => write!(out, ";height={rows}")?,
+                None => (),
+            }
+            write!(out, ":{encoded}\x07")?;
+        }
+    }
+    Ok(())
+}
+
+fn format_name(kind: &ImageType) -> &'static str {
+    match kind {
+        ImageType::Aseprite => "Aseprite",
+        ImageType::Bmp => "BMP",
+        ImageType::Dds => "DDS",
+        ImageType::Exr => "OpenEXR",
+        ImageType::Farbfeld => "Farbfeld",
+        ImageType::Gif => "GIF",
+        ImageType::Hdr => "HDR",
+        ImageType::Heif(_) => "HEIF",
+        ImageType::Ico => "ICO",
+        ImageType::Ilbm => "ILBM",
+        ImageType::Jpeg => "JPEG",
+        ImageType::Jxl => "JPEG XL",
+        ImageType::Ktx2 => "KTX2",
+        ImageType::Png => "PNG",
+        ImageType::Pnm => "PNM",
+        ImageType::Psd => "PSD",
+        ImageType::Qoi => "QOI",
+        ImageType::Tga => "TGA",
+        ImageType::Tiff => "TIFF",
+        ImageType::Vtf => "VTF",
+        ImageType::Webp => "WebP",
+        _ => "Unknown",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn image(kind: ImageType, width: u64, height: u64) -> ImageInfo {
+        ImageInfo {
+            kind,
+            width,
+            height,
+        }
+    }
+
+    #[test]
+    fn note() {
+        assert_eq!(
+            image(ImageType::Png, 640, 480).note(1536),
+            concat!(
+                "+------------------------------------+\n",
+                "| NOTE: PNG image, 640x480, 1.50 KiB |\n",
+                "+------------------------------------+\n",
+                "\n"
+            )
+        );
+    }
+
+    #[test]
+    fn size_cap() {
+        let inline = |image: &ImageInfo| {
+            let mut out = Vec::new();
+            write_inline_image(ImageProtocol::Iterm2, image, b"x", &mut out).unwrap();
+            String::from_utf8(out).unwrap()
+        };
+        assert_eq!(
+            inline(&image(ImageType::Png, 64, 64)),
+            "\x1b]1337;File=inline=1;size=1:eA==\x07"
+        );
+        assert_eq!(
+            inline(&image(ImageType::Png, 4000, 1000)),
+            "\x1b]1337;File=inline=1;size=1;width=80:eA==\x07"
+        );
+        assert_eq!(
+            inline(&image(ImageType::Png, 1000, 4000)),
+            "\x1b]1337;File=inline=1;size=1;height=24:eA==\x07"
+        );
+    }
+
+    #[test]
+    fn kitty_chunks() {
+        let body = vec![0; 4000];
+        let mut out = Vec::new();
+        write_inline_image(
+            ImageProtocol::Kitty,
+            &image(ImageType::Png, 1, 1),
+            &body,
+            &mut out,
+        )
+        .unwrap();
+        let out = String::from_utf8(out).unwrap();
+        let chunks: Vec<_> = out.split("\x1b\\").filter(|s| !s.is_empty()).collect();
+        assert_eq!(chunks.len(), 2);
+        assert!(chunks[0].starts_with("\x1b_Ga=T,f=100,q=2,m=1;"));
+        assert!(chunks[1].starts_with("\x1b_Gq=2,m=0;"));
+    }
+}
@@ -5,6 +5,8 @@ use std::{
 
 use quick_xml::events::Event;
 use quick_xml::{Reader, Writer};
+use serde::Serialize;
+use serde_json::Value;
 use syntect::dumps::from_binary;
 use syntect::easy::HighlightLines;
 use syntect::highlighting::ThemeSet;
@@ -14,15 +16,182 @@ use termcolor::WriteColor;
 
 use crate::{buffer::Buffer, cli::Theme};
 
+pub(crate) mod binary;
+pub(crate) mod csv;
 pub(crate) mod headers;
+pub(crate) mod image;
 pub(crate) mod palette;
+pub(crate) mod yaml;
 
-pub fn get_json_formatter(indent_level: usize) -> jsonxf::Formatter {
+pub fn get_json_formatter(indent_level: usize, sort_keys: bool) -> JsonFormatter {
+    if sort_keys {
+        return JsonFormatter::Sorted(SortedJson {
+            indent: " ".repeat(indent_level),
+            ..SortedJson::default()
+        });
+    }
     let mut fmt = jsonxf::Formatter::pretty_printer();
     fmt.indent = " ".repeat(indent_level);
     fmt.record_separator = String::from("\n\n");
     fmt.eager_record_separators = true;
-    fmt
+    JsonFormatter::Streaming(fmt)
+}
+
+/// Formats a stream of JSON values as it arrives.
+pub enum JsonFormatter {
+    Streaming(jsonxf::Formatter),
+    Sorted(SortedJson),
+}
+
+/// Sorting keys needs every key of an object, so each value is held back
+/// until it's complete. Invalid JSON is passed through from there on.
+///
+/// Where values end is tracked as the bytes arrive, so that each value only
+/// gets parsed once it's complete.
+#[derive(Default)]
+pub struct SortedJson {
+    indent: String,
+    pending: Vec<u8>,
+    /// How much of `pending` has been scanned.
+    scanned: usize,
+    /// Where the last complete top-level value in `pending` ends.
+    complete: usize,
+    depth: usize,
+    in_string: bool,
+    escaped: bool,
+    in_scalar: bool,
+    passthrough: bool,
+}
+
+impl JsonFormatter {
+    pub fn format_buf(&mut self, buf: &[u8], out: &mut impl Write) -> io::Result<()> {
+        match self {
+            JsonFormatter::Streaming(fmt) => fmt.format_buf(buf, out),
+            JsonFormatter::Sorted(sorted) => sorted.format_buf(buf, out),
+        }
+    }
+
+    /// Write out anything that was held back, e.g. a truncated value.
+    pub fn finish(&mut self, out: &mut impl Write) -> io::Result<()> {
+        if let JsonFormatter::Sorted(sorted) = self {
+            if !sorted.in_string && sorted.depth == 0 {
+                // A number at the very end has nothing after it to end it
+                sorted.complete = sorted.pending.len();
+                sorted.write_complete(out)?;
+            }
+            out.write_all(&sorted.pending)?;
+            sorted.pending.clear();
+        }
+        Ok(())
+    }
+}
+
+impl SortedJson {
+    fn format_buf(&mut self, buf: &[u8], out: &mut impl Write) -> io::Result<()> {
+        if self.passthrough {
+            return out.write_all(buf);
+        }
+        self.pending.extend_from_slice(buf);
+        self.scan();
+        self.write_complete(out)
+    }
+
+    /// Track strings and nesting in the new bytes to find where values end.
+    fn scan(&mut self) {
+        for (pos, &byte) in self.pending.iter().enumerate().skip(self.scanned) {
+            if self.in_string {
+                if self.escaped {
+                    self.escaped = false;
+                } else if byte == b'\\' {
+                    self.escaped = true;
+                } else if byte == b'"' {
+                    self.in_string = false;
+                    if self.depth == 0 {
+                        self.complete = pos + 1;
+                    }
+                }
+                continue;
+            }
+            if self.in_scalar {
+                if !byte.is_ascii_whitespace() && !b"{}[],:\"".contains(&byte) {
+                    continue;
+                }
+                self.in_scalar = false;
+                self.complete = pos;
+            }
+            match byte {
+                b'"' => self.in_string = true,
+                b'{' | b'[' => self.depth += 1,
+                b'}' | b']' => {
+                    self.depth = self.depth.saturating_sub(1);
+                    if self.depth == 0 {
+                        self.complete = pos + 1;
+                    }
+                }
+                _ if byte.is_ascii_whitespace() => (),
+                // Anything else outside of a value is a number, a literal, or
+                // invalid JSON for the parser to reject
+                _ if self.depth == 0 => self.in_scalar = true,
+                _ => (),
+            }
+        }
+        self.scanned = self.pending.len();
+    }
+
+    /// Sort and write the values that are complete.
+    fn write_complete(&mut self, out: &mut impl Write) -> io::Result<()> {
+        if self.complete == 0 {
+            return Ok(());
+        }
+        let mut values = serde_json::Deserializer::from_slice(&self.pending[..self.complete])
+            .into_iter::<Value>();
+        let mut consumed = 0;
+        loop {
+            match values.next() {
+                Some(Ok(mut value)) => {
+                    consumed = values.byte_offset();
+                    sort_json_keys(&mut value);
+                    write_json_value(&self.indent, &value, &mut *out)?;
+                    out.write_all(b"\n\n")?;
+                }
+                Some(Err(_)) => {
+                    self.passthrough = true;
+                    break;
+                }
+                None => {
+                    consumed = values.byte_offset();
+                    break;
+                }
+            }
+        }
+        self.pending.drain(..consumed);
+        self.scanned -= consumed;
+        self.complete = 0;
+        if self.passthrough {
+            out.write_all(&self.pending)?;
+            self.pending.clear();
+        }
+        Ok(())
+    }
+}
+
+/// Sort the keys of all objects in a JSON value, recursively.
+pub fn sort_json_keys(value: &mut Value) {
+    match value {
+        Value::Object(map) => {
+            map.sort_keys();
+            map.values_mut().for_each(sort_json_keys);
+        }
+        Value::Array(items) => items.iter_mut().for_each(sort_json_keys),
+        _ => (),
+    }
+}
+
+fn write_json_value(indent: &str, value: &Value, write: impl Write) -> io::Result<()> {
+    let formatter = serde_json::ser::PrettyFormatter::with_indent(indent.as_bytes());
+    let mut serializer = serde_json::Serializer::with_formatter(write, formatter);
+    value.serialize(&mut serializer)?;
+    Ok(())
 }
 
 /// Pretty-print an XML document. Whitespace-only text nodes (typically existing
@@ -45,15 +214,52 @@ pub fn format_xml(indent: usize, text: &str) -> io::Result<Vec<u8>> {
 ///
 /// Note that if parsing fails this function will stop midway through and return an error.
 /// It should only be used with known-valid JSON.
-pub fn serde_json_format(indent_level: usize, text: &str, write: impl Write) -> io::Result<()> {
+pub fn serde_json_format(
+    indent_level: usize,
+    sort_keys: bool,
+    text: &str,
+    write: impl Write,
+) -> io::Result<()> {
     let indent = " ".repeat(indent_level);
+    if sort_keys {
+        let mut value: Value = serde_json::from_str(text)?;
+        sort_json_keys(&mut value);
+        return write_json_value(&indent, &value, write);
+    }
     let formatter = serde_json::ser::PrettyFormatter::with_indent(indent.as_bytes());
+    let write = KeepWriteError {
+        inner: write,
+        error: None,
+    };
     let mut serializer = serde_json::Serializer::with_formatter(write, formatter);
     let mut deserializer = serde_json::Deserializer::from_str(text);
-    serde_transcode::transcode(&mut deserializer, &mut serializer)?;
+    if let Err(err) = serde_transcode::transcode(&mut deserializer, &mut serializer) {
+        return Err(serializer.into_inner().error.unwrap_or_else(|| err.into()));
+    }
     Ok(())
 }
 
+/// serde_transcode turns write errors into deserialization errors that only
+/// have the message, so this keeps the original.
+struct KeepWriteError<W> {
+    inner: W,
+    error: Option<io::Error>,
+}
+
+impl<W: Write> Write for KeepWriteError<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.inner.write(buf).map_err(|err| {
+            let copy = io::Error::new(err.kind(), err.to_string());
+            self.error = Some(err);
+            copy
+        })
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
 pub(crate) static THEMES: LazyLock<ThemeSet> = LazyLock::new(|| {
     from_binary(include_bytes!(concat!(
         env!("OUT_DIR"),
@@ -172,7 +378,49 @@ pub(crate) fn supports_hyperlinks() -> bool {
     *SUPPORTS_HYPERLINKS.get_or_init(supports_hyperlinks::supports_hyperlinks)
 }
 
+pub(crate) fn image_protocol() -> Option<image::ImageProtocol> {
+    static IMAGE_PROTOCOL: OnceLock<Option<image::ImageProtocol>> = OnceLock::new();
+    *IMAGE_PROTOCOL.get_or_init(image::detect_protocol)
+}
+
 pub(crate) fn create_hyperlink(text: &str, url: &str) -> String {
     // https://gist.github.com/egmontkob/eb114294efbcd5adb1944c9f3cb5feda
     format!("\x1B]8;;{url}\x1B\\{text}\x1B]8;;\x1B\\")
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sorted_json_stream() {
+        let mut formatter = get_json_formatter(2, true);
+        let mut out = Vec::new();
+        for chunk in [
+            &b"{\"b\": 1, "[..],
+            b"\"a\": 2} [{\"d\"",
+            b": 1, \"c\": 2}] not json",
+        ] {
+            formatter.format_buf(chunk, &mut out).unwrap();
+        }
+        formatter.finish(&mut out).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "{\n  \"a\": 2,\n  \"b\": 1\n}\n\n[\n  {\n    \"c\": 2,\n    \"d\": 1\n  }\n]\n\n not json"
+        );
+    }
+
+    #[test]
+    fn sorted_json_stream_byte_by_byte() {
+        let mut formatter = get_json_formatter(0, true);
+        let mut out = Vec::new();
+        for byte in br#"{"b": "}\"{", "a": [1, true]} "x" 12 3"# {
+            formatter.format_buf(&[*byte], &mut out).unwrap();
+        }
+        formatter.finish(&mut out).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "{\n\"a\": [\n1,\ntrue\n],\n\"b\": \"}\\\"{\"\n}\n\n\"x\"\n\n12\n\n3\n\n"
+        );
+    }
+}
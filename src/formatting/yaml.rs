@@ -0,0 +1,203 @@
+//! Reformatting YAML documents with a consistent indentation.
+
+use yaml_rust2::{Yaml, YamlLoader};
+
+/// Reindent a YAML document, or return `None` if it can't be done faithfully.
+///
+/// Comments aren't kept by the parser, so documents that seem to contain any
+/// are left alone. Aliases are replaced by a copy of what they refer to.
+pub fn format_yaml(indent: usize, text: &str) -> Option<String> {
+    if has_comments(text) {
+        return None;
+    }
+    let documents = YamlLoader::load_from_str(text).ok()?;
+    if documents.is_empty() {
+        return None;
+    }
+
+    let mut emitter = Emitter {
+        indent: indent.max(1),
+        out: String::new(),
+    };
+    for (index, document) in documents.iter().enumerate() {
+        if index > 0 {
+            emitter.out.push_str("---\n");
+        }
+        match document {
+            Yaml::Hash(hash) if !hash.is_empty() => emitter.node(document, 0, false)?,
+            Yaml::Array(array) if !array.is_empty() => emitter.node(document, 0, false)?,
+            _ => emitter.scalar_line(document, emitter.indent)?,
+        }
+    }
+    Some(emitter.out)
+}
+
+/// May have false positives, e.g. for a " #" inside a string.
+fn has_comments(text: &str) -> bool {
+    text.lines().any(|line| {
+        line.trim_start().starts_with('#') || line.contains(" #") || line.contains("\t#")
+    })
+}
+
+struct Emitter {
+    indent: usize,
+    out: String,
+}
+
+impl Emitter {
+    fn pad(&mut self, column: usize) {
+        self.out.extend(std::iter::repeat_n(' ', column));
+    }
+
+    /// Write a non-empty mapping or sequence whose entries start at `column`.
+    /// If `inline` is set the first entry continues the current line, as in "- key: value".
+    fn node(&mut self, value: &Yaml, column: usize, inline: bool) -> Option<()> {
+        match value {
+            Yaml::Hash(hash) => {
+                for (index, (key, value)) in hash.iter().enumerate() {
+                    if index > 0 || !inline {
+                        self.pad(column);
+                    }
+                    let key = scalar(key)?;
+                    self.out.push_str(&key);
+                    self.out.push(':');
+                    if is_collection(value) {
+                        self.out.push('\n');
+                        self.node(value, column + self.indent, false)?;
+                    } else {
+                        self.out.push(' ');
+                        self.scalar_line(value, column + self.indent)?;
+                    }
+                }
+            }
+            Yaml::Array(array) => {
+                for (index, item) in array.iter().enumerate() {
+                    if index > 0 || !inline {
+                        self.pad(column);
+                    }
+                    self.out.push_str("- ");
+                    if is_collection(item) {
+                        self.node(item, column + 2, true)?;
+                    } else {
+                        self.scalar_line(item, column + 2)?;
+                    }
+                }
+            }
+            _ => unreachable!("only collections are nodes"),
+        }
+        Some(())
+    }
+
+    /// Write a scalar and end the line. Multi-line strings become literal
+    /// blocks with their content at `block_column`.
+    fn scalar_line(&mut self, value: &Yaml, block_column: usize) -> Option<()> {
+        if let Yaml::String(text) = value {
+            if is_literal_block(text) {
+                self.out
+                    .push_str(if text.ends_with('\n') { "|\n" } else { "|-\n" });
+                for line in text.strip_suffix('\n').unwrap_or(text).split('\n') {
+                    if !line.is_empty() {
+                        self.pad(block_column);
+                        self.out.push_str(line);
+                    }
+                    self.out.push('\n');
+                }
+                return Some(());
+            }
+        }
+        self.out.push_str(&scalar(value)?);
+        self.out.push('\n');
+        Some(())
+    }
+}
+
+fn is_collection(value: &Yaml) -> bool {
+    match value {
+        Yaml::Hash(hash) => !hash.is_empty(),
+        Yaml::Array(array) => !array.is_empty(),
+        _ => false,
+    }
+}
+
+/// Whether a string can be written as a literal block without an indentation
+/// or chomping indicator.
+fn is_literal_block(text: &str) -> bool {
+    text.contains('\n')
+        && !text.starts_with([' ', '\t', '\n'])
+        && !text.ends_with("\n\n")
+        && !text
+            .chars()
+            .any(|c| c.is_control() && c != '\n' && c != '\t')
+}
+
+/// A scalar that fits on one line, or `None` for values that we don't write.
+fn scalar(value: &Yaml) -> Option<String> {
+    Some(match value {
+        Yaml::Real(real) => real.clone(),
+        Yaml::Integer(integer) => integer.to_string(),
+        Yaml::Boolean(boolean) => boolean.to_string(),
+        Yaml::Null => "null".to_string(),
+        Yaml::String(text) if is_plain(text) => text.clone(),
+        // JSON strings are valid double-quoted YAML strings
+        Yaml::String(text) => serde_json::to_string(text).ok()?,
+        Yaml::Hash(hash) if hash.is_empty() => "{}".to_string(),
+        Yaml::Array(array) if array.is_empty() => "[]".to_string(),
+        Yaml::Hash(_) | Yaml::Array(_) | Yaml::Alias(_) | Yaml::BadValue => return None,
+    })
+}
+
+/// Whether a string can be written without quotes and still read back the same.
+fn is_plain(text: &str) -> bool {
+    let Some(first) = text.chars().next() else {
+        return false;
+    };
+    if text.trim() != text
+        || text.contains(": ")
+        || text.contains(" #")
+        || text.ends_with(':')
+        || text.chars().any(char::is_control)
+        || "#'\"[]{},&*!|>%@`".contains(first)
+    {
+        return false;
+    }
+    if matches!(first, '-' | '?' | ':') && text[1..].chars().next().is_none_or(|c| c == ' ') {
+        return false;
+    }
+    // Rules out strings that would read as another type, like "true" or "1"
+    matches!(
+        YamlLoader::load_from_str(text).as_deref(),
+        Ok([Yaml::String(parsed)]) if parsed == text
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reindent() {
+        let text = "name: xh\nlist:\n- a\n- b: 1\n  c: [1, 2]\nempty: {}\nquoted: 'true'\nmultiline: |\n  one\n  two\n";
+        assert_eq!(
+            format_yaml(4, text).unwrap(),
+            "name: xh\nlist:\n    - a\n    - b: 1\n      c:\n          - 1\n          - 2\nempty: {}\nquoted: \"true\"\nmultiline: |\n    one\n    two\n"
+        );
+    }
+
+    #[test]
+    fn round_trip() {
+        let text = "a: [\"- x\", \"y: z\", \"#c\", \"\", \" pad\", \"1.5\", null, 1.5]\nb: \"line\\n\\n\"\n---\n- - 1\n  - 2\n";
+        let formatted = format_yaml(3, text).unwrap();
+        assert_eq!(
+            YamlLoader::load_from_str(&formatted).unwrap(),
+            YamlLoader::load_from_str(text).unwrap()
+        );
+    }
+
+    #[test]
+    fn left_alone() {
+        assert_eq!(format_yaml(2, "a: 1 # comment\n"), None);
+        assert_eq!(format_yaml(2, "a: &x 1\nb: *x\n").unwrap(), "a: 1\nb: 1\n");
+        assert_eq!(format_yaml(2, "a: [\n"), None);
+        assert_eq!(format_yaml(2, ""), None);
+    }
+}
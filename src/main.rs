@@ -1,9 +1,13 @@
 #![allow(clippy::bool_assert_comparison)]
 mod auth;
+mod batch_download;
 mod buffer;
+mod checksum;
 mod cli;
 mod content_disposition;
+mod cookie_file;
 mod decoder;
+mod diff;
 mod download;
 mod error_reporting;
 mod formatting;
@@ -13,45 +17,58 @@ mod message_signature;
 mod middleware;
 mod nested_json;
 mod netrc;
+mod ntlm;
 mod printer;
+mod protobuf;
 mod redacted;
 mod redirect;
 mod request_items;
+mod serialization;
 mod session;
+mod session_commands;
 mod to_curl;
+mod transfer;
 mod utils;
 
 use std::env;
 use std::fs::File;
 use std::io::{self, IsTerminal, Read, Write as _};
 use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::process::ExitCode;
 use std::str::FromStr;
 use std::sync::Arc;
 
 use anyhow::{Context, Result, anyhow};
-use cookie_store::{CookieStore, RawCookie};
+use cookie_store::{Cookie, CookieStore, RawCookie};
 use flate2::write::ZlibEncoder;
 use hyper::header::CONTENT_ENCODING;
 use redirect::RedirectFollower;
-use reqwest::blocking::{Body as ReqwestBody, Client};
+use reqwest::blocking::{Body as ReqwestBody, Client, Request, RequestBuilder, Response};
 use reqwest::header::{
-    ACCEPT, ACCEPT_ENCODING, CONNECTION, CONTENT_TYPE, COOKIE, HeaderValue, RANGE, USER_AGENT,
+    ACCEPT, ACCEPT_ENCODING, AUTHORIZATION, CONNECTION, CONTENT_TYPE, COOKIE, HeaderValue, RANGE,
+    USER_AGENT,
 };
 use reqwest::tls;
-use url::Host;
+use url::{Host, Url};
 use utils::reason_phrase;
 
-use crate::auth::{Auth, DigestAuthMiddleware};
+use crate::auth::{Auth, ChallengeAuthMiddleware};
+use crate::batch_download::BatchOptions;
 use crate::buffer::Buffer;
-use crate::cli::{Cli, FormatOptions, HttpVersion, Print, Proxy, Verify};
-use crate::download::{download_file, get_file_size};
-use crate::middleware::ClientWithMiddleware;
+use crate::cli::{
+    AuthType, ByteSize, Cli, FormatOptions, HttpVersion, Print, Proxy, ProxyAuthType, SessionsCli,
+    Verify,
+};
+use crate::diff::{DiffTarget, Snapshot};
+use crate::download::{Retry, Segmented, download_file, get_file_size, segment_state_path};
+use crate::middleware::{ClientWithMiddleware, NoPrinter};
 use crate::printer::Printer;
+use crate::protobuf::{PROTOBUF_ACCEPT, PROTOBUF_CONTENT_TYPE};
 use crate::request_items::{Body, FORM_CONTENT_TYPE, JSON_ACCEPT, JSON_CONTENT_TYPE};
 use crate::session::Session;
-use crate::utils::{test_mode, test_pretend_term, url_with_query};
+use crate::transfer::TransferLimits;
+use crate::utils::{clone_request, test_mode, test_pretend_term, url_with_query};
 
 #[cfg(not(any(feature = "native-tls", feature = "rustls")))]
 compile_error!("Either native-tls or rustls feature must be enabled!");
@@ -66,6 +83,16 @@ fn get_user_agent() -> &'static str {
 }
 
 fn main() -> ExitCode {
+    if let Some(sessions) = SessionsCli::parse_if_requested(std::env::args_os()) {
+        return match session_commands::run(sessions.command) {
+            Ok(()) => ExitCode::SUCCESS,
+            Err(err) => {
+                eprintln!("{}: error: {err:?}", sessions.bin_name);
+                error_reporting::exit_code(&err)
+            }
+        };
+    }
+
     let args = Cli::parse();
 
     if args.debug {
@@ -79,9 +106,12 @@ fn main() -> ExitCode {
 
     let native_tls = args.native_tls;
     let bin_name = args.bin_name.clone();
+    let diff = args.diff.is_some();
 
-    match run(args) {
+    match run(args, None) {
         Ok(exit_code) => exit_code,
+        // Quitting the pager early is normal
+        Err(err) if buffer::is_pager_closed(&err) => ExitCode::SUCCESS,
         Err(err) => {
             log::debug!("{err:#?}");
             eprintln!("{bin_name}: error: {err:?}");
@@ -91,12 +121,19 @@ fn main() -> ExitCode {
                 eprintln!("{message}");
             }
 
-            error_reporting::exit_code(&err)
+            if diff {
+                // Like diff(1), 1 means that the responses differ
+                ExitCode::from(2)
+            } else {
+                error_reporting::exit_code(&err)
+            }
         }
     }
 }
 
-fn run(args: Cli) -> Result<ExitCode> {
+/// Send the request and print the exchange. With `capture`, the response is
+/// stored there instead of printed.
+fn run(mut args: Cli, capture: Option<&mut Option<Snapshot>>) -> Result<ExitCode> {
     if let Some(generate) = args.generate {
         generation::generate(&args.bin_name, generate);
         return Ok(ExitCode::SUCCESS);
@@ -107,8 +144,53 @@ fn run(args: Cli) -> Result<ExitCode> {
         return Ok(ExitCode::SUCCESS);
     }
 
+    if let Some(target) = args.diff.take() {
+        return run_diff(args, target);
+    }
+
+    let mut session = match &args.session {
+        Some(name_or_path) => Some(
+            Session::load_session(
+                args.url.clone(),
+                name_or_path.clone(),
+                args.is_session_read_only,
+            )
+            .with_context(|| {
+                format!("couldn't load session {:?}", name_or_path.to_string_lossy())
+            })?,
+        ),
+        None => None,
+    };
+    if let Some(ref s) = session {
+        s.apply_options(&mut args)?;
+    }
+
     let (mut headers, headers_to_unset) = args.request_items.headers()?;
-    let url = url_with_query(args.url, &args.request_items.query()?);
+    let binary_body_format = args.binary_body_format();
+    let proto_pool = match &args.proto_descriptor {
+        Some(path) => Some(protobuf::load_descriptors(path)?),
+        None => None,
+    };
+    let proto_message = |name: &Option<String>| match (&proto_pool, name) {
+        (Some(pool), Some(name)) => protobuf::find_message(pool, name).map(Some),
+        _ => Ok(None),
+    };
+    let proto_request = proto_message(&args.proto_request)?;
+    let proto_response = proto_message(&args.proto_message)?;
+    let mut query = args.request_items.query()?;
+    if let Some(ref s) = session {
+        // Parameters from the URL and the command line take precedence
+        let defaults: Vec<_> = s
+            .query()
+            .filter(|(name, _)| {
+                !query.iter().any(|(arg, _)| arg == name)
+                    && !args.url.query_pairs().any(|(arg, _)| arg == *name)
+            })
+            .map(|(name, value)| (name, value.into()))
+            .collect();
+        query.splice(0..0, defaults);
+    }
+    let url = url_with_query(args.url, &query);
     log::debug!("Complete URL: {url}");
 
     let use_stdin = !(args.ignore_stdin || io::stdin().is_terminal() || test_pretend_term());
@@ -143,11 +225,26 @@ fn run(args: Cli) -> Result<ExitCode> {
     let method = args.method.unwrap_or_else(|| body.pick_method());
     log::debug!("HTTP method: {method}");
 
+    let limits = TransferLimits::new(
+        args.limit_rate.map(|rate| rate.0),
+        args.speed_limit.map(|speed| speed.0),
+        args.speed_time,
+        args.max_filesize.map(|size| size.0),
+    );
+    // A read that hangs has to time out for --speed-time to notice it
+    let timeout = match (
+        args.timeout.and_then(|t| t.as_duration()),
+        limits.stall_timeout(),
+    ) {
+        (Some(timeout), Some(stall_timeout)) => Some(timeout.min(stall_timeout)),
+        (timeout, stall_timeout) => timeout.or(stall_timeout),
+    };
+
     let mut client = Client::builder()
         .http1_title_case_headers()
         .http2_adaptive_window(true)
         .redirect(reqwest::redirect::Policy::none())
-        .timeout(args.timeout.and_then(|t| t.as_duration()))
+        .timeout(timeout)
         .no_gzip()
         .no_deflate()
         .no_brotli();
@@ -275,12 +372,42 @@ fn run(args: Cli) -> Result<ExitCode> {
         );
     }
 
-    for proxy in args.proxy.into_iter().rev() {
-        client = client.proxy(match proxy {
+    // reqwest can only attach credentials and exceptions to explicit proxies,
+    // so if they're needed we take over the environment variables.
+    let proxies_from_env =
+        args.proxy.is_empty() && (args.proxy_auth.is_some() || args.noproxy.is_some());
+    let proxies = if proxies_from_env {
+        Proxy::from_env()
+    } else {
+        args.proxy
+    };
+    let no_proxy = match &args.noproxy {
+        Some(hosts) => reqwest::NoProxy::from_string(hosts),
+        None if proxies_from_env => reqwest::NoProxy::from_env(),
+        None => None,
+    };
+    let mut proxy_auth = None;
+    if let Some(credentials) = &args.proxy_auth {
+        let proxy_host = match proxies.first() {
+            Some(Proxy::Http(url) | Proxy::Https(url) | Proxy::All(url)) => url.host_str(),
+            None => None,
+        };
+        let (username, password) = auth::parse_auth(credentials, proxy_host.unwrap_or("<proxy>"))?;
+        proxy_auth = Some(match args.proxy_auth_type.unwrap_or_default() {
+            ProxyAuthType::Basic => Auth::Basic(username, password),
+            ProxyAuthType::Digest => Auth::Digest(username, password.unwrap_or_default()),
+        });
+    }
+    for proxy in proxies.into_iter().rev() {
+        let mut proxy = match proxy {
             Proxy::Http(url) => reqwest::Proxy::http(url),
             Proxy::Https(url) => reqwest::Proxy::https(url),
             Proxy::All(url) => reqwest::Proxy::all(url),
-        }?);
+        }?;
+        if let Some(Auth::Basic(username, password)) = &proxy_auth {
+            proxy = proxy.basic_auth(username, password.as_deref().unwrap_or(""));
+        }
+        client = client.proxy(proxy.no_proxy(no_proxy.clone()));
     }
 
     client = match args.http_version {
@@ -393,16 +520,6 @@ fn run(args: Cli) -> Result<ExitCode> {
     log::trace!("{client:#?}");
     let client = client.build()?;
 
-    let mut session = match &args.session {
-        Some(name_or_path) => Some(
-            Session::load_session(url.clone(), name_or_path.clone(), args.is_session_read_only)
-                .with_context(|| {
-                    format!("couldn't load session {:?}", name_or_path.to_string_lossy())
-                })?,
-        ),
-        None => None,
-    };
-
     if let Some(ref mut s) = session {
         auth = s.auth()?;
 
@@ -424,6 +541,32 @@ fn run(args: Cli) -> Result<ExitCode> {
         }
     }
 
+    // Cookie files are kept apart from the session: cookies from one are
+    // never saved into the other, only cookies set by the server go to both
+    let (session_cookies, file_cookies) = {
+        let mut cookie_jar = cookie_jar.lock().unwrap();
+        let session_cookies: Vec<Cookie<'static>> = cookie_jar.iter_any().cloned().collect();
+        let mut file_jar = CookieStore::default();
+        if let Some(path) = &args.cookies {
+            cookie_file::load(path, &mut file_jar)?;
+        }
+        if let Some(path) = args.cookie_jar.as_ref().filter(|path| path.exists()) {
+            cookie_file::load(path, &mut file_jar)?;
+        }
+        let file_cookies: Vec<Cookie<'static>> = file_jar.iter_any().cloned().collect();
+        if !file_cookies.is_empty() {
+            *cookie_jar = CookieStore::from_cookies(
+                session_cookies
+                    .iter()
+                    .chain(&file_cookies)
+                    .cloned()
+                    .map(Ok::<_, anyhow::Error>),
+                false,
+            )?;
+        }
+        (session_cookies, file_cookies)
+    };
+
     let mut request = {
         let mut request_builder = client
             .request(method, url.clone())
@@ -459,7 +602,31 @@ fn run(args: Cli) -> Result<ExitCode> {
             Body::Json(body) => {
                 // An empty JSON body would produce null instead of "", so
                 // this is the one kind of body that needs an is_null() check
-                if !body.is_null() {
+                if let Some(message) = &proto_request {
+                    let request_builder = request_builder
+                        .header(ACCEPT, HeaderValue::from_static(PROTOBUF_ACCEPT))
+                        .header(
+                            CONTENT_TYPE,
+                            HeaderValue::from_static(PROTOBUF_CONTENT_TYPE),
+                        );
+                    if body.is_null() {
+                        request_builder
+                    } else {
+                        request_builder.body(protobuf::encode(message, &body)?)
+                    }
+                } else if let Some(format) = binary_body_format {
+                    let request_builder = request_builder
+                        .header(ACCEPT, HeaderValue::from_static(format.accept()))
+                        .header(
+                            CONTENT_TYPE,
+                            HeaderValue::from_static(format.content_type()),
+                        );
+                    if body.is_null() {
+                        request_builder
+                    } else {
+                        request_builder.body(serialization::encode(format, &body)?)
+                    }
+                } else if !body.is_null() {
                     request_builder
                         .header(ACCEPT, HeaderValue::from_static(JSON_ACCEPT))
                         .json(&body)
@@ -477,6 +644,20 @@ fn run(args: Cli) -> Result<ExitCode> {
                 if args.form {
                     request_builder
                         .header(CONTENT_TYPE, HeaderValue::from_static(FORM_CONTENT_TYPE))
+                } else if proto_request.is_some() {
+                    request_builder
+                        .header(ACCEPT, HeaderValue::from_static(PROTOBUF_ACCEPT))
+                        .header(
+                            CONTENT_TYPE,
+                            HeaderValue::from_static(PROTOBUF_CONTENT_TYPE),
+                        )
+                } else if let Some(format) = binary_body_format {
+                    request_builder
+                        .header(ACCEPT, HeaderValue::from_static(format.accept()))
+                        .header(
+                            CONTENT_TYPE,
+                            HeaderValue::from_static(format.content_type()),
+                        )
                 } else {
                     request_builder
                         .header(ACCEPT, HeaderValue::from_static(JSON_ACCEPT))
@@ -524,6 +705,14 @@ fn run(args: Cli) -> Result<ExitCode> {
                 log::warn!(
                     "--continue can't be used with a 'Range:' header. --continue will be disabled."
                 );
+            } else if args.segments.is_some_and(|segments| segments > 1)
+                && args
+                    .output
+                    .as_deref()
+                    .is_some_and(|output| segment_state_path(output).exists())
+            {
+                // The state file tells which parts are missing, so the whole
+                // file is requested first to check that it didn't change
             } else if let Some(file_size) = get_file_size(args.output.as_deref()) {
                 request_builder = request_builder.header(RANGE, format!("bytes={file_size}-"));
                 resume = Some(file_size);
@@ -531,18 +720,29 @@ fn run(args: Cli) -> Result<ExitCode> {
         }
 
         let auth_type = args.auth_type.unwrap_or_default();
-        if let Some(auth_from_arg) = args.auth {
+        if let Some(auth_cmd) = &args.auth_cmd {
+            auth = Some(Auth::from_command(
+                args.auth.as_deref().map(String::as_str),
+                auth_cmd,
+                auth_type,
+            )?);
+            save_auth_in_session = false;
+        } else if let Some(auth_from_arg) = args.auth {
             auth = Some(Auth::from_str(
                 &auth_from_arg,
                 auth_type,
                 url.host_str().unwrap_or("<host>"),
             )?);
-        } else if !args.ignore_netrc {
-            // I don't know if it's possible for host() to return None
-            // But if it does we still want to use the default entry, if there is one
-            let host = url.host().unwrap_or(Host::Domain(""));
-            if let Some(entry) = netrc::find_entry(host) {
-                auth = Auth::from_netrc(auth_type, entry);
+        } else if !args.ignore_netrc && args.input_file.is_none() {
+            // With --input-file the URL is a placeholder, each URL from the
+            // file is looked up separately
+            if let Some(netrc_auth) = netrc_auth(
+                &url,
+                auth_type,
+                args.netrc_file.as_deref(),
+                args.netrc_decrypt_cmd.as_deref(),
+            )? {
+                auth = Some(netrc_auth);
                 save_auth_in_session = false;
             }
         }
@@ -553,13 +753,7 @@ fn run(args: Cli) -> Result<ExitCode> {
                     s.save_auth(auth);
                 }
             }
-            request_builder = match auth {
-                Auth::Basic(username, password) => {
-                    request_builder.basic_auth(username, password.as_ref())
-                }
-                Auth::Bearer(token) => request_builder.bearer_auth(token),
-                Auth::Digest(..) => request_builder,
-            }
+            request_builder = with_auth_header(request_builder, auth);
         }
 
         let mut request = request_builder.headers(headers).build()?;
@@ -593,6 +787,7 @@ fn run(args: Cli) -> Result<ExitCode> {
         if args.m_sig.m_sig_id.is_some()
             || args.m_sig.m_sig_key.is_some()
             || args.m_sig.m_sig_alg.is_some()
+            || args.m_sig.m_sig_scheme.is_some()
             || args.m_sig.has_components()
         {
             return Err(anyhow!(
@@ -607,17 +802,10 @@ fn run(args: Cli) -> Result<ExitCode> {
             ));
         }
 
+        // Batch downloads sign each request once its URL is known
         #[cfg(feature = "http-message-signatures")]
-        if let Some((key_id, key_material)) = args.m_sig.key_pair() {
-            let m_sig_components = args.m_sig.flattened_components();
-            let m_sig_algorithm = args.m_sig.algorithm().map(Into::into);
-            message_signature::sign_request(
-                &mut request,
-                key_id,
-                key_material,
-                (!m_sig_components.is_empty()).then_some(m_sig_components.as_slice()),
-                m_sig_algorithm,
-            )?;
+        if args.input_file.is_none() {
+            message_signature::sign_request_with_args(&mut request, &args.m_sig)?;
         }
 
         request
@@ -629,6 +817,34 @@ fn run(args: Cli) -> Result<ExitCode> {
             .insert(ACCEPT_ENCODING, HeaderValue::from_static("identity"));
     }
 
+    let segmented = match args.segments {
+        Some(segments) if segments > 1 && request.method() == reqwest::Method::GET => {
+            request.try_clone().map(|request| Segmented {
+                segments,
+                client: client.clone(),
+                request,
+                resume: args.resume,
+            })
+        }
+        _ => None,
+    };
+
+    // A manual Range header means the response isn't the file from its start
+    let retry = match args.retry {
+        Some(retries)
+            if retries > 0
+                && request.method() == reqwest::Method::GET
+                && (resume.is_some() || !request.headers().contains_key(RANGE)) =>
+        {
+            request.try_clone().map(|request| Retry {
+                retries,
+                client: client.clone(),
+                request,
+            })
+        }
+        _ => None,
+    };
+
     log::trace!("Built reqwest request");
     // Note: Debug impl is incomplete?
     log::trace!("{request:#?}");
@@ -637,6 +853,8 @@ fn run(args: Cli) -> Result<ExitCode> {
         args.download,
         args.output.as_deref(),
         io::stdout().is_terminal() || test_pretend_term(),
+        args.pager
+            .unwrap_or_else(|| std::env::var_os("XH_PAGER").is_some()),
     )?;
     let is_output_redirected = buffer.is_redirect();
     let print = match args.print {
@@ -653,11 +871,117 @@ fn run(args: Cli) -> Result<ExitCode> {
     };
     let theme = args.style.unwrap_or_default();
     let pretty = args.pretty.unwrap_or_else(|| buffer.guess_pretty());
+
+    let redirect_follower = if args.follow {
+        #[cfg(feature = "http-message-signatures")]
+        {
+            let message_signature = args.m_sig.has_key_pair().then_some(args.m_sig.clone());
+            Some(RedirectFollower::new(
+                args.max_redirects.unwrap_or(10),
+                message_signature,
+            ))
+        }
+        #[cfg(not(feature = "http-message-signatures"))]
+        {
+            Some(RedirectFollower::new(args.max_redirects.unwrap_or(10)))
+        }
+    } else {
+        None
+    };
+
+    if let Some(input_file) = &args.input_file {
+        let urls = batch_download::read_urls(input_file, args.default_scheme.as_deref())?;
+        // The request was built for a placeholder URL, so netrc credentials
+        // and signatures have to be redone for each URL
+        let netrc = match auth {
+            None if !args.ignore_netrc => netrc::Netrc::load(
+                args.netrc_file.as_deref(),
+                args.netrc_decrypt_cmd.as_deref(),
+            )?,
+            _ => None,
+        };
+        // Each entry is only turned into credentials once, so that its
+        // passwordcmd doesn't run again for every URL
+        let mut netrc_entries: Vec<(netrc::Entry, Option<Auth>)> = Vec::new();
+        let mut entry_indexes = Vec::with_capacity(urls.len());
+        for url in &urls {
+            let Some(entry) = netrc
+                .as_ref()
+                .and_then(|netrc| netrc.find_entry(netrc_host(url)))
+            else {
+                entry_indexes.push(None);
+                continue;
+            };
+            let index = match netrc_entries.iter().position(|(known, _)| *known == entry) {
+                Some(index) => index,
+                None => {
+                    let auth_type = args.auth_type.unwrap_or_default();
+                    let netrc_auth = Auth::from_netrc(auth_type, entry.clone())?;
+                    netrc_entries.push((entry, netrc_auth));
+                    netrc_entries.len() - 1
+                }
+            };
+            entry_indexes.push(Some(index));
+        }
+        let mut requests = Vec::with_capacity(urls.len());
+        for (url, index) in urls.into_iter().zip(entry_indexes) {
+            let netrc_auth = index.and_then(|index| netrc_entries[index].1.as_ref());
+            let mut url_request = clone_request(&mut request)?;
+            *url_request.url_mut() = url;
+            if let Some(netrc_auth) = netrc_auth {
+                if !url_request.headers().contains_key(AUTHORIZATION) {
+                    let request_builder = RequestBuilder::from_parts(client.clone(), url_request);
+                    url_request = with_auth_header(request_builder, netrc_auth).build()?;
+                }
+            }
+            #[cfg(feature = "http-message-signatures")]
+            message_signature::sign_request_with_args(&mut url_request, &args.m_sig)?;
+            requests.push((url_request, netrc_auth.or(auth.as_ref())));
+        }
+        let new_client = |auth| {
+            with_middlewares(
+                ClientWithMiddleware::<NoPrinter>::new(&client),
+                redirect_follower.clone(),
+                auth,
+                proxy_auth.as_ref(),
+            )
+        };
+        let options = BatchOptions {
+            output_dir: args.output_dir.as_deref().unwrap_or(Path::new(".")),
+            parallel: args.parallel.map_or(1, usize::from),
+            color: pretty.color(),
+            quiet: args.quiet > 0,
+            limits: &limits,
+        };
+        let exit_code = batch_download::download_all(requests, new_client, &options)?;
+        if let Some(path) = &args.cookie_jar {
+            let cookie_jar = cookie_jar.lock().unwrap();
+            save_cookie_jar(path, &cookie_jar, &session_cookies, &file_cookies)?;
+        }
+        return Ok(exit_code);
+    }
+
     let format_options = args
         .format_options
         .iter()
         .fold(FormatOptions::default(), FormatOptions::merge);
-    let mut printer = Printer::new(pretty, theme, args.stream, buffer, format_options);
+    let mut printer = Printer::new(pretty, theme, args.stream, buffer, format_options)
+        .with_transfer_limits(limits.clone())
+        .with_protobuf_messages(proto_request, proto_response)
+        .with_binary_display(
+            args.binary.unwrap_or_default(),
+            match args.binary_limit {
+                Some(ByteSize(0)) => None,
+                Some(ByteSize(limit)) => Some(limit),
+                None => Some(4 * 1024),
+            },
+        )
+        .with_body_display_limits(
+            args.max_body_display
+                .map(|ByteSize(limit)| limit)
+                .filter(|&limit| limit > 0),
+            args.head_lines.filter(|&lines| lines > 0),
+        );
 
     let response_charset = args.response_charset;
     let response_mime = args.response_mime.as_deref();
@@ -698,24 +1022,12 @@ fn run(args: Cli) -> Result<ExitCode> {
                     Ok(())
                 });
             }
-            if args.follow {
-                #[cfg(feature = "http-message-signatures")]
-                {
-                    let message_signature = args.m_sig.has_key_pair().then_some(args.m_sig.clone());
-
-                    client = client.with(RedirectFollower::new(
-                        args.max_redirects.unwrap_or(10),
-                        message_signature,
-                    ));
-                }
-                #[cfg(not(feature = "http-message-signatures"))]
-                {
-                    client = client.with(RedirectFollower::new(args.max_redirects.unwrap_or(10)));
-                }
-            }
-            if let Some(Auth::Digest(username, password)) = &auth {
-                client = client.with(DigestAuthMiddleware::new(username, password));
-            }
+            client = with_middlewares(
+                client,
+                redirect_follower,
+                auth.as_ref(),
+                proxy_auth.as_ref(),
+            );
             client.execute(request)?
         };
 
@@ -752,12 +1064,19 @@ fn run(args: Cli) -> Result<ExitCode> {
                 download_file(
                     response,
                     args.output,
+                    args.output_dir.as_deref(),
                     &url,
                     resume,
                     pretty.color(),
                     args.quiet > 0,
+                    segmented,
+                    retry,
+                    args.checksum,
+                    &limits,
                 )?;
             }
+        } else if let Some(capture) = capture {
+            *capture = Some(Snapshot::from_response(&mut response, &limits)?);
         } else {
             if print.response_body {
                 printer.print_response_body(&mut response, response_charset, response_mime)?;
@@ -773,14 +1092,164 @@ fn run(args: Cli) -> Result<ExitCode> {
 
     if let Some(ref mut s) = session {
         let cookie_jar = cookie_jar.lock().unwrap();
-        s.save_cookies(cookie_jar.iter_unexpired());
+        s.save_cookies(
+            cookie_jar.iter_unexpired().filter(|cookie| {
+                !file_cookies.contains(cookie) || session_cookies.contains(cookie)
+            }),
+        );
         s.persist()
             .with_context(|| format!("couldn't persist session {}", s.path.display()))?;
     }
 
+    if let Some(path) = &args.cookie_jar {
+        let cookie_jar = cookie_jar.lock().unwrap();
+        save_cookie_jar(path, &cookie_jar, &session_cookies, &file_cookies)?;
+    }
+
     Ok(failure_code.unwrap_or(ExitCode::SUCCESS))
 }
 
+/// Save the cookies to the `--cookie-jar` file, leaving out those that only
+/// came from the session.
+fn save_cookie_jar(
+    path: &Path,
+    cookie_jar: &CookieStore,
+    session_cookies: &[Cookie<'static>],
+    file_cookies: &[Cookie<'static>],
+) -> Result<()> {
+    cookie_file::save(
+        path,
+        cookie_jar
+            .iter_unexpired()
+            .filter(|cookie| !session_cookies.contains(cookie) || file_cookies.contains(cookie)),
+    )
+}
+
+/// Send the request twice, or once to compare with a file, and print how the
+/// responses differ.
+fn run_diff(args: Cli, target: DiffTarget) -> Result<ExitCode> {
+    let fetch = |mut args: Cli| -> Result<Snapshot> {
+        args.print = Some(Print {
+            request_headers: false,
+            request_body: false,
+            response_headers: false,
+            response_body: false,
+            response_meta: false,
+        });
+        args.all = false;
+        args.check_status = Some(false);
+        args.output = None;
+        args.pager = Some(false);
+        args.download = false;
+        // Comparing shouldn't change the session
+        args.is_session_read_only = true;
+        let mut snapshot = None;
+        run(args, Some(&mut snapshot))?;
+        snapshot.context("No response to compare")
+    };
+
+    enum Right {
+        Request(Box<Cli>),
+        Saved(Snapshot),
+    }
+    let left_label = args.url.to_string();
+    let (right, right_label) = match target {
+        DiffTarget::Url(url) => {
+            let label = url.to_string();
+            let mut right_args = args.clone();
+            right_args.url = url;
+            (Right::Request(Box::new(right_args)), label)
+        }
+        DiffTarget::Session(session) => {
+            let label = format!("{left_label} (session {})", session.to_string_lossy());
+            let mut right_args = args.clone();
+            right_args.session = Some(session);
+            (Right::Request(Box::new(right_args)), label)
+        }
+        DiffTarget::File(path) => (
+            Right::Saved(Snapshot::load(&path)?),
+            path.display().to_string(),
+        ),
+    };
+    let mut buffer = Buffer::new(
+        false,
+        args.output.as_deref(),
+        io::stdout().is_terminal() || test_pretend_term(),
+        args.pager
+            .unwrap_or_else(|| std::env::var_os("XH_PAGER").is_some()),
+    )?;
+    let color = args.pretty.unwrap_or_else(|| buffer.guess_pretty()).color();
+
+    let left = fetch(args)?;
+    let right = match right {
+        Right::Request(right_args) => fetch(*right_args)?,
+        Right::Saved(snapshot) => snapshot,
+    };
+    let differ = diff::print_diff(
+        &left,
+        &right,
+        (&left_label, &right_label),
+        color,
+        &mut buffer,
+    )?;
+    buffer.flush()?;
+    Ok(if differ {
+        ExitCode::from(1)
+    } else {
+        ExitCode::SUCCESS
+    })
+}
+
+/// Look up credentials for the URL's host in the netrc file.
+fn netrc_auth(
+    url: &Url,
+    auth_type: AuthType,
+    netrc_file: Option<&Path>,
+    decrypt_cmd: Option<&str>,
+) -> Result<Option<Auth>> {
+    match netrc::find_entry(netrc_host(url), netrc_file, decrypt_cmd)? {
+        Some(entry) => Auth::from_netrc(auth_type, entry),
+        None => Ok(None),
+    }
+}
+
+fn netrc_host(url: &Url) -> Host<&str> {
+    // I don't know if it's possible for host() to return None
+    // But if it does we still want to use the default entry, if there is one
+    url.host().unwrap_or(Host::Domain(""))
+}
+
+/// Add the Authorization header for schemes that don't need a challenge.
+fn with_auth_header(request_builder: RequestBuilder, auth: &Auth) -> RequestBuilder {
+    match auth {
+        Auth::Basic(username, password) => request_builder.basic_auth(username, password.as_ref()),
+        Auth::Bearer(token) => request_builder.bearer_auth(token),
+        Auth::Digest(..) | Auth::Ntlm(..) | Auth::Any(..) => request_builder,
+    }
+}
+
+/// Add the middlewares that follow redirects and answer auth challenges.
+fn with_middlewares<'a, T>(
+    mut client: ClientWithMiddleware<'a, T>,
+    redirect_follower: Option<RedirectFollower>,
+    auth: Option<&'a Auth>,
+    proxy_auth: Option<&'a Auth>,
+) -> ClientWithMiddleware<'a, T>
+where
+    T: FnMut(&mut Response, &mut Request) -> Result<()> + 'a,
+{
+    if let Some(redirect_follower) = redirect_follower {
+        client = client.with(redirect_follower);
+    }
+    if let Some(middleware) = auth.and_then(ChallengeAuthMiddleware::new) {
+        client = client.with(middleware);
+    }
+    if let Some(middleware) = proxy_auth.and_then(ChallengeAuthMiddleware::for_proxy) {
+        client = client.with(middleware);
+    }
+    client
+}
+
 /// Configure backtraces for standard panics and anyhow using `$RUST_BACKTRACE`.
 ///
 /// Note: they only check the environment variable once, so this won't take effect if
@@ -1,4 +1,5 @@
 use std::collections::HashSet;
+use std::time::{SystemTime, UNIX_EPOCH};
 
 use anyhow::{Context, Result, anyhow, bail};
 use base64::{Engine as _, engine::general_purpose::STANDARD};
@@ -8,9 +9,29 @@ use httpsig_hyper::prelude::{
 };
 use hyper::http;
 use reqwest::blocking::{Body as ReqwestBody, Request};
-use reqwest::header::{HeaderName, HeaderValue};
+use reqwest::header::{DATE, HOST, HeaderName, HeaderValue};
 use sha2::{Digest, Sha256};
 
+use crate::cli::{MessageSignature, MessageSignatureScheme};
+
+/// Signs a request using the key, components and scheme passed on the command line.
+pub fn sign_request_with_args(request: &mut Request, args: &MessageSignature) -> Result<()> {
+    let Some((key_id, key_material)) = args.key_pair() else {
+        return Ok(());
+    };
+    let components = args.flattened_components();
+    let components = (!components.is_empty()).then_some(components.as_slice());
+    let algorithm = args.algorithm().map(Into::into);
+    match args.scheme() {
+        MessageSignatureScheme::Rfc9421 => {
+            sign_request(request, key_id, key_material, components, algorithm)
+        }
+        MessageSignatureScheme::Cavage => {
+            sign_request_cavage(request, key_id, key_material, components, algorithm)
+        }
+    }
+}
+
 pub fn sign_request(
     request: &mut Request,
     key_id: &str,
@@ -64,6 +85,210 @@ pub fn sign_request(
     Ok(())
 }
 
+/// Signs a request following draft-cavage-http-signatures-12.
+///
+/// Unlike RFC 9421, the signature and all of its parameters end up in a single
+/// header, e.g.
+/// `Signature: keyId="k",algorithm="hmac-sha256",headers="(request-target) host date",signature="..."`
+pub fn sign_request_cavage(
+    request: &mut Request,
+    key_id: &str,
+    key_material: &str,
+    components: Option<&[String]>,
+    algorithm_override: Option<AlgorithmName>,
+) -> Result<()> {
+    let key = parse_key_input(key_material)?;
+
+    let (signing_key, algorithm) = build_signing_key(&key, key_id, algorithm_override)?;
+
+    let headers = resolve_cavage_headers(request, components)?;
+    // The draft only defines (created) for hs2019, verifiers reject it otherwise
+    let algorithm_name = cavage_algorithm_name(&algorithm);
+    if algorithm_name != "hs2019" && headers.iter().any(|h| h == "(created)") {
+        bail!(
+            "message-signature: (created) can't be used with {}, only with hs2019",
+            algorithm_name
+        );
+    }
+    ensure_date(request, &headers)?;
+    ensure_digest(request, &headers)?;
+
+    let created = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .context("message-signature: System clock is set before the UNIX epoch")?
+        .as_secs();
+    let signing_string = build_cavage_signing_string(request, &headers, created)?;
+    let signature = signing_key
+        .sign(signing_string.as_bytes())
+        .map_err(|e| anyhow!("message-signature: Failed to sign request: {:?}", e))?;
+
+    let mut value = format!("keyId=\"{}\",algorithm=\"{}\"", key_id, algorithm_name);
+    if headers.iter().any(|h| h == "(created)") {
+        value.push_str(&format!(",created={created}"));
+    }
+    value.push_str(&format!(
+        ",headers=\"{}\",signature=\"{}\"",
+        headers.join(" "),
+        STANDARD.encode(signature)
+    ));
+
+    request.headers_mut().insert(
+        HeaderName::from_static("signature"),
+        HeaderValue::from_str(&value)
+            .context("message-signature: Invalid characters in Signature header")?,
+    );
+    Ok(())
+}
+
+/// Resolves the list of headers covered by a draft-cavage signature.
+///
+/// Components are plain (lowercase) header names plus the `(request-target)` and
+/// `(created)` pseudo-headers. RFC 9421 derived components have no equivalent and
+/// are rejected. Like content-digest in RFC 9421 mode, `digest` is only included if
+/// the request has a body.
+fn resolve_cavage_headers(request: &Request, components: Option<&[String]>) -> Result<Vec<String>> {
+    let source = if let Some(c) = components {
+        c
+    } else {
+        &[
+            "(request-target)".to_string(),
+            "host".to_string(),
+            "date".to_string(),
+        ] as &[String]
+    };
+
+    let mut resolved = Vec::new();
+    let mut seen = HashSet::new();
+    for component in source {
+        if component.starts_with('@') {
+            bail!(
+                "message-signature: Derived component {} is not supported by the cavage scheme. Use (request-target) instead",
+                component
+            );
+        }
+        if component.starts_with('(') && component != "(request-target)" && component != "(created)"
+        {
+            bail!(
+                "message-signature: Unsupported pseudo-header {} for the cavage scheme",
+                component
+            );
+        }
+        if component == "digest" && request.body().is_none() {
+            continue;
+        }
+        HeaderName::from_bytes(component.trim_matches(|c| c == '(' || c == ')').as_bytes())
+            .with_context(|| format!("message-signature: Invalid component: {}", component))?;
+        if !seen.insert(component.as_str()) {
+            bail!(
+                "message-signature: Duplicate covered component identifier: {}",
+                component
+            );
+        }
+        resolved.push(component.clone());
+    }
+    Ok(resolved)
+}
+
+/// Builds the draft-cavage signing string: one `name: value` line per covered header.
+fn build_cavage_signing_string(
+    request: &Request,
+    headers: &[String],
+    created: u64,
+) -> Result<String> {
+    let mut lines = Vec::with_capacity(headers.len());
+    for name in headers {
+        let value = match name.as_str() {
+            "(request-target)" => {
+                let url = request.url();
+                let mut target = format!(
+                    "{} {}",
+                    request.method().as_str().to_lowercase(),
+                    url.path()
+                );
+                if let Some(query) = url.query() {
+                    target.push('?');
+                    target.push_str(query);
+                }
+                target
+            }
+            "(created)" => created.to_string(),
+            // reqwest only adds the Host header when the request is sent
+            "host" if !request.headers().contains_key(HOST) => {
+                let url = request.url();
+                let host = url
+                    .host_str()
+                    .context("message-signature: Request URL has no host")?;
+                match url.port() {
+                    Some(port) => format!("{host}:{port}"),
+                    None => host.to_string(),
+                }
+            }
+            _ => {
+                let values = request
+                    .headers()
+                    .get_all(name.as_str())
+                    .iter()
+                    .map(|value| value.to_str().map(str::trim))
+                    .collect::<Result<Vec<_>, _>>()
+                    .with_context(|| {
+                        format!("message-signature: Non-ASCII value in covered header {name}")
+                    })?;
+                if values.is_empty() {
+                    bail!("message-signature: Covered header {} is missing", name);
+                }
+                values.join(", ")
+            }
+        };
+        lines.push(format!("{name}: {value}"));
+    }
+    Ok(lines.join("\n"))
+}
+
+/// Maps an algorithm to the name used in the `algorithm` parameter of draft-cavage
+/// signatures.
+///
+/// The draft deprecates algorithm-specific names other than hmac-sha256 and
+/// rsa-sha256 in favor of "hs2019", where the verifier derives the algorithm from
+/// the key.
+fn cavage_algorithm_name(algorithm: &AlgorithmName) -> &'static str {
+    match algorithm {
+        AlgorithmName::HmacSha256 => "hmac-sha256",
+        AlgorithmName::RsaV1_5Sha256 => "rsa-sha256",
+        _ => "hs2019",
+    }
+}
+
+/// Adds a Date header if it is covered by the signature and not set by the user.
+fn ensure_date(request: &mut Request, headers: &[String]) -> Result<()> {
+    if headers.iter().any(|h| h == "date") && !request.headers().contains_key(DATE) {
+        let date = httpdate::fmt_http_date(SystemTime::now());
+        request
+            .headers_mut()
+            .insert(DATE, HeaderValue::from_str(&date)?);
+    }
+    Ok(())
+}
+
+/// Ensures the Digest header is present if it's a covered header.
+///
+/// According to RFC 3230, which draft-cavage signatures rely on, the Digest header
+/// uses the format: `SHA-256=<base64-encoded-hash>`
+fn ensure_digest(request: &mut Request, headers: &[String]) -> Result<()> {
+    if headers.iter().any(|h| h == "digest")
+        && !request.headers().contains_key("digest")
+        && request.body().is_some()
+    {
+        let bytes = buffer_request_body(request)?;
+        let digest = Sha256::digest(&bytes);
+        let value = format!("SHA-256={}", STANDARD.encode(digest));
+        request.headers_mut().insert(
+            HeaderName::from_static("digest"),
+            HeaderValue::from_str(&value)?,
+        );
+    }
+    Ok(())
+}
+
 /// Resolves and expands message components for signature coverage.
 ///
 /// This function handles:
@@ -218,7 +443,7 @@ fn buffer_request_body(request: &mut Request) -> Result<Vec<u8>> {
     if let Some(body) = request.body_mut() {
         let bytes = body
             .buffer()
-            .context("message-signature: Failed to buffer request body for Content-Digest")?
+            .context("message-signature: Failed to buffer request body for digest")?
             .to_vec();
         *body = ReqwestBody::from(bytes.clone());
         Ok(bytes)
@@ -611,6 +836,132 @@ mod tests {
         assert!(!req.headers().contains_key("signature"));
     }
 
+    #[test]
+    fn test_cavage_signing_string() {
+        let req = Client::new()
+            .post("http://example.com:8080/foo?param=value")
+            .header("date", "Thu, 15 Jan 2026 12:00:00 GMT")
+            .header("x-multi", "a")
+            .header("x-multi", " b ")
+            .body("data")
+            .build()
+            .unwrap();
+
+        let headers = vec![
+            "(request-target)".to_string(),
+            "(created)".to_string(),
+            "host".to_string(),
+            "date".to_string(),
+            "x-multi".to_string(),
+        ];
+        let signing_string = build_cavage_signing_string(&req, &headers, 1402170695).unwrap();
+        assert_eq!(
+            signing_string,
+            "(request-target): post /foo?param=value\n\
+             (created): 1402170695\n\
+             host: example.com:8080\n\
+             date: Thu, 15 Jan 2026 12:00:00 GMT\n\
+             x-multi: a, b"
+        );
+
+        let headers = vec!["x-missing".to_string()];
+        assert!(build_cavage_signing_string(&req, &headers, 0).is_err());
+    }
+
+    #[test]
+    fn test_resolve_cavage_headers() {
+        let req = Client::new().get("http://a.com").build().unwrap();
+
+        let defaults = resolve_cavage_headers(&req, None).unwrap();
+        assert_eq!(defaults, vec!["(request-target)", "host", "date"]);
+
+        // digest is skipped without a body
+        let input = vec!["(request-target)".to_string(), "digest".to_string()];
+        let resolved = resolve_cavage_headers(&req, Some(&input)).unwrap();
+        assert_eq!(resolved, vec!["(request-target)"]);
+
+        for invalid in ["@method", "(expires)", "bad header"] {
+            let input = vec![invalid.to_string()];
+            assert!(resolve_cavage_headers(&req, Some(&input)).is_err());
+        }
+
+        let input = vec!["date".to_string(), "date".to_string()];
+        let result = resolve_cavage_headers(&req, Some(&input));
+        assert!(format!("{:?}", result.err().unwrap()).contains("Duplicate covered component"));
+    }
+
+    #[test]
+    fn test_sign_request_cavage_hmac() {
+        let mut req = Client::new()
+            .post("https://example.com/foo")
+            .header("date", "Thu, 15 Jan 2026 12:00:00 GMT")
+            .body("Hello, World!")
+            .build()
+            .unwrap();
+
+        let components = vec![
+            "(request-target)".to_string(),
+            "date".to_string(),
+            "digest".to_string(),
+        ];
+        sign_request_cavage(&mut req, "test-key", "secret", Some(&components), None).unwrap();
+
+        assert_eq!(
+            req.headers()["digest"],
+            "SHA-256=3/1gIbsr1bCvZ2KQgJ7DpTGR3YHH9wpLKGiKNiGCmG8="
+        );
+        assert!(!req.headers().contains_key("signature-input"));
+
+        let expected_signature =
+            SharedKey::from_base64(&AlgorithmName::HmacSha256, &STANDARD.encode("secret"))
+                .unwrap()
+                .sign(
+                    b"(request-target): post /foo\n\
+              date: Thu, 15 Jan 2026 12:00:00 GMT\n\
+              digest: SHA-256=3/1gIbsr1bCvZ2KQgJ7DpTGR3YHH9wpLKGiKNiGCmG8=",
+                )
+                .unwrap();
+        assert_eq!(
+            req.headers()["signature"].to_str().unwrap(),
+            format!(
+                "keyId=\"test-key\",algorithm=\"hmac-sha256\",headers=\"(request-target) date digest\",signature=\"{}\"",
+                STANDARD.encode(expected_signature)
+            )
+        );
+    }
+
+    #[test]
+    fn test_sign_request_cavage_adds_date() {
+        let mut req = Client::new().get("https://example.com").build().unwrap();
+        sign_request_cavage(&mut req, "key1", "secret", None, None).unwrap();
+
+        assert!(req.headers().contains_key("date"));
+        let signature = req.headers()["signature"].to_str().unwrap();
+        assert!(signature.contains("headers=\"(request-target) host date\""));
+    }
+
+    #[test]
+    fn test_cavage_created_requires_hs2019() {
+        let mut req = Client::new().get("https://example.com").build().unwrap();
+        let components = vec!["(request-target)".to_string(), "(created)".to_string()];
+        let result = sign_request_cavage(&mut req, "key1", "secret", Some(&components), None);
+        assert!(format!("{:?}", result.err().unwrap()).contains("only with hs2019"));
+        assert!(!req.headers().contains_key("signature"));
+    }
+
+    #[test]
+    fn test_cavage_algorithm_names() {
+        assert_eq!(
+            cavage_algorithm_name(&AlgorithmName::HmacSha256),
+            "hmac-sha256"
+        );
+        assert_eq!(
+            cavage_algorithm_name(&AlgorithmName::RsaV1_5Sha256),
+            "rsa-sha256"
+        );
+        assert_eq!(cavage_algorithm_name(&AlgorithmName::Ed25519), "hs2019");
+    }
+
     #[test]
     fn test_rsa_pem_requires_explicit_algorithm() {
         let rsa_key_path = format!(
@@ -24,6 +24,9 @@ impl ResponseExt for Response {
     }
 }
 
+/// The printer type of a [`ClientWithMiddleware`] that never gets a printer.
+pub type NoPrinter = fn(&mut Response, &mut Request) -> Result<()>;
+
 type Printer<'a, 'b> = &'a mut (dyn FnMut(&mut Response, &mut Request) -> Result<()> + 'b);
 
 pub struct Context<'a, 'b> {
@@ -23,52 +23,154 @@
 //! This implementation additionally handles entries with just a password and no login,
 //! to support using .netrc for bearer auth.
 //!
+//! Encrypted files (e.g. `.netrc.gpg`) are decrypted by running a command before
+//! being parsed.
+//!
+//! It also supports a non-standard `passwordcmd` token. Its argument is the rest of
+//! the line, a command whose output is used as the password. This keeps secrets out
+//! of the file while reusing its host matching.
+//!
 //! This is too specialized for our use case to be a crate, but feel free to
 //! copy/paste into another project and modify.
 
 use std::{
+    env,
     fs::File,
-    io::{self, BufRead, BufReader},
+    io::{self, BufRead, Cursor, Read},
+    path::{Path, PathBuf},
+    process::Stdio,
 };
 
+use anyhow::{Context, Result, anyhow};
 use encoding_rs::UTF_8;
 use encoding_rs_io::DecodeReaderBytesBuilder;
 
-use crate::utils::get_home_dir;
+use crate::utils::{get_home_dir, shell_command};
 
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Entry {
     pub login: Option<String>,
-    pub password: String,
+    pub password: Password,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Password {
+    Plain(String),
+    /// A command to run to get the password, from `passwordcmd`.
+    Command(String),
+}
+
+/// Look up the entry for `host`.
+///
+/// `path` overrides `$NETRC` and the default locations. If `decrypt_cmd` is given,
+/// or the file ends in `.gpg`, the file is decrypted before being parsed. The
+/// encrypted default locations are only used if `decrypt_cmd` is given.
+pub fn find_entry(
+    host: url::Host<&str>,
+    path: Option<&Path>,
+    decrypt_cmd: Option<&str>,
+) -> Result<Option<Entry>> {
+    Ok(Netrc::load(path, decrypt_cmd)?.and_then(|netrc| netrc.find_entry(host)))
+}
+
+/// A netrc file that's been read, and decrypted if needed, so that it can be
+/// searched for many hosts.
+pub struct Netrc(String);
+
+impl Netrc {
+    /// Read the netrc file. See [`find_entry`] for the arguments.
+    pub fn load(path: Option<&Path>, decrypt_cmd: Option<&str>) -> Result<Option<Netrc>> {
+        let Some(file) = open_netrc(path, decrypt_cmd)? else {
+            return Ok(None);
+        };
+        // UTF-16 is detected if it has a BOM.
+        // Invalid UTF-8 is sanitized with replacement characters. That way it
+        // at least won't stop us from parsing the rest of the file.
+        let mut file = DecodeReaderBytesBuilder::new()
+            .encoding(Some(UTF_8))
+            .bom_override(true)
+            .build(file);
+        let mut text = String::new();
+        // Logging I/O errors would be nice.
+        if file.read_to_string(&mut text).is_err() {
+            return Ok(None);
+        }
+        Ok(Some(Netrc(text)))
+    }
+
+    /// Look up the entry for `host`.
+    pub fn find_entry(&self, host: url::Host<&str>) -> Option<Entry> {
+        Parser::new(self.0.as_bytes(), host).parse().ok().flatten()
+    }
 }
 
-pub fn find_entry(host: url::Host<&str>) -> Option<Entry> {
-    let file = open_netrc()?;
-    // UTF-16 is detected if it has a BOM.
-    // Invalid UTF-8 is sanitized with replacement characters. That way it
-    // at least won't stop us from parsing the rest of the file.
-    let file = DecodeReaderBytesBuilder::new()
-        .encoding(Some(UTF_8))
-        .bom_override(true)
-        .build(file);
-    let file = BufReader::new(file);
-    let parser = Parser::new(file, host);
-    // Logging I/O errors would be nice.
-    parser.parse().ok()?
+/// Open the netrc file, decrypting it if necessary.
+///
+/// A missing file in a default location is silently ignored, but a file the
+/// user pointed to that can't be opened or decrypted is an error since they
+/// went out of their way to set it up.
+fn open_netrc(path: Option<&Path>, decrypt_cmd: Option<&str>) -> Result<Option<Box<dyn Read>>> {
+    let Some((path, file)) = find_netrc(path, decrypt_cmd.is_some())? else {
+        return Ok(None);
+    };
+    let decrypt_cmd = match (decrypt_cmd, path.extension().and_then(|ext| ext.to_str())) {
+        (Some(decrypt_cmd), _) => decrypt_cmd,
+        (None, Some("gpg" | "asc")) => "gpg --quiet --decrypt",
+        (None, Some("age")) => {
+            return Err(anyhow!(
+                "{} is encrypted with age, use --netrc-decrypt-cmd to decrypt it \
+                (e.g. --netrc-decrypt-cmd='age --decrypt -i ~/.age/key.txt')",
+                path.display()
+            ));
+        }
+        (None, _) => return Ok(Some(Box::new(file))),
+    };
+
+    log::debug!("Decrypting {path:?} with {decrypt_cmd:?}");
+    let output = shell_command(decrypt_cmd)
+        .stdin(file)
+        .stderr(Stdio::inherit())
+        .output()
+        .with_context(|| format!("Failed to run netrc decrypt command {decrypt_cmd:?}"))?;
+    if !output.status.success() {
+        return Err(anyhow!(
+            "Failed to decrypt {} ({})",
+            path.display(),
+            output.status
+        ));
+    }
+    Ok(Some(Box::new(Cursor::new(output.stdout))))
 }
 
-fn open_netrc() -> Option<File> {
-    match std::env::var_os("NETRC") {
-        Some(path) => File::open(path).ok(),
+fn find_netrc(path: Option<&Path>, can_decrypt: bool) -> Result<Option<(PathBuf, File)>> {
+    if let Some(path) = path {
+        let file = File::open(path)
+            .with_context(|| format!("Failed to open netrc file {}", path.display()))?;
+        return Ok(Some((path.to_path_buf(), file)));
+    }
+    match env::var_os("NETRC").map(PathBuf::from) {
+        // An empty $NETRC turns it off
+        Some(path) if path.as_os_str().is_empty() => Ok(None),
+        // Unlike --netrc-file it's fine for $NETRC to point to a missing file
+        Some(path) => Ok(File::open(&path).ok().map(|file| (path, file))),
         None => {
-            let home_dir = get_home_dir()?;
-            for name in [".netrc", "_netrc"] {
+            let Some(home_dir) = get_home_dir() else {
+                return Ok(None);
+            };
+            // Decrypting takes a command (and maybe a passphrase prompt) on
+            // every request, so encrypted files have to be asked for
+            let names: &[&str] = if can_decrypt {
+                &[".netrc", "_netrc", ".netrc.gpg", ".netrc.age"]
+            } else {
+                &[".netrc", "_netrc"]
+            };
+            for name in names {
                 let path = home_dir.join(name);
-                if let Ok(file) = File::open(path) {
-                    return Some(file);
+                if let Ok(file) = File::open(&path) {
+                    return Ok(Some((path, file)));
                 }
             }
-            None
+            Ok(None)
         }
     }
 }
@@ -95,7 +197,7 @@ struct Parser<'a, R> {
     state: EntryState,
     /// The data collected for the current entry.
     login: Option<String>,
-    password: Option<String>,
+    password: Option<Password>,
     account: Option<String>,
     /// Whether to block the default entry from being returned.
     suppress_default: bool,
@@ -161,7 +263,16 @@ impl<'a, R: BufRead> Parser<'a, R> {
                         // But that's a lot of work and somewhat less vital in the
                         // single-user age. Python's stdlib does it by default, but
                         // requests/HTTPie avoids that check.
-                        self.password = Some(password);
+                        self.password = Some(Password::Plain(password));
+                    }
+                }
+                "passwordcmd" => {
+                    // The command may contain spaces, so unlike other tokens
+                    // it takes up the rest of the line.
+                    if let Some(command) = self.rest_of_line() {
+                        if !matches!(self.state, EntryState::Wrong) {
+                            self.password = Some(Password::Command(command));
+                        }
                     }
                 }
                 "account" => {
@@ -240,6 +351,13 @@ impl<'a, R: BufRead> Parser<'a, R> {
         }
     }
 
+    /// Consume the rest of the current line, if it isn't blank.
+    fn rest_of_line(&mut self) -> Option<String> {
+        let rest = self.buf[self.pos..].trim();
+        self.pos = self.buf.len();
+        (!rest.is_empty()).then(|| rest.to_owned())
+    }
+
     /// Advance the reader/buffer to the next line.
     fn advance_line(&mut self) -> io::Result<usize> {
         self.buf.clear();
@@ -442,6 +560,24 @@ mod tests {
         ";
         found(MANY_LINES, COM, "user", "pass");
 
+        const PASSWORD_COMMAND: &str = "
+            machine example.com login user passwordcmd pass show api/token
+            machine example.org passwordcmd
+            password pass
+        ";
+        let entry = Parser::new(PASSWORD_COMMAND.as_bytes(), COM)
+            .parse()
+            .unwrap();
+        assert_eq!(
+            entry,
+            Some(Entry {
+                login: Some("user".to_owned()),
+                password: Password::Command("pass show api/token".to_owned()),
+            })
+        );
+        found(PASSWORD_COMMAND, ORG, None, "pass");
+        notfound(PASSWORD_COMMAND, IP1);
+
         const STRANGE_CHARACTERS: &str = "
             machine\u{2029}oké\t\u{2029}login  u   password  p\t\t\t\r\n
         ";
@@ -458,7 +594,7 @@ mod tests {
         let entry = Parser::new(netrc.as_bytes(), host).parse().unwrap();
         let entry = entry.expect("Didn't find entry");
         assert_eq!(entry.login.as_deref(), login.into());
-        assert_eq!(entry.password, password);
+        assert_eq!(entry.password, Password::Plain(password.to_owned()));
     }
 
     #[track_caller]
@@ -0,0 +1,301 @@
+//! NTLMv2 message construction for the NTLM and Negotiate auth schemes.
+//!
+//! Only the handshake needed to authenticate an HTTP request is supported:
+//! no message signing, sealing or session key exchange.
+//!
+//! See https://learn.microsoft.com/en-us/openspecs/windows_protocols/ms-nlmp/
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::{Result, anyhow};
+use hmac::{Hmac, Mac};
+use md4::{Digest, Md4};
+use md5::Md5;
+
+const SIGNATURE: &[u8; 8] = b"NTLMSSP\0";
+
+const NEGOTIATE_UNICODE: u32 = 0x0000_0001;
+const NEGOTIATE_OEM: u32 = 0x0000_0002;
+const REQUEST_TARGET: u32 = 0x0000_0004;
+const NEGOTIATE_NTLM: u32 = 0x0000_0200;
+const NEGOTIATE_ALWAYS_SIGN: u32 = 0x0000_8000;
+const NEGOTIATE_EXTENDED_SESSIONSECURITY: u32 = 0x0008_0000;
+const NEGOTIATE_128: u32 = 0x2000_0000;
+const NEGOTIATE_56: u32 = 0x8000_0000;
+
+const NEGOTIATE_FLAGS: u32 = NEGOTIATE_UNICODE
+    | NEGOTIATE_OEM
+    | REQUEST_TARGET
+    | NEGOTIATE_NTLM
+    | NEGOTIATE_ALWAYS_SIGN
+    | NEGOTIATE_EXTENDED_SESSIONSECURITY
+    | NEGOTIATE_128
+    | NEGOTIATE_56;
+
+/// AV_PAIR ids used in the challenge's target info.
+const MSV_AV_EOL: u16 = 0;
+const MSV_AV_TIMESTAMP: u16 = 7;
+
+/// Difference between the Windows epoch (1601) and the Unix epoch, in seconds.
+const WINDOWS_EPOCH_OFFSET: u64 = 11_644_473_600;
+
+/// The NEGOTIATE_MESSAGE that starts the handshake.
+pub fn negotiate_message() -> Vec<u8> {
+    let mut message = Vec::with_capacity(32);
+    message.extend_from_slice(SIGNATURE);
+    message.extend_from_slice(&1u32.to_le_bytes());
+    message.extend_from_slice(&NEGOTIATE_FLAGS.to_le_bytes());
+    // Empty domain and workstation fields
+    message.extend_from_slice(&[0; 16]);
+    message
+}
+
+/// The parts of the server's CHALLENGE_MESSAGE that are needed to respond to it.
+#[derive(Debug)]
+pub struct Challenge {
+    flags: u32,
+    server_challenge: [u8; 8],
+    target_info: Vec<u8>,
+}
+
+impl Challenge {
+    pub fn parse(message: &[u8]) -> Result<Challenge> {
+        let invalid = || anyhow!("Invalid NTLM challenge message");
+        if message.len() < 32 || &message[..8] != SIGNATURE || read_u32(message, 8) != 2 {
+            return Err(invalid());
+        }
+        let target_info = if message.len() >= 48 {
+            let len = read_u16(message, 40) as usize;
+            let offset = read_u32(message, 44) as usize;
+            message
+                .get(offset..offset + len)
+                .ok_or_else(invalid)?
+                .to_vec()
+        } else {
+            Vec::new()
+        };
+        Ok(Challenge {
+            flags: read_u32(message, 20),
+            server_challenge: message[24..32].try_into().unwrap(),
+            target_info,
+        })
+    }
+}
+
+/// The AUTHENTICATE_MESSAGE that answers the server's challenge.
+///
+/// A username of the form `DOMAIN\user` is split into its domain and user parts.
+pub fn authenticate_message(challenge: &Challenge, username: &str, password: &str) -> Vec<u8> {
+    let (domain, user) = username.split_once('\\').unwrap_or(("", username));
+
+    // If the server sends a timestamp it must be used, and the LMv2 response
+    // must be left empty.
+    let server_timestamp = target_info_timestamp(&challenge.target_info);
+    let timestamp = server_timestamp.unwrap_or_else(current_timestamp);
+    let (lm_response, nt_response) = ntlmv2_responses(
+        user,
+        domain,
+        password,
+        &challenge.server_challenge,
+        &client_challenge(),
+        &timestamp,
+        &challenge.target_info,
+    );
+    let lm_response = match server_timestamp {
+        Some(_) => vec![0; 24],
+        None => lm_response,
+    };
+
+    let flags = (challenge.flags & NEGOTIATE_FLAGS & !NEGOTIATE_OEM) | NEGOTIATE_UNICODE;
+    let fields: [&[u8]; 6] = [
+        &lm_response,
+        &nt_response,
+        &utf16le(domain),
+        &utf16le(user),
+        // Workstation
+        &[],
+        // Encrypted random session key
+        &[],
+    ];
+
+    let mut message = Vec::new();
+    message.extend_from_slice(SIGNATURE);
+    message.extend_from_slice(&3u32.to_le_bytes());
+    let mut offset = 8 + 4 + fields.len() * 8 + 4;
+    for field in fields {
+        message.extend_from_slice(&(field.len() as u16).to_le_bytes());
+        message.extend_from_slice(&(field.len() as u16).to_le_bytes());
+        message.extend_from_slice(&(offset as u32).to_le_bytes());
+        offset += field.len();
+    }
+    message.extend_from_slice(&flags.to_le_bytes());
+    for field in fields {
+        message.extend_from_slice(field);
+    }
+    message
+}
+
+/// Compute the LMv2 and NTLMv2 responses.
+fn ntlmv2_responses(
+    user: &str,
+    domain: &str,
+    password: &str,
+    server_challenge: &[u8; 8],
+    client_challenge: &[u8; 8],
+    timestamp: &[u8; 8],
+    target_info: &[u8],
+) -> (Vec<u8>, Vec<u8>) {
+    let nt_hash = Md4::digest(utf16le(password));
+    let user_and_domain = utf16le(&(user.to_uppercase() + domain));
+    let response_key = hmac_md5(&nt_hash, &[&user_and_domain]);
+
+    let mut lm_response = hmac_md5(&response_key, &[server_challenge, client_challenge]).to_vec();
+    lm_response.extend_from_slice(client_challenge);
+
+    let mut temp = vec![1, 1, 0, 0, 0, 0, 0, 0];
+    temp.extend_from_slice(timestamp);
+    temp.extend_from_slice(client_challenge);
+    temp.extend_from_slice(&[0; 4]);
+    temp.extend_from_slice(target_info);
+    temp.extend_from_slice(&[0; 4]);
+    let mut nt_response = hmac_md5(&response_key, &[server_challenge, &temp]).to_vec();
+    nt_response.extend_from_slice(&temp);
+
+    (lm_response, nt_response)
+}
+
+fn target_info_timestamp(target_info: &[u8]) -> Option<[u8; 8]> {
+    let mut rest = target_info;
+    while rest.len() >= 4 {
+        let id = read_u16(rest, 0);
+        let len = read_u16(rest, 2) as usize;
+        let value = rest.get(4..4 + len)?;
+        match id {
+            MSV_AV_EOL => break,
+            MSV_AV_TIMESTAMP => return value.try_into().ok(),
+            _ => rest = &rest[4 + len..],
+        }
+    }
+    None
+}
+
+/// The current time in tenths of a microsecond since 1601, as used by Windows.
+fn current_timestamp() -> [u8; 8] {
+    let since_epoch = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    let ticks = (since_epoch.as_secs() + WINDOWS_EPOCH_OFFSET) * 10_000_000
+        + u64::from(since_epoch.subsec_nanos() / 100);
+    ticks.to_le_bytes()
+}
+
+fn client_challenge() -> [u8; 8] {
+    let mut challenge = [0; 8];
+    getrandom::fill(&mut challenge).expect("failed to get random bytes");
+    challenge
+}
+
+fn hmac_md5(key: &[u8], parts: &[&[u8]]) -> [u8; 16] {
+    let mut mac = Hmac::<Md5>::new_from_slice(key).expect("HMAC can take a key of any size");
+    for part in parts {
+        mac.update(part);
+    }
+    mac.finalize().into_bytes().into()
+}
+
+fn utf16le(text: &str) -> Vec<u8> {
+    text.encode_utf16().flat_map(u16::to_le_bytes).collect()
+}
+
+fn read_u16(bytes: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([bytes[offset], bytes[offset + 1]])
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hex(text: &str) -> Vec<u8> {
+        (0..text.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&text[i..i + 2], 16).unwrap())
+            .collect()
+    }
+
+    // NetBIOS domain "Domain" and NetBIOS computer "Server"
+    const TARGET_INFO: &str =
+        "02000c0044006f006d00610069006e0001000c0053006500720076006500720000000000";
+
+    #[test]
+    fn ntlmv2_test_vector() {
+        // From section 4.2.4 of MS-NLMP
+        let (lm_response, nt_response) = ntlmv2_responses(
+            "User",
+            "Domain",
+            "Password",
+            &[0x01, 0x23, 0x45, 0x67, 0x89, 0xab, 0xcd, 0xef],
+            &[0xaa; 8],
+            &[0; 8],
+            &hex(TARGET_INFO),
+        );
+        assert_eq!(
+            lm_response,
+            hex("86c35097ac9cec102554764a57cccc19aaaaaaaaaaaaaaaa")
+        );
+        assert_eq!(nt_response[..16], hex("68cd0ab851e51c96aabc927bebef6a1c"));
+    }
+
+    #[test]
+    fn handshake_messages() {
+        let negotiate = negotiate_message();
+        assert_eq!(&negotiate[..8], SIGNATURE);
+        assert_eq!(read_u32(&negotiate, 8), 1);
+        assert_eq!(read_u32(&negotiate, 12), 0xa008_8207);
+
+        let target_info = hex(TARGET_INFO);
+        let mut message = Vec::new();
+        message.extend_from_slice(SIGNATURE);
+        message.extend_from_slice(&2u32.to_le_bytes());
+        message.extend_from_slice(&[0; 8]);
+        message.extend_from_slice(&0xe288_8215u32.to_le_bytes());
+        message.extend_from_slice(&[0x01, 0x23, 0x45, 0x67, 0x89, 0xab, 0xcd, 0xef]);
+        message.extend_from_slice(&[0; 8]);
+        message.extend_from_slice(&(target_info.len() as u16).to_le_bytes());
+        message.extend_from_slice(&(target_info.len() as u16).to_le_bytes());
+        message.extend_from_slice(&48u32.to_le_bytes());
+        message.extend_from_slice(&target_info);
+        let challenge = Challenge::parse(&message).unwrap();
+        assert_eq!(challenge.target_info, target_info);
+        assert!(Challenge::parse(&message[..20]).is_err());
+        assert!(Challenge::parse(&negotiate).is_err());
+
+        let authenticate = authenticate_message(&challenge, "Domain\\User", "Password");
+        assert_eq!(read_u32(&authenticate, 8), 3);
+        let field = |index: usize| {
+            let len = read_u16(&authenticate, 12 + index * 8) as usize;
+            let offset = read_u32(&authenticate, 16 + index * 8) as usize;
+            &authenticate[offset..offset + len]
+        };
+        assert_eq!(field(0).len(), 24);
+        assert_eq!(field(1).len(), 16 + 28 + target_info.len() + 4);
+        assert_eq!(field(2), utf16le("Domain"));
+        assert_eq!(field(3), utf16le("User"));
+        assert_eq!(read_u32(&authenticate, 60) & NEGOTIATE_OEM, 0);
+    }
+
+    #[test]
+    fn server_timestamp() {
+        let mut target_info = hex("07000800");
+        target_info.extend_from_slice(&42u64.to_le_bytes());
+        target_info.extend_from_slice(&[0; 4]);
+        assert_eq!(
+            target_info_timestamp(&target_info),
+            Some(42u64.to_le_bytes())
+        );
+        assert_eq!(target_info_timestamp(&hex(TARGET_INFO)), None);
+    }
+}
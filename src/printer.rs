@@ -4,22 +4,36 @@ use std::time::Instant;
 
 use encoding_rs::Encoding;
 use encoding_rs_io::DecodeReaderBytesBuilder;
+use indicatif::HumanBytes;
 use mime::Mime;
+use prost_reflect::MessageDescriptor;
 use reqwest::blocking::{Body, Request, Response};
 use reqwest::cookie::CookieStore;
 use reqwest::header::{ACCEPT, CONTENT_LENGTH, CONTENT_TYPE, COOKIE, HOST, HeaderMap, HeaderValue};
+use termcolor::{ColorSpec, WriteColor};
 use url::Url;
 
 use crate::formatting::headers::HeaderFormatter;
+use crate::transfer::TransferLimits;
 use crate::utils::reason_phrase;
 use crate::{
-    buffer::Buffer,
+    buffer::{Buffer, is_line_limit_reached},
     cli::FormatOptions,
-    cli::{Pretty, Theme},
+    cli::{BinaryDisplay, Pretty, Theme},
     decoder::{decompress, get_compression_type},
     formatting::serde_json_format,
-    formatting::{Highlighter, format_xml, get_json_formatter},
+    formatting::{
+        Highlighter,
+        binary::dump_binary,
+        csv::{format_table, parse_csv},
+        format_xml, get_json_formatter,
+        image::{HEADER_SIZE, ImageInfo, MAX_INLINE_SIZE, write_inline_image},
+        image_protocol,
+        yaml::format_yaml,
+    },
     middleware::ResponseExt,
+    protobuf,
+    serialization::{self, BinaryFormat},
     utils::{BUFFER_SIZE, copy_largebuf, test_mode},
 };
 
@@ -55,6 +69,95 @@ impl std::fmt::Display for FoundBinaryData {
 
 impl std::error::Error for FoundBinaryData {}
 
+/// A reader that fails with [`FoundBinaryData`] as soon as the raw body
+/// contains a null byte, and keeps that chunk so it can be shown after all.
+///
+/// [`BinaryGuard`] only sees the body after it's been decoded, and by then
+/// binary data has been mangled.
+struct BinarySniffer<'a, T: Read> {
+    reader: &'a mut T,
+    enabled: bool,
+    position: u64,
+    found: Option<Vec<u8>>,
+}
+
+impl<'a, T: Read> BinarySniffer<'a, T> {
+    fn new(reader: &'a mut T, enabled: bool) -> Self {
+        Self {
+            reader,
+            enabled,
+            position: 0,
+            found: None,
+        }
+    }
+
+    /// The offset and contents of the chunk with binary data, if any.
+    fn into_found(self) -> Option<(u64, Vec<u8>)> {
+        Some((self.position, self.found?))
+    }
+}
+
+impl<T: Read> Read for BinarySniffer<'_, T> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.found.is_some() {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, FoundBinaryData));
+        }
+        let n = self.reader.read(buf)?;
+        let read = &buf[..n];
+        if self.position == 0 && (read.starts_with(b"\xFF\xFE") || read.starts_with(b"\xFE\xFF")) {
+            // UTF-16 text has null bytes, which the decoded body won't have
+            self.enabled = false;
+        }
+        if self.enabled && read.contains(&b'\0') {
+            self.found = Some(read.to_vec());
+            return Err(io::Error::new(io::ErrorKind::InvalidData, FoundBinaryData));
+        }
+        self.position += n as u64;
+        Ok(n)
+    }
+}
+
+/// A reader that stops after --max-body-display bytes, and keeps track of
+/// how much it read and whether there was more.
+struct BodyPreview<'a, T: Read> {
+    reader: &'a mut T,
+    limit: Option<u64>,
+    consumed: u64,
+    truncated: bool,
+}
+
+impl<'a, T: Read> BodyPreview<'a, T> {
+    fn new(reader: &'a mut T, limit: Option<u64>) -> Self {
+        Self {
+            reader,
+            limit,
+            consumed: 0,
+            truncated: false,
+        }
+    }
+}
+
+impl<T: Read> Read for BodyPreview<'_, T> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let buf = match self.limit {
+            Some(limit) if self.consumed >= limit => {
+                if !self.truncated {
+                    self.truncated = self.reader.read(&mut [0])? > 0;
+                }
+                return Ok(0);
+            }
+            Some(limit) => {
+                let wanted = (limit - self.consumed).min(buf.len() as u64);
+                &mut buf[..wanted as usize]
+            }
+            None => buf,
+        };
+        let n = self.reader.read(buf)?;
+        self.consumed += n as u64;
+        Ok(n)
+    }
+}
+
 impl<'a, T: Read> BinaryGuard<'a, T> {
     fn new(reader: &'a mut T, checked: bool) -> Self {
         Self {
@@ -119,13 +222,24 @@ impl<'a, T: Read> BinaryGuard<'a, T> {
 pub struct Printer {
     format_json: bool,
     json_indent_level: usize,
+    json_sort_keys: bool,
     format_xml: bool,
     xml_indent_level: usize,
+    format_yaml: bool,
+    yaml_indent_level: usize,
+    format_csv: bool,
     sort_headers: bool,
     color: bool,
     theme: Theme,
     stream: Option<bool>,
     buffer: Buffer,
+    limits: TransferLimits,
+    proto_request: Option<MessageDescriptor>,
+    proto_response: Option<MessageDescriptor>,
+    binary_display: BinaryDisplay,
+    binary_limit: Option<u64>,
+    max_body_display: Option<u64>,
+    head_lines: Option<usize>,
 }
 
 impl Printer {
@@ -139,16 +253,58 @@ impl Printer {
         Printer {
             format_json: format_options.json_format.unwrap_or(pretty.format()),
             json_indent_level: format_options.json_indent.unwrap_or(4),
+            json_sort_keys: format_options.json_sort_keys.unwrap_or(false),
             format_xml: format_options.xml_format.unwrap_or(pretty.format()),
             xml_indent_level: format_options.xml_indent.unwrap_or(2),
+            format_yaml: pretty.format(),
+            yaml_indent_level: format_options.yaml_indent.unwrap_or(2),
+            format_csv: pretty.format(),
             sort_headers: format_options.headers_sort.unwrap_or(pretty.format()),
             color: pretty.color(),
             stream: stream.into(),
             theme,
             buffer,
+            limits: TransferLimits::default(),
+            proto_request: None,
+            proto_response: None,
+            binary_display: BinaryDisplay::default(),
+            binary_limit: None,
+            max_body_display: None,
+            head_lines: None,
         }
     }
 
+    pub fn with_transfer_limits(mut self, limits: TransferLimits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    /// How to show binary bodies, and how many bytes of them at most.
+    pub fn with_binary_display(mut self, display: BinaryDisplay, limit: Option<u64>) -> Self {
+        self.binary_display = display;
+        self.binary_limit = limit;
+        self
+    }
+
+    /// How much of a response body to show in the terminal, in bytes of the
+    /// body and in lines of output.
+    pub fn with_body_display_limits(mut self, bytes: Option<u64>, lines: Option<usize>) -> Self {
+        self.max_body_display = bytes;
+        self.head_lines = lines;
+        self
+    }
+
+    /// Message types for decoding protobuf request and response bodies.
+    pub fn with_protobuf_messages(
+        mut self,
+        request: Option<MessageDescriptor>,
+        response: Option<MessageDescriptor>,
+    ) -> Self {
+        self.proto_request = request;
+        self.proto_response = response;
+        self
+    }
+
     fn get_highlighter(&mut self, syntax: &'static str) -> Highlighter<'_> {
         Highlighter::new(syntax, self.theme, &mut self.buffer)
     }
@@ -190,14 +346,19 @@ impl Printer {
 
         if self.color {
             let mut buf = Vec::new();
-            serde_json_format(self.json_indent_level, text, &mut buf)?;
+            serde_json_format(self.json_indent_level, self.json_sort_keys, text, &mut buf)?;
             buf.write_all(b"\n\n")?;
             // in principle, buf should already be valid UTF-8,
             // because JSONXF doesn't mangle it
             let text = String::from_utf8_lossy(&buf);
             self.print_colorized_text(&text, "json")
         } else {
-            serde_json_format(self.json_indent_level, text, &mut self.buffer)?;
+            serde_json_format(
+                self.json_indent_level,
+                self.json_sort_keys,
+                text,
+                &mut self.buffer,
+            )?;
             self.buffer.write_all(b"\n\n")?;
             self.buffer.flush()?;
             Ok(())
@@ -228,12 +389,158 @@ impl Printer {
         }
     }
 
+    fn print_yaml_text(&mut self, body: &str) -> io::Result<()> {
+        if !self.format_yaml {
+            return self.print_syntax_text(body, "yaml");
+        }
+
+        let Some(mut text) = format_yaml(self.yaml_indent_level, body) else {
+            log::debug!("Not formatting YAML");
+            return self.print_syntax_text(body, "yaml");
+        };
+        text.push('\n');
+        self.print_syntax_text(&text, "yaml")
+    }
+
+    fn print_csv_text(&mut self, body: &str) -> io::Result<()> {
+        // A table is easier to read but it can't be parsed back as CSV,
+        // so only show it in a terminal
+        if !self.format_csv || !self.buffer.is_terminal() {
+            return self.print_syntax_text(body, "csv");
+        }
+
+        let Some(rows) = parse_csv(body) else {
+            return self.print_syntax_text(body, "csv");
+        };
+        for (index, line) in format_table(&rows).iter().enumerate() {
+            if index == 0 && self.color {
+                self.buffer.set_color(ColorSpec::new().set_bold(true))?;
+                self.buffer.print(line)?;
+                self.buffer.reset()?;
+            } else {
+                self.buffer.print(line)?;
+            }
+            self.buffer.print("\n")?;
+        }
+        self.buffer.print("\n")?;
+        self.buffer.flush()
+    }
+
+    /// Print a MessagePack, CBOR or BSON body as JSON, or the binary suppressor
+    /// if it can't be decoded.
+    fn print_serialized_body(&mut self, format: BinaryFormat, body: &[u8]) -> io::Result<()> {
+        match serialization::decode(format, body) {
+            Ok(value) => {
+                self.print_json_text(&value.to_string(), false)?;
+                self.buffer.print("\n")
+            }
+            Err(err) => {
+                log::debug!("Failed to decode {format:?} body: {err}");
+                self.print_binary(&mut &*body, 0)
+            }
+        }
+    }
+
+    /// Print a protobuf body as JSON if its message type is known, and
+    /// otherwise as a list of numbered fields.
+    fn print_protobuf_body(
+        &mut self,
+        message: Option<&MessageDescriptor>,
+        body: &[u8],
+    ) -> io::Result<()> {
+        if let Some(message) = message {
+            match protobuf::decode(message, body) {
+                Ok(value) => {
+                    self.print_json_text(&value.to_string(), false)?;
+                    return self.buffer.print("\n");
+                }
+                Err(err) => log::debug!("Failed to decode {}: {err}", message.full_name()),
+            }
+        }
+        match protobuf::decode_raw(body) {
+            Some(fields) => {
+                self.buffer.print(&fields)?;
+                self.buffer.print("\n")
+            }
+            None => self.print_binary(&mut &*body, 0),
+        }
+    }
+
+    /// Show a binary body the way --binary asks for, by default with a note
+    /// that it isn't shown.
+    fn print_binary(&mut self, body: &mut impl Read, offset: u64) -> io::Result<()> {
+        if self.binary_display == BinaryDisplay::Suppress {
+            return self.buffer.print(BINARY_SUPPRESSOR);
+        }
+        dump_binary(
+            self.binary_display,
+            body,
+            offset,
+            self.binary_limit,
+            self.color,
+            &mut self.buffer,
+        )?;
+        self.buffer.print("\n")
+    }
+
+    /// Show an image inline if the terminal can do that, and describe it
+    /// otherwise. Returns false if it's not an image we recognize, or if the
+    /// user asked for binary data to be dumped, with what was read in `buf`.
+    ///
+    /// Only the header is read unless the image is shown inline, and images
+    /// that are too big for that are described instead.
+    fn print_image(&mut self, body: &mut impl Read, buf: &mut Vec<u8>) -> io::Result<bool> {
+        if self.binary_display != BinaryDisplay::Suppress {
+            return Ok(false);
+        }
+        body.by_ref().take(HEADER_SIZE).read_to_end(buf)?;
+        let Some(image) = ImageInfo::read(buf) else {
+            return Ok(false);
+        };
+        // Without colors the output shouldn't contain escape sequences
+        let protocol = image_protocol().filter(|&protocol| self.color && image.can_show(protocol));
+        if let Some(protocol) = protocol {
+            body.by_ref()
+                .take(MAX_INLINE_SIZE + 1 - buf.len() as u64)
+                .read_to_end(buf)?;
+            // The escape sequences would show up as garbage in the pager
+            if buf.len() as u64 <= MAX_INLINE_SIZE && self.buffer.bypass_pager()? {
+                write_inline_image(protocol, &image, buf, &mut self.buffer)?;
+                self.buffer.print("\n\n")?;
+                return Ok(true);
+            }
+        }
+        let length = buf.len() as u64 + io::copy(body, &mut io::sink())?;
+        self.buffer.print(&image.note(length))?;
+        Ok(true)
+    }
+
+    fn print_body_blob(
+        &mut self,
+        content_type: ContentType,
+        buf: &[u8],
+        encoding: Option<&'static Encoding>,
+        url: &Url,
+    ) -> io::Result<()> {
+        match decode_blob(buf, encoding, url) {
+            None => self.print_binary(&mut &*buf, 0),
+            Some(text) => {
+                self.print_body_text(content_type, &text)?;
+                self.buffer.print("\n")
+            }
+        }
+    }
+
     fn print_body_text(&mut self, content_type: ContentType, body: &str) -> io::Result<()> {
         match content_type {
             ContentType::Json => self.print_json_text(body, true),
             ContentType::Xml => self.print_xml_text(body),
             ContentType::Html => self.print_syntax_text(body, "html"),
             ContentType::Css => self.print_syntax_text(body, "css"),
+            ContentType::Yaml => self.print_yaml_text(body),
+            ContentType::Toml => self.print_syntax_text(body, "toml"),
+            ContentType::Csv => self.print_csv_text(body),
+            ContentType::Markdown => self.print_syntax_text(body, "md"),
             // In HTTPie part of this behavior is gated behind the --json flag
             // But it does JSON formatting even without that flag, so doing
             // this check unconditionally is fine
@@ -291,7 +598,7 @@ impl Printer {
             self.print_syntax_stream(stream, "json")
         } else if self.color {
             let mut guard = BinaryGuard::new(stream, self.buffer.is_terminal());
-            let mut formatter = get_json_formatter(self.json_indent_level);
+            let mut formatter = get_json_formatter(self.json_indent_level, self.json_sort_keys);
             let mut highlighter = self.get_highlighter("json");
             let mut buf = Vec::new();
             while let Some(lines) = guard.read_lines()? {
@@ -302,14 +609,19 @@ impl Printer {
                 highlighter.flush()?;
                 buf.clear();
             }
-            Ok(())
+            formatter.finish(&mut buf)?;
+            highlighter.highlight_bytes(&buf)?;
+            highlighter.flush()
         } else {
-            let mut formatter = get_json_formatter(self.json_indent_level);
+            let mut formatter = get_json_formatter(self.json_indent_level, self.json_sort_keys);
             if !self.buffer.is_terminal() {
                 let mut buf = vec![0; BUFFER_SIZE];
                 loop {
                     match stream.read(&mut buf) {
-                        Ok(0) => return Ok(()),
+                        Ok(0) => {
+                            formatter.finish(&mut self.buffer)?;
+                            return self.buffer.flush();
+                        }
                         Ok(n) => {
                             formatter.format_buf(&buf[0..n], &mut self.buffer)?;
                             self.buffer.flush()?;
@@ -324,7 +636,8 @@ impl Printer {
                 formatter.format_buf(lines, &mut self.buffer)?;
                 self.buffer.flush()?;
             }
-            Ok(())
+            formatter.finish(&mut self.buffer)?;
+            self.buffer.flush()
         }
     }
 
@@ -338,6 +651,10 @@ impl Printer {
             ContentType::Xml => self.print_syntax_stream(body, "xml"),
             ContentType::Html => self.print_syntax_stream(body, "html"),
             ContentType::Css => self.print_syntax_stream(body, "css"),
+            ContentType::Yaml => self.print_syntax_stream(body, "yaml"),
+            ContentType::Toml => self.print_syntax_stream(body, "toml"),
+            ContentType::Csv => self.print_syntax_stream(body, "csv"),
+            ContentType::Markdown => self.print_syntax_stream(body, "md"),
             // print_body_text() has fancy JSON detection, but we can't do that here
             ContentType::JavaScript => self.print_syntax_stream(body, "js"),
             _ => self.print_stream(body),
@@ -421,8 +738,17 @@ impl Printer {
         let content_type = get_content_type(request.headers());
         if let Some(body) = request.body_mut() {
             let body = body.buffer()?;
-            if body.contains(&b'\0') {
-                self.buffer.print(BINARY_SUPPRESSOR)?;
+            if let ContentType::Serialized(format) = content_type {
+                self.print_serialized_body(format, body)?;
+            } else if let ContentType::Protobuf = content_type {
+                let message = self.proto_request.clone();
+                self.print_protobuf_body(message.as_ref(), body)?;
+            } else if matches!(content_type, ContentType::Image)
+                && self.print_image(&mut &*body, &mut Vec::new())?
+            {
+                // Already shown
+            } else if body.contains(&b'\0') {
+                self.print_binary(&mut &*body, 0)?;
             } else {
                 self.print_body_text(content_type, &String::from_utf8_lossy(body))?;
                 self.buffer.print("\n")?;
@@ -434,6 +760,77 @@ impl Printer {
         Ok(())
     }
 
+    /// Print a response body for the terminal, where it's formatted and
+    /// binary data isn't shown as is.
+    fn print_terminal_body(
+        &mut self,
+        body: &mut impl Read,
+        content_type: ContentType,
+        encoding: Option<&'static Encoding>,
+        url: &Url,
+        stream: bool,
+    ) -> anyhow::Result<()> {
+        if let ContentType::Serialized(format) = content_type {
+            // These can only be decoded as a whole, even with --stream
+            let mut buf = Vec::new();
+            body.read_to_end(&mut buf)?;
+            self.print_serialized_body(format, &buf)?;
+        } else if let ContentType::Protobuf = content_type {
+            let mut buf = Vec::new();
+            body.read_to_end(&mut buf)?;
+            let message = self.proto_response.clone();
+            self.print_protobuf_body(message.as_ref(), &buf)?;
+        } else if let ContentType::Image = content_type {
+            // Images are only shown whole, even with --stream
+            let mut buf = Vec::new();
+            if !self.print_image(body, &mut buf)? {
+                body.read_to_end(&mut buf)?;
+                self.print_body_blob(content_type, &buf, encoding, url)?;
+            }
+        } else if stream {
+            // Decoding mangles binary data, so look for it before that if it's
+            // going to be shown
+            let mut sniffer = BinarySniffer::new(
+                &mut *body,
+                self.binary_display != BinaryDisplay::Suppress
+                    && encoding.is_none_or(Encoding::is_ascii_compatible),
+            );
+            let result = decode_stream(&mut sniffer, encoding, url)
+                .and_then(|mut stream| self.print_body_stream(content_type, &mut stream));
+            match result {
+                Ok(_) => {
+                    self.buffer.print("\n")?;
+                }
+                Err(err) if err.get_ref().is_some_and(|err| err.is::<FoundBinaryData>()) => {
+                    match sniffer.into_found() {
+                        Some((offset, found)) => {
+                            self.print_binary(&mut found.as_slice().chain(&mut *body), offset)?;
+                        }
+                        None => self.buffer.print(BINARY_SUPPRESSOR)?,
+                    }
+                }
+                Err(err) => return Err(err.into()),
+            }
+        } else {
+            let mut buf = Vec::new();
+            body.read_to_end(&mut buf)?;
+            self.print_body_blob(content_type, &buf, encoding, url)?;
+        }
+        Ok(())
+    }
+
+    fn print_truncation_note(&mut self, remaining: Option<u64>) -> io::Result<()> {
+        match remaining {
+            Some(remaining) => self.buffer.print(&format!(
+                "… {} more, use --download to get the whole body\n\n",
+                HumanBytes(remaining)
+            )),
+            None => self
+                .buffer
+                .print("… the rest isn't shown, use --download to get the whole body\n\n"),
+        }
+    }
+
     pub fn print_response_body(
         &mut self,
         response: &mut Response,
@@ -446,13 +843,18 @@ impl Printer {
             mime.map_or_else(|| get_content_type(response.headers()), ContentType::from);
         let encoding = encoding.or_else(|| get_charset(response));
         let compression_type = get_compression_type(response.headers());
-        let mut body = decompress(response, compression_type);
+        let content_length = response.content_length();
+        self.limits.check_length(content_length)?;
+        let mut limited = self.limits.wrap(&mut *response);
+        let mut body = decompress(&mut limited, compression_type);
 
         // Automatically activate stream mode when it hasn't been set by the user and the content type is stream
         let stream = self.stream.unwrap_or(content_type.is_stream());
 
         if !self.buffer.is_terminal() {
-            if (self.color || self.format_json || self.format_xml) && content_type.is_text() {
+            if (self.color || self.format_json || self.format_xml || self.format_yaml)
+                && content_type.is_text()
+            {
                 // The user explicitly asked for formatting even though this is
                 // going into a file, and the response is at least supposed to be
                 // text, so decode it
@@ -483,33 +885,39 @@ impl Printer {
                 body.read_to_end(&mut buf)?;
                 self.buffer.write_all(&buf)?;
             }
-        } else if stream {
-            match self
-                .print_body_stream(content_type, &mut decode_stream(&mut body, encoding, &url)?)
-            {
-                Ok(_) => {
-                    self.buffer.print("\n")?;
-                }
-                Err(err) if err.get_ref().is_some_and(|err| err.is::<FoundBinaryData>()) => {
-                    self.buffer.print(BINARY_SUPPRESSOR)?;
-                }
-                Err(err) => return Err(err.into()),
-            }
         } else {
-            let mut buf = Vec::new();
-            body.read_to_end(&mut buf)?;
-            match decode_blob(&buf, encoding, &url) {
-                None => {
-                    self.buffer.print(BINARY_SUPPRESSOR)?;
-                }
-                Some(text) => {
-                    self.print_body_text(content_type, &text)?;
-                    self.buffer.print("\n")?;
-                }
+            if stream {
+                // It could take a long time to fill the screen, if it ever does
+                self.buffer.bypass_pager()?;
+            }
+            let mut preview = BodyPreview::new(&mut body, self.max_body_display);
+            self.buffer.set_line_limit(self.head_lines);
+            let result =
+                self.print_terminal_body(&mut preview, content_type, encoding, &url, stream);
+            self.buffer.set_line_limit(None);
+            // What's left when the body isn't compressed
+            let remaining = match compression_type {
+                None => content_length
+                    .map(|length| length.saturating_sub(preview.consumed))
+                    .filter(|&remaining| remaining > 0),
+                Some(_) => None,
             };
+            match result {
+                Err(err) if is_line_limit_reached(&err) => {
+                    if self.color {
+                        // It may have stopped in the middle of a colored line
+                        self.buffer.reset()?;
+                    }
+                    self.print_truncation_note(remaining)?;
+                }
+                Err(err) => return Err(err),
+                Ok(()) if preview.truncated => self.print_truncation_note(remaining)?,
+                Ok(()) => (),
+            }
         }
         self.buffer.flush()?;
         drop(body); // silence the borrow checker
+        drop(limited);
         response.meta_mut().content_download_duration = Some(starting_time.elapsed());
         Ok(())
     }
@@ -539,6 +947,13 @@ enum ContentType {
     Xml,
     JavaScript,
     Css,
+    Yaml,
+    Toml,
+    Csv,
+    Markdown,
+    Serialized(BinaryFormat),
+    Protobuf,
+    Image,
     Text,
     UrlencodedForm,
     Multipart,
@@ -549,12 +964,21 @@ enum ContentType {
 impl ContentType {
     fn is_text(&self) -> bool {
         match self {
-            ContentType::Unknown | ContentType::UrlencodedForm | ContentType::Multipart => false,
+            ContentType::Unknown
+            | ContentType::UrlencodedForm
+            | ContentType::Multipart
+            | ContentType::Serialized(_)
+            | ContentType::Protobuf
+            | ContentType::Image => false,
             ContentType::Json
             | ContentType::Html
             | ContentType::Xml
             | ContentType::JavaScript
             | ContentType::Css
+            | ContentType::Yaml
+            | ContentType::Toml
+            | ContentType::Csv
+            | ContentType::Markdown
             | ContentType::Text
             | ContentType::EventStream => true,
         }
@@ -567,6 +991,13 @@ impl ContentType {
             | ContentType::Xml
             | ContentType::JavaScript
             | ContentType::Css
+            | ContentType::Yaml
+            | ContentType::Toml
+            | ContentType::Csv
+            | ContentType::Markdown
+            | ContentType::Serialized(_)
+            | ContentType::Protobuf
+            | ContentType::Image
             | ContentType::Text
             | ContentType::UrlencodedForm
             | ContentType::Multipart
@@ -577,7 +1008,11 @@ impl ContentType {
 
 impl From<&str> for ContentType {
     fn from(content_type: &str) -> Self {
-        if content_type.contains("json") {
+        if let Some(format) = BinaryFormat::from_content_type(content_type) {
+            ContentType::Serialized(format)
+        } else if content_type.contains("protobuf") {
+            ContentType::Protobuf
+        } else if content_type.contains("json") {
             ContentType::Json
         } else if content_type.contains("xml") {
             ContentType::Xml
@@ -593,6 +1028,17 @@ impl From<&str> for ContentType {
             ContentType::Css
         } else if content_type.contains("event-stream") {
             ContentType::EventStream
+        } else if content_type.contains("yaml") {
+            ContentType::Yaml
+        } else if content_type.contains("toml") {
+            ContentType::Toml
+        } else if content_type.contains("csv") {
+            ContentType::Csv
+        } else if content_type.contains("markdown") {
+            ContentType::Markdown
+        } else if content_type.starts_with("image/") {
+            // SVG is XML, so it's caught above
+            ContentType::Image
         } else if content_type.contains("text") {
             // We later check if this one's JSON
             // HTTPie checks for "json", "javascript" and "text" in one place:
@@ -756,7 +1202,8 @@ mod tests {
     fn run_cmd(args: impl IntoIterator<Item = String>, is_stdout_tty: bool) -> Printer {
         let args = Cli::try_parse_from(args).unwrap();
         let theme = args.style.unwrap_or_default();
-        let buffer = Buffer::new(args.download, args.output.as_deref(), is_stdout_tty).unwrap();
+        let buffer =
+            Buffer::new(args.download, args.output.as_deref(), is_stdout_tty, false).unwrap();
         let pretty = args.pretty.unwrap_or_else(|| buffer.guess_pretty());
         Printer::new(pretty, theme, false, buffer, FormatOptions::default())
     }
@@ -836,4 +1283,19 @@ mod tests {
         assert_eq!(p.color, true);
         assert!(p.buffer.is_stderr());
     }
+
+    #[test]
+    fn binary_sniffer() {
+        let mut body = (&b"text\n"[..]).chain(&b"\x00bin"[..]);
+        let mut sniffer = BinarySniffer::new(&mut body, true);
+        let mut buf = [0; 16];
+        assert_eq!(sniffer.read(&mut buf).unwrap(), 5);
+        assert!(sniffer.read(&mut buf).is_err());
+        assert_eq!(sniffer.into_found(), Some((5, b"\x00bin".to_vec())));
+
+        let mut body = &b"\xFF\xFEa\x00"[..];
+        let mut sniffer = BinarySniffer::new(&mut body, true);
+        assert_eq!(sniffer.read(&mut buf).unwrap(), 4);
+        assert_eq!(sniffer.into_found(), None);
+    }
 }
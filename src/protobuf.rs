@@ -0,0 +1,223 @@
+//! Protobuf bodies, decoded with a compiled descriptor set given by
+//! --proto-descriptor or, without one, dumped like `protoc --decode_raw`.
+
+use std::fs;
+use std::path::Path;
+
+use anyhow::{Context, Result, anyhow};
+use prost::Message;
+use prost_reflect::{DescriptorPool, DynamicMessage, MessageDescriptor};
+use serde_json::Value;
+
+pub const PROTOBUF_CONTENT_TYPE: &str = "application/x-protobuf";
+pub const PROTOBUF_ACCEPT: &str = "application/x-protobuf, */*;q=0.5";
+
+/// Load a FileDescriptorSet, as written by `protoc --descriptor_set_out`.
+pub fn load_descriptors(path: &Path) -> Result<DescriptorPool> {
+    let bytes = fs::read(path)
+        .with_context(|| format!("Failed to read descriptor set {}", path.display()))?;
+    DescriptorPool::decode(bytes.as_slice())
+        .with_context(|| format!("Invalid descriptor set {}", path.display()))
+}
+
+pub fn find_message(pool: &DescriptorPool, name: &str) -> Result<MessageDescriptor> {
+    pool.get_message_by_name(name.trim_start_matches('.'))
+        .ok_or_else(|| anyhow!("Message type '{name}' not found in the descriptor set"))
+}
+
+/// Decode a message into its canonical JSON mapping.
+pub fn decode(message: &MessageDescriptor, bytes: &[u8]) -> Result<Value> {
+    let message = DynamicMessage::decode(message.clone(), bytes)?;
+    Ok(serde_json::to_value(&message)?)
+}
+
+pub fn encode(message: &MessageDescriptor, value: &Value) -> Result<Vec<u8>> {
+    let message = DynamicMessage::deserialize(message.clone(), value)
+        .with_context(|| format!("Request body doesn't match {}", message.full_name()))?;
+    Ok(message.encode_to_vec())
+}
+
+/// Show the fields of a message without knowing its type, in the format of
+/// `protoc --decode_raw`. Returns `None` if it isn't valid protobuf.
+pub fn decode_raw(mut bytes: &[u8]) -> Option<String> {
+    let mut out = String::new();
+    write_raw_fields(&mut bytes, 0, None, &mut out)?;
+    Some(out)
+}
+
+/// How deeply messages and groups are nested at most, like protoc's default
+/// recursion limit. Deeper messages are shown as strings.
+const MAX_DEPTH: usize = 100;
+
+/// Write fields until the input runs out, or until the end of the group
+/// with number `group` if there is one.
+fn write_raw_fields(
+    bytes: &mut &[u8],
+    depth: usize,
+    group: Option<u64>,
+    out: &mut String,
+) -> Option<()> {
+    let indent = "  ".repeat(depth);
+    while !bytes.is_empty() {
+        let key = read_varint(bytes)?;
+        let (number, wire_type) = (key >> 3, key & 7);
+        if number == 0 {
+            return None;
+        }
+        match wire_type {
+            0 => out.push_str(&format!("{indent}{number}: {}\n", read_varint(bytes)?)),
+            1 => {
+                let value = u64::from_le_bytes(take(bytes, 8)?.try_into().ok()?);
+                out.push_str(&format!("{indent}{number}: 0x{value:016x}\n"));
+            }
+            2 => {
+                let length = usize::try_from(read_varint(bytes)?).ok()?;
+                let value = take(bytes, length)?;
+                let mut nested = String::new();
+                if !value.is_empty()
+                    && depth < MAX_DEPTH
+                    && write_raw_fields(&mut &*value, depth + 1, None, &mut nested).is_some()
+                {
+                    out.push_str(&format!("{indent}{number} {{\n"));
+                    out.push_str(&nested);
+                    out.push_str(&format!("{indent}}}\n"));
+                } else {
+                    out.push_str(&format!("{indent}{number}: \"{}\"\n", escape(value)));
+                }
+            }
+            // A group can't be shown any other way, so give up on the message
+            3 if depth >= MAX_DEPTH => return None,
+            3 => {
+                out.push_str(&format!("{indent}{number} {{\n"));
+                write_raw_fields(bytes, depth + 1, Some(number), out)?;
+                out.push_str(&format!("{indent}}}\n"));
+            }
+            4 if group == Some(number) => return Some(()),
+            5 => {
+                let value = u32::from_le_bytes(take(bytes, 4)?.try_into().ok()?);
+                out.push_str(&format!("{indent}{number}: 0x{value:08x}\n"));
+            }
+            _ => return None,
+        }
+    }
+    // A group that's never closed
+    group.is_none().then_some(())
+}
+
+fn read_varint(bytes: &mut &[u8]) -> Option<u64> {
+    let mut value = 0;
+    for (index, &byte) in bytes.iter().take(10).enumerate() {
+        value |= u64::from(byte & 0x7f) << (7 * index);
+        if byte & 0x80 == 0 {
+            *bytes = &bytes[index + 1..];
+            return Some(value);
+        }
+    }
+    None
+}
+
+fn take<'a>(bytes: &mut &'a [u8], length: usize) -> Option<&'a [u8]> {
+    if bytes.len() < length {
+        return None;
+    }
+    let (value, rest) = bytes.split_at(length);
+    *bytes = rest;
+    Some(value)
+}
+
+/// Escape a string or bytes field. Unlike protoc we keep valid UTF-8 as is.
+fn escape(value: &[u8]) -> String {
+    let mut escaped = String::new();
+    match std::str::from_utf8(value) {
+        Ok(text) => text.chars().for_each(|c| escape_char(c, &mut escaped)),
+        Err(_) => {
+            for &byte in value {
+                if byte.is_ascii() {
+                    escape_char(char::from(byte), &mut escaped);
+                } else {
+                    escaped.push_str(&format!("\\{byte:03o}"));
+                }
+            }
+        }
+    }
+    escaped
+}
+
+fn escape_char(c: char, escaped: &mut String) {
+    match c {
+        '\n' => escaped.push_str("\\n"),
+        '\r' => escaped.push_str("\\r"),
+        '\t' => escaped.push_str("\\t"),
+        '"' => escaped.push_str("\\\""),
+        '\\' => escaped.push_str("\\\\"),
+        c if c.is_control() => {
+            let mut buf = [0; 4];
+            for byte in c.encode_utf8(&mut buf).bytes() {
+                escaped.push_str(&format!("\\{byte:03o}"));
+            }
+        }
+        c => escaped.push(c),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn raw() {
+        // 1: 150, 2: "a\nb", 3: {1: 1}, 4: fixed32, 5: fixed64, 6: bytes
+        let bytes = b"\x08\x96\x01\x12\x03a\nb\x1a\x02\x08\x01\x25\x01\x00\x00\x00\x29\x02\x00\x00\x00\x00\x00\x00\x00\x32\x02\xff\x00";
+        assert_eq!(
+            decode_raw(bytes).unwrap(),
+            concat!(
+                "1: 150\n",
+                "2: \"a\\nb\"\n",
+                "3 {\n",
+                "  1: 1\n",
+                "}\n",
+                "4: 0x00000001\n",
+                "5: 0x0000000000000002\n",
+                "6: \"\\377\\000\"\n",
+            )
+        );
+        // A group, and a string that starts like a message
+        assert_eq!(
+            decode_raw(b"\x0a\x02\x08\x80").unwrap(),
+            "1: \"\\010\\200\"\n"
+        );
+        assert_eq!(decode_raw(b"\x0b\x08\x01\x0c").unwrap(), "1 {\n  1: 1\n}\n");
+    }
+
+    #[test]
+    fn raw_invalid() {
+        assert_eq!(decode_raw(b"\x08"), None);
+        assert_eq!(decode_raw(b"\x12\x05ab"), None);
+        assert_eq!(decode_raw(b"\x0b\x08\x01"), None);
+        assert_eq!(decode_raw(b"\x00"), None);
+    }
+
+    #[test]
+    fn raw_nesting_limit() {
+        // Groups nested too deeply can't be shown
+        assert_eq!(decode_raw(&[0x0b; 100_000]), None);
+        let mut groups = vec![0x0b; MAX_DEPTH];
+        groups.extend([0x0c; MAX_DEPTH]);
+        assert!(decode_raw(&groups).is_some());
+        groups.insert(0, 0x0b);
+        groups.push(0x0c);
+        assert_eq!(decode_raw(&groups), None);
+
+        // Messages nested too deeply are shown as a string from there on
+        let mut message = b"\x08\x01".to_vec();
+        for _ in 0..MAX_DEPTH + 1 {
+            let mut outer = vec![0x0a];
+            prost::encoding::encode_varint(message.len() as u64, &mut outer);
+            outer.append(&mut message);
+            message = outer;
+        }
+        let raw = decode_raw(&message).unwrap();
+        assert_eq!(raw.matches('{').count(), MAX_DEPTH);
+        assert!(raw.contains(": \""));
+    }
+}
@@ -11,6 +11,7 @@ use crate::cli::MessageSignature;
 use crate::middleware::{Context, Middleware};
 use crate::utils::{HeaderValueExt, clone_request};
 
+#[derive(Clone)]
 pub struct RedirectFollower {
     max_redirects: usize,
     #[cfg(feature = "http-message-signatures")]
@@ -50,17 +51,7 @@ impl Middleware for RedirectFollower {
 
             #[cfg(feature = "http-message-signatures")]
             if let Some(signature) = &self.message_signature {
-                if let Some((key_id, key_material)) = signature.key_pair() {
-                    let components = signature.flattened_components();
-                    let algorithm = signature.algorithm().map(Into::into);
-                    crate::message_signature::sign_request(
-                        &mut next_request,
-                        key_id,
-                        key_material,
-                        (!components.is_empty()).then_some(components.as_slice()),
-                        algorithm,
-                    )?;
-                }
+                crate::message_signature::sign_request_with_args(&mut next_request, signature)?;
             }
 
             log::info!("Following redirect to {}", next_request.url());
@@ -141,13 +132,13 @@ fn get_next_request(mut request: Request, response: &Response) -> Option<Request
 }
 
 // See https://github.com/seanmonstar/reqwest/blob/bbeb1ede4e8098481c3de6f2cafb8ecca1db4ede/src/redirect.rs#L234-L246
-fn is_cross_domain_redirect(next: &Url, previous: &Url) -> bool {
+pub(crate) fn is_cross_domain_redirect(next: &Url, previous: &Url) -> bool {
     next.host_str() != previous.host_str()
         || next.port_or_known_default() != previous.port_or_known_default()
 }
 
 // See https://github.com/seanmonstar/reqwest/blob/bbeb1ede4e8098481c3de6f2cafb8ecca1db4ede/src/redirect.rs#L234-L246
-fn remove_sensitive_headers(headers: &mut HeaderMap) {
+pub(crate) fn remove_sensitive_headers(headers: &mut HeaderMap) {
     log::debug!("Removing sensitive headers for cross-domain redirect");
     headers.remove(AUTHORIZATION);
     headers.remove(COOKIE);
@@ -164,6 +155,7 @@ fn remove_content_headers(headers: &mut HeaderMap) {
     headers.remove(CONTENT_TYPE);
     headers.remove(CONTENT_LENGTH);
     headers.remove("content-digest");
+    headers.remove("digest");
 }
 
 fn remove_signature_headers(headers: &mut HeaderMap) {
@@ -182,10 +174,12 @@ mod tests {
         let mut headers = HeaderMap::new();
         headers.insert(CONTENT_LENGTH, HeaderValue::from_static("1"));
         headers.insert("content-digest", HeaderValue::from_static("sha-256=:abc=:"));
+        headers.insert("digest", HeaderValue::from_static("SHA-256=abc="));
 
         remove_content_headers(&mut headers);
 
         assert!(!headers.contains_key(CONTENT_LENGTH));
         assert!(!headers.contains_key("content-digest"));
+        assert!(!headers.contains_key("digest"));
     }
 }
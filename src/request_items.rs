@@ -205,7 +205,7 @@ fn rsplit_once_any<'a>(
     res
 }
 
-#[derive(Default, Debug)]
+#[derive(Default, Debug, Clone)]
 pub struct RequestItems {
     pub items: Vec<RequestItem>,
     pub body_type: BodyType,
@@ -0,0 +1,181 @@
+//! Binary serialization formats that are shown as JSON, and that --msgpack
+//! and --cbor encode request bodies in.
+
+use anyhow::{Result, anyhow};
+use base64::{Engine as _, engine::general_purpose::STANDARD};
+use serde_json::{Map, Value};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BinaryFormat {
+    MessagePack,
+    Cbor,
+    Bson,
+}
+
+impl BinaryFormat {
+    pub fn from_content_type(content_type: &str) -> Option<Self> {
+        if content_type.contains("msgpack") {
+            Some(BinaryFormat::MessagePack)
+        } else if content_type.contains("cbor") {
+            Some(BinaryFormat::Cbor)
+        } else if content_type.contains("bson") {
+            Some(BinaryFormat::Bson)
+        } else {
+            None
+        }
+    }
+
+    pub fn content_type(self) -> &'static str {
+        match self {
+            BinaryFormat::MessagePack => "application/msgpack",
+            BinaryFormat::Cbor => "application/cbor",
+            BinaryFormat::Bson => "application/bson",
+        }
+    }
+
+    pub fn accept(self) -> &'static str {
+        match self {
+            BinaryFormat::MessagePack => "application/msgpack, */*;q=0.5",
+            BinaryFormat::Cbor => "application/cbor, */*;q=0.5",
+            BinaryFormat::Bson => "application/bson, */*;q=0.5",
+        }
+    }
+}
+
+/// Decode a single document into JSON.
+///
+/// Byte strings become base64 strings and map keys that aren't strings are
+/// written as JSON, since JSON has no way to represent either.
+pub fn decode(format: BinaryFormat, mut bytes: &[u8]) -> Result<Value> {
+    let value = match format {
+        BinaryFormat::MessagePack => from_msgpack(rmpv::decode::read_value(&mut bytes)?),
+        BinaryFormat::Cbor => from_cbor(ciborium::from_reader(&mut bytes)?),
+        BinaryFormat::Bson => {
+            bson::Bson::Document(bson::Document::from_reader(&mut bytes)?).into_relaxed_extjson()
+        }
+    };
+    if !bytes.is_empty() {
+        return Err(anyhow!("Unexpected data after the end of the document"));
+    }
+    Ok(value)
+}
+
+pub fn encode(format: BinaryFormat, value: &Value) -> Result<Vec<u8>> {
+    match format {
+        BinaryFormat::MessagePack => Ok(rmp_serde::to_vec_named(value)?),
+        BinaryFormat::Cbor => {
+            let mut buf = Vec::new();
+            ciborium::into_writer(value, &mut buf)?;
+            Ok(buf)
+        }
+        BinaryFormat::Bson => Err(anyhow!("Encoding request bodies as BSON is not supported")),
+    }
+}
+
+fn from_msgpack(value: rmpv::Value) -> Value {
+    use rmpv::Value as Msgpack;
+    match value {
+        Msgpack::Nil => Value::Null,
+        Msgpack::Boolean(boolean) => Value::Bool(boolean),
+        Msgpack::Integer(integer) => match (integer.as_i64(), integer.as_u64()) {
+            (Some(integer), _) => integer.into(),
+            (_, Some(integer)) => integer.into(),
+            (None, None) => unreachable!("msgpack integers fit in an i64 or u64"),
+        },
+        Msgpack::F32(float) => float.into(),
+        Msgpack::F64(float) => float.into(),
+        Msgpack::String(text) => String::from_utf8_lossy(text.as_bytes()).into(),
+        Msgpack::Binary(bytes) => Value::String(STANDARD.encode(bytes)),
+        Msgpack::Array(items) => items.into_iter().map(from_msgpack).collect(),
+        Msgpack::Map(entries) => entries
+            .into_iter()
+            .map(|(key, value)| (object_key(from_msgpack(key)), from_msgpack(value)))
+            .collect::<Map<_, _>>()
+            .into(),
+        Msgpack::Ext(tag, bytes) => {
+            let mut ext = Map::new();
+            ext.insert("type".into(), tag.into());
+            ext.insert("data".into(), STANDARD.encode(bytes).into());
+            ext.into()
+        }
+    }
+}
+
+fn from_cbor(value: ciborium::Value) -> Value {
+    use ciborium::Value as Cbor;
+    match value {
+        Cbor::Null => Value::Null,
+        Cbor::Bool(boolean) => Value::Bool(boolean),
+        Cbor::Integer(integer) => {
+            let integer = i128::from(integer);
+            match (i64::try_from(integer), u64::try_from(integer)) {
+                (Ok(integer), _) => integer.into(),
+                (_, Ok(integer)) => integer.into(),
+                // CBOR integers go down to -2^64
+                _ => Value::String(integer.to_string()),
+            }
+        }
+        Cbor::Float(float) => float.into(),
+        Cbor::Text(text) => Value::String(text),
+        Cbor::Bytes(bytes) => Value::String(STANDARD.encode(bytes)),
+        Cbor::Array(items) => items.into_iter().map(from_cbor).collect(),
+        Cbor::Map(entries) => entries
+            .into_iter()
+            .map(|(key, value)| (object_key(from_cbor(key)), from_cbor(value)))
+            .collect::<Map<_, _>>()
+            .into(),
+        // Tags only say how to interpret the value, e.g. as a date
+        Cbor::Tag(_, value) => from_cbor(*value),
+        _ => Value::Null,
+    }
+}
+
+fn object_key(key: Value) -> String {
+    match key {
+        Value::String(key) => key,
+        key => key.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn round_trip() {
+        let value = json!({"name": "xh", "list": [1, -2, 3.5, true, null], "nested": {"a": "b"}});
+        for format in [BinaryFormat::MessagePack, BinaryFormat::Cbor] {
+            let bytes = encode(format, &value).unwrap();
+            assert_eq!(decode(format, &bytes).unwrap(), value);
+        }
+    }
+
+    #[test]
+    fn decode_non_json_values() {
+        // {1: b"\x00\xff"}
+        let msgpack = [0x81, 0x01, 0xc4, 0x02, 0x00, 0xff];
+        assert_eq!(
+            decode(BinaryFormat::MessagePack, &msgpack).unwrap(),
+            json!({"1": "AP8="})
+        );
+        // 1(1700000000), a tagged timestamp
+        let cbor = [0xc1, 0x1a, 0x65, 0x53, 0xf1, 0x00];
+        assert_eq!(
+            decode(BinaryFormat::Cbor, &cbor).unwrap(),
+            json!(1700000000)
+        );
+        // {"a": 1}
+        let bson = [
+            0x0c, 0x00, 0x00, 0x00, 0x10, b'a', 0x00, 0x01, 0x00, 0x00, 0x00, 0x00,
+        ];
+        assert_eq!(decode(BinaryFormat::Bson, &bson).unwrap(), json!({"a": 1}));
+    }
+
+    #[test]
+    fn decode_errors() {
+        assert!(decode(BinaryFormat::MessagePack, &[0x92, 0x01]).is_err());
+        assert!(decode(BinaryFormat::MessagePack, &[0x01, 0x02]).is_err());
+        assert!(decode(BinaryFormat::Cbor, b"hello").is_err());
+    }
+}
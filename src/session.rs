@@ -1,19 +1,28 @@
 use std::collections::HashMap;
 use std::convert::TryInto;
-use std::ffi::OsString;
+use std::env;
+use std::ffi::{OsStr, OsString};
 use std::fs;
 use std::io::{self, Write};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
 
 use anyhow::{Context, Result, anyhow};
+use base64::{Engine as _, engine::general_purpose::STANDARD};
+use chacha20poly1305::aead::rand_core::RngCore;
+use chacha20poly1305::aead::{Aead, AeadCore, KeyInit, OsRng};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
 use reqwest::header::HeaderMap;
 use serde::{Deserialize, Serialize};
+use sha2::Sha256;
 use url::Url;
 
 use crate::auth;
-use crate::utils::{config_dir, test_mode};
+use crate::cli::{Cli, Proxy, Timeout, Verify};
+use crate::redacted::SecretString;
+use crate::utils::{config_dir, shell_command, test_mode};
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 #[serde(untagged)]
 enum Meta {
     Xh {
@@ -37,7 +46,7 @@ impl Default for Meta {
     }
 }
 
-#[derive(Default, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[derive(Clone, Default, Debug, PartialEq, Eq, Serialize, Deserialize)]
 struct Auth {
     #[serde(rename = "type")]
     auth_type: Option<String>,
@@ -70,7 +79,7 @@ struct Cookie {
     domain: Option<String>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 #[serde(untagged)]
 enum Cookies {
     // old cookie format kept for backward compatibility
@@ -85,13 +94,13 @@ impl Default for Cookies {
     }
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 struct Header {
     name: String,
     value: String,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 #[serde(untagged)]
 enum Headers {
     // old headers format kept for backward compatibility
@@ -106,16 +115,54 @@ impl Default for Headers {
     }
 }
 
-#[derive(Debug, Default, Serialize, Deserialize)]
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
 struct Content {
     #[serde(rename = "__meta__")]
     meta: Meta,
     auth: Auth,
     cookies: Cookies,
     headers: Headers,
+    // xh-specific, so it's left out when empty to keep sessions compatible with HTTPie
+    #[serde(default, skip_serializing_if = "Options::is_empty")]
+    options: Options,
+}
+
+/// Defaults for command line options, for when they're not passed explicitly.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+struct Options {
+    /// "yes", "no" or the path to a CA bundle, like --verify.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    verify: Option<String>,
+    /// In seconds, like --timeout.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    timeout: Option<f64>,
+    /// In the PROTOCOL:URL format of --proxy.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    proxy: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    query: Vec<QueryParam>,
+}
+
+impl Options {
+    fn is_empty(&self) -> bool {
+        *self == Options::default()
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+struct QueryParam {
+    name: String,
+    value: String,
 }
 
 impl Content {
+    fn cookie_list(&self) -> &[Cookie] {
+        match &self.cookies {
+            Cookies::Map(_) => unreachable!("cookies should have been migrated to Cookies::List"),
+            Cookies::List(cookies) => cookies,
+        }
+    }
+
     fn migrate(mut self) -> Self {
         self.meta = Meta::default();
         if let Headers::Map(headers) = self.headers {
@@ -156,43 +203,159 @@ impl Content {
     }
 }
 
+/// The encrypted form of everything in a session except `__meta__`.
+#[derive(Debug, Serialize, Deserialize)]
+struct Encrypted {
+    cipher: String,
+    kdf: String,
+    iterations: u32,
+    salt: String,
+    nonce: String,
+    ciphertext: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct EncryptedContent {
+    #[serde(rename = "__meta__")]
+    meta: Meta,
+    encrypted: Encrypted,
+}
+
+const CIPHER: &str = "chacha20-poly1305";
+const KDF: &str = "pbkdf2-sha256";
+
+/// A key derived from the session passphrase.
+#[derive(Clone)]
+struct Cipher {
+    iterations: u32,
+    salt: Vec<u8>,
+    key: Key,
+}
+
+impl Cipher {
+    fn new(passphrase: &str) -> Cipher {
+        let mut salt = vec![0; 16];
+        OsRng.fill_bytes(&mut salt);
+        Cipher::derive(passphrase, salt, kdf_iterations())
+    }
+
+    fn derive(passphrase: &str, salt: Vec<u8>, iterations: u32) -> Cipher {
+        let mut key = Key::default();
+        pbkdf2::pbkdf2_hmac::<Sha256>(passphrase.as_bytes(), &salt, iterations, &mut key);
+        Cipher {
+            iterations,
+            salt,
+            key,
+        }
+    }
+
+    fn encrypt(&self, plaintext: &[u8]) -> Result<Encrypted> {
+        let nonce = ChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let ciphertext = ChaCha20Poly1305::new(&self.key)
+            .encrypt(&nonce, plaintext)
+            .map_err(|_| anyhow!("couldn't encrypt session"))?;
+        Ok(Encrypted {
+            cipher: CIPHER.into(),
+            kdf: KDF.into(),
+            iterations: self.iterations,
+            salt: STANDARD.encode(&self.salt),
+            nonce: STANDARD.encode(nonce),
+            ciphertext: STANDARD.encode(ciphertext),
+        })
+    }
+}
+
+/// The most iterations a session file may ask for. Anything above this would
+/// just hang, since the key is derived again for every request.
+const MAX_KDF_ITERATIONS: u32 = 10_000_000;
+
+fn kdf_iterations() -> u32 {
+    // The iteration count is stored in the file, so this only speeds up the
+    // tests, which would otherwise spend seconds deriving keys in debug builds.
+    if test_mode() { 1_000 } else { 600_000 }
+}
+
+/// Get the passphrase that session files are encrypted with, if any.
+///
+/// It's taken from `$XH_SESSION_KEY`, read from the file named by
+/// `$XH_SESSION_KEY_FILE`, or printed by the `$XH_SESSION_KEY_CMD` command
+/// (e.g. to get it from the OS keyring), in that order.
+fn session_passphrase() -> Result<Option<SecretString>> {
+    let passphrase = if let Some(passphrase) = env::var_os("XH_SESSION_KEY") {
+        passphrase
+            .into_string()
+            .map_err(|_| anyhow!("XH_SESSION_KEY is not valid UTF-8"))?
+    } else if let Some(path) = env::var_os("XH_SESSION_KEY_FILE") {
+        fs::read_to_string(&path).with_context(|| {
+            format!(
+                "couldn't read session key from {}",
+                Path::new(&path).display()
+            )
+        })?
+    } else if let Ok(command) = env::var("XH_SESSION_KEY_CMD") {
+        let output = shell_command(&command)
+            .stdin(Stdio::null())
+            .stderr(Stdio::inherit())
+            .output()
+            .with_context(|| format!("Failed to run session key command {command:?}"))?;
+        if !output.status.success() {
+            return Err(anyhow!(
+                "Session key command {command:?} failed ({})",
+                output.status
+            ));
+        }
+        String::from_utf8(output.stdout)
+            .map_err(|_| anyhow!("Session key command {command:?} printed invalid UTF-8"))?
+    } else {
+        return Ok(None);
+    };
+    let passphrase = passphrase.trim_end_matches(['\n', '\r']);
+    if passphrase.is_empty() {
+        return Err(anyhow!("The session key is empty"));
+    }
+    Ok(Some(passphrase.parse()?))
+}
+
 pub struct Session {
     url: Url,
     pub path: PathBuf,
     read_only: bool,
     content: Content,
+    /// The cookies as they were loaded, to tell which ones were changed when
+    /// merging with updates from other processes.
+    loaded_cookies: Vec<Cookie>,
+    passphrase: Option<SecretString>,
+    /// The key the session was decrypted with, to avoid deriving it twice.
+    cipher: Option<Cipher>,
 }
 
 impl Session {
-    pub fn load_session(url: Url, mut name_or_path: OsString, read_only: bool) -> Result<Self> {
-        let path = if is_path(&name_or_path) {
-            PathBuf::from(name_or_path)
-        } else {
-            let mut path = config_dir()
-                .context("couldn't get config directory")?
-                .join("sessions")
-                .join(path_from_url(&url)?);
-            name_or_path.push(".json");
-            path.push(name_or_path);
-            path
-        };
+    pub fn load_session(url: Url, name_or_path: OsString, read_only: bool) -> Result<Self> {
+        let path = session_path(&url, name_or_path)?;
+        let passphrase = session_passphrase()?;
 
         log::debug!("Checking for session in {path:?}");
-        let content = match fs::read_to_string(&path) {
-            Ok(content) => serde_json::from_str::<Content>(&content)?.migrate(),
-            Err(err) if err.kind() == io::ErrorKind::NotFound => Content::default(),
-            Err(err) => return Err(err.into()),
-        };
+        // No lock needed, the session file is replaced in one go when written
+        let loaded = read_content(&path, passphrase.as_ref(), None)?;
+        let (content, cipher) = loaded.unwrap_or_default();
         log::debug!("Loaded session from {path:?}");
 
         Ok(Session {
             url,
             path,
             read_only,
+            loaded_cookies: content.cookie_list().to_vec(),
             content,
+            passphrase,
+            cipher,
         })
     }
 
+    /// Whether the session is written to disk encrypted.
+    pub fn is_encrypted(&self) -> bool {
+        self.passphrase.is_some()
+    }
+
     pub fn headers(&self) -> Result<HeaderMap> {
         match &self.content.headers {
             Headers::Map(_) => unreachable!("headers should have been migrated to Headers::List"),
@@ -237,12 +400,14 @@ impl Session {
                     let (username, password) = auth::parse_auth(raw_auth, "")?;
                     Ok(Some(auth::Auth::Basic(username, password)))
                 }
-                "digest" => {
+                "digest" | "ntlm" | "any" => {
                     let (username, password) = auth::parse_auth(raw_auth, "")?;
-                    Ok(Some(auth::Auth::Digest(
-                        username,
-                        password.unwrap_or_default(),
-                    )))
+                    let password = password.unwrap_or_default();
+                    Ok(Some(match auth_type.as_str() {
+                        "digest" => auth::Auth::Digest(username, password),
+                        "ntlm" => auth::Auth::Ntlm(username, password),
+                        _ => auth::Auth::Any(username, password),
+                    }))
                 }
                 "bearer" => Ok(Some(auth::Auth::Bearer(raw_auth.into()))),
                 _ => Err(anyhow!("Unknown auth type {}", raw_auth)),
@@ -267,6 +432,18 @@ impl Session {
                     raw_auth: Some(format!("{username}:{password}")),
                 }
             }
+            auth::Auth::Ntlm(username, password) => {
+                self.content.auth = Auth {
+                    auth_type: Some("ntlm".into()),
+                    raw_auth: Some(format!("{username}:{password}")),
+                }
+            }
+            auth::Auth::Any(username, password) => {
+                self.content.auth = Auth {
+                    auth_type: Some("any".into()),
+                    raw_auth: Some(format!("{username}:{password}")),
+                }
+            }
             auth::Auth::Bearer(token) => {
                 self.content.auth = Auth {
                     auth_type: Some("bearer".into()),
@@ -343,22 +520,375 @@ impl Session {
         }
     }
 
+    /// Use the session's options for those that weren't passed on the command line.
+    pub fn apply_options(&self, args: &mut Cli) -> Result<()> {
+        let options = &self.content.options;
+        if let (None, Some(verify)) = (&args.verify, &options.verify) {
+            args.verify = Some(Verify::from(OsStr::new(verify)));
+        }
+        if let (None, Some(timeout)) = (&args.timeout, options.timeout) {
+            args.timeout = Some(Timeout::try_from(timeout).context("Invalid timeout in session")?);
+        }
+        let mut proxies = Vec::new();
+        for proxy in &options.proxy {
+            let proxy: Proxy = proxy.parse().context("Invalid proxy in session")?;
+            if !args.proxy.iter().any(|arg| same_protocol(arg, &proxy)) {
+                proxies.push(proxy);
+            }
+        }
+        args.proxy.extend(proxies);
+        Ok(())
+    }
+
+    /// Default query parameters, for those that aren't in the URL already.
+    pub fn query(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.content
+            .options
+            .query
+            .iter()
+            .map(|param| (param.name.as_str(), param.value.as_str()))
+    }
+
+    /// Set one of the options in the session's `options` block.
+    ///
+    /// `proxy` and `query` can hold multiple values, so those are appended to.
+    pub fn set_option(&mut self, name: &str, value: &str) -> Result<()> {
+        let options = &mut self.content.options;
+        match name {
+            "verify" => options.verify = Some(value.into()),
+            "timeout" => {
+                value.parse::<Timeout>()?;
+                options.timeout = Some(value.parse()?);
+            }
+            "proxy" => {
+                value.parse::<Proxy>()?;
+                options.proxy.push(value.into());
+            }
+            "query" => {
+                let (name, value) = value
+                    .split_once('=')
+                    .ok_or_else(|| anyhow!("Expected a query parameter as NAME=VALUE"))?;
+                options.query.push(QueryParam {
+                    name: name.into(),
+                    value: value.into(),
+                });
+            }
+            _ => return Err(unknown_option(name)),
+        }
+        Ok(())
+    }
+
+    pub fn unset_option(&mut self, name: &str) -> Result<()> {
+        let options = &mut self.content.options;
+        match name {
+            "verify" => options.verify = None,
+            "timeout" => options.timeout = None,
+            "proxy" => options.proxy.clear(),
+            "query" => options.query.clear(),
+            _ => return Err(unknown_option(name)),
+        }
+        Ok(())
+    }
+
+    /// Remove the cookies called `name`. Returns false if there weren't any.
+    pub fn remove_cookie(&mut self, name: &str) -> bool {
+        let Cookies::List(ref mut cookies) = self.content.cookies else {
+            unreachable!("cookies should have been migrated to Cookies::List")
+        };
+        let len = cookies.len();
+        cookies.retain(|cookie| cookie.name != name);
+        cookies.len() != len
+    }
+
+    /// Remove the headers called `name`. Returns false if there weren't any.
+    pub fn remove_header(&mut self, name: &str) -> bool {
+        let Headers::List(ref mut headers) = self.content.headers else {
+            unreachable!("headers should have been migrated to Headers::List")
+        };
+        let len = headers.len();
+        headers.retain(|header| !header.name.eq_ignore_ascii_case(name));
+        headers.len() != len
+    }
+
+    /// Move the session to a new location, possibly for another host.
+    ///
+    /// Cookies that belong to the old host are moved to the new host. Nothing is
+    /// written until the session is persisted.
+    pub fn relocate(&mut self, url: Url, path: PathBuf) {
+        if let (Some(old_host), Some(new_host)) = (self.url.host_str(), url.host_str()) {
+            if let Cookies::List(ref mut cookies) = self.content.cookies {
+                for cookie in cookies {
+                    if cookie.domain.as_deref() == Some(old_host) {
+                        cookie.domain = Some(new_host.to_string());
+                    }
+                }
+            }
+        }
+        self.url = url;
+        self.path = path;
+    }
+
+    /// The session's content, with credentials and cookie values replaced by
+    /// a placeholder.
+    pub fn redacted(&self) -> Result<serde_json::Value> {
+        const REDACTED: &str = "(redacted)";
+
+        let mut content = serde_json::to_value(&self.content)?;
+        if let Some(raw_auth) = content["auth"]["raw_auth"].as_str() {
+            content["auth"]["raw_auth"] = match (self.content.auth.auth_type.as_deref(), raw_auth) {
+                (Some("bearer"), _) => REDACTED.into(),
+                (_, raw_auth) => match raw_auth.split_once(':') {
+                    Some((username, _)) => format!("{username}:{REDACTED}").into(),
+                    None => raw_auth.into(),
+                },
+            };
+        }
+        if let Some(cookies) = content["cookies"].as_array_mut() {
+            for cookie in cookies {
+                cookie["value"] = REDACTED.into();
+            }
+        }
+        if let Some(headers) = content["headers"].as_array_mut() {
+            for header in headers {
+                if header["name"].as_str().is_some_and(is_sensitive_header) {
+                    header["value"] = REDACTED.into();
+                }
+            }
+        }
+        if let Some(proxies) = content
+            .pointer_mut("/options/proxy")
+            .and_then(|p| p.as_array_mut())
+        {
+            for proxy in proxies {
+                let Some((protocol, url)) = proxy.as_str().and_then(|proxy| proxy.split_once(':'))
+                else {
+                    continue;
+                };
+                if let Ok(mut url) = Url::parse(url) {
+                    if url.password().is_some() && url.set_password(Some(REDACTED)).is_ok() {
+                        *proxy = format!("{protocol}:{url}").into();
+                    }
+                }
+            }
+        }
+        Ok(content)
+    }
+
+    /// Write the session to disk.
+    ///
+    /// If another process updated the session since it was loaded, the cookies
+    /// that changed here are applied on top of the cookies that changed there.
     pub fn persist(&self) -> Result<()> {
-        if !self.path.exists() || !self.read_only {
-            if let Some(parent_path) = self.path.parent() {
-                fs::create_dir_all(parent_path)?;
+        if self.path.exists() && self.read_only {
+            return Ok(());
+        }
+        let dir = match self.path.parent() {
+            Some(parent_path) if !parent_path.as_os_str().is_empty() => parent_path,
+            _ => Path::new("."),
+        };
+        fs::create_dir_all(dir)?;
+
+        with_lock(&self.path, || {
+            let mut content = self.content.clone();
+            if let Some((current, _)) =
+                read_content(&self.path, self.passphrase.as_ref(), self.cipher.as_ref())?
+            {
+                content.cookies = Cookies::List(merge_cookies(
+                    &self.loaded_cookies,
+                    self.content.cookie_list(),
+                    current.cookie_list(),
+                ));
             }
-            let mut session_file = fs::File::create(&self.path)?;
+
+            // Write to a temporary file first so that the session is never
+            // left half-written
+            let mut session_file = tempfile::NamedTempFile::new_in(dir)?;
             log::debug!("Persisting session to {:?}", self.path);
             let formatter = serde_json::ser::PrettyFormatter::with_indent(b"    ");
             let mut ser = serde_json::Serializer::with_formatter(&mut session_file, formatter);
-            self.content.serialize(&mut ser)?;
+            match &self.passphrase {
+                Some(passphrase) => self.encrypt(&content, passphrase)?.serialize(&mut ser)?,
+                None => content.serialize(&mut ser)?,
+            }
             session_file.write_all(b"\n")?;
+            session_file.persist(&self.path)?;
+            Ok(())
+        })
+    }
+
+    fn encrypt(&self, content: &Content, passphrase: &str) -> Result<EncryptedContent> {
+        let mut secrets = serde_json::to_value(content)?;
+        let meta = secrets
+            .as_object_mut()
+            .and_then(|secrets| secrets.shift_remove("__meta__"));
+        let plaintext = serde_json::to_vec(&secrets)?;
+        let encrypted = match &self.cipher {
+            Some(cipher) => cipher.encrypt(&plaintext)?,
+            None => Cipher::new(passphrase).encrypt(&plaintext)?,
+        };
+        Ok(EncryptedContent {
+            meta: serde_json::from_value(meta.unwrap_or_default())?,
+            encrypted,
+        })
+    }
+}
+
+/// Read the session at `path`, if it exists.
+///
+/// `cipher` is reused if it matches the file's encryption parameters.
+fn read_content(
+    path: &Path,
+    passphrase: Option<&SecretString>,
+    cipher: Option<&Cipher>,
+) -> Result<Option<(Content, Option<Cipher>)>> {
+    let content = match fs::read_to_string(path) {
+        Ok(content) => serde_json::from_str::<serde_json::Value>(&content)?,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(err) => return Err(err.into()),
+    };
+    if content.get("encrypted").is_none() {
+        let content = serde_json::from_value::<Content>(content)?;
+        return Ok(Some((content.migrate(), None)));
+    }
+    let passphrase = passphrase.ok_or_else(|| {
+        anyhow!(
+            "{} is encrypted, set XH_SESSION_KEY, XH_SESSION_KEY_FILE or \
+            XH_SESSION_KEY_CMD to decrypt it",
+            path.display()
+        )
+    })?;
+    let (content, cipher) = decrypt(content, passphrase, cipher)
+        .with_context(|| format!("couldn't decrypt {}", path.display()))?;
+    Ok(Some((content.migrate(), Some(cipher))))
+}
+
+/// Decrypt a session, returning the key so it can be used to encrypt it again.
+fn decrypt(
+    content: serde_json::Value,
+    passphrase: &str,
+    cipher: Option<&Cipher>,
+) -> Result<(Content, Cipher)> {
+    let EncryptedContent { meta, encrypted } = serde_json::from_value(content)?;
+    if encrypted.cipher != CIPHER || encrypted.kdf != KDF {
+        return Err(anyhow!(
+            "Unsupported encryption ({} with {})",
+            encrypted.cipher,
+            encrypted.kdf
+        ));
+    }
+    // A tampered file could weaken the key or make deriving it take forever
+    if !(kdf_iterations()..=MAX_KDF_ITERATIONS).contains(&encrypted.iterations) {
+        return Err(anyhow!(
+            "Invalid number of {} iterations: {}",
+            encrypted.kdf,
+            encrypted.iterations
+        ));
+    }
+    let nonce: [u8; 12] = STANDARD
+        .decode(&encrypted.nonce)?
+        .try_into()
+        .map_err(|_| anyhow!("Invalid nonce"))?;
+    let salt = STANDARD.decode(&encrypted.salt)?;
+    let cipher = match cipher {
+        Some(cipher) if cipher.salt == salt && cipher.iterations == encrypted.iterations => {
+            cipher.clone()
         }
-        Ok(())
+        _ => Cipher::derive(passphrase, salt, encrypted.iterations),
+    };
+    let plaintext = ChaCha20Poly1305::new(&cipher.key)
+        .decrypt(
+            &Nonce::from(nonce),
+            STANDARD.decode(&encrypted.ciphertext)?.as_slice(),
+        )
+        .map_err(|_| anyhow!("Wrong session key, or the file was modified"))?;
+    let mut secrets = serde_json::from_slice::<serde_json::Value>(&plaintext)?;
+    if let Some(secrets) = secrets.as_object_mut() {
+        secrets.insert("__meta__".into(), serde_json::to_value(meta)?);
+    }
+    Ok((serde_json::from_value(secrets)?, cipher))
+}
+
+fn same_protocol(a: &Proxy, b: &Proxy) -> bool {
+    std::mem::discriminant(a) == std::mem::discriminant(b)
+}
+
+fn unknown_option(name: &str) -> anyhow::Error {
+    anyhow!("Unknown session option {name:?}, expected one of: verify, timeout, proxy, query")
+}
+
+/// Combine the cookie changes from this process with those from another process
+/// that updated the session in the meantime.
+///
+/// Cookies that were added, changed or removed here win. All other cookies are
+/// taken from `current`, the session as it is now on disk.
+fn merge_cookies(loaded: &[Cookie], ours: &[Cookie], current: &[Cookie]) -> Vec<Cookie> {
+    let same =
+        |a: &Cookie, b: &Cookie| a.name == b.name && a.domain == b.domain && a.path == b.path;
+
+    let mut merged: Vec<Cookie> = current
+        .iter()
+        .filter(|cookie| {
+            // Drop cookies that were removed here, unless they've changed since
+            let removed_here = !ours.iter().any(|ours| same(ours, cookie));
+            !(removed_here && loaded.contains(cookie))
+        })
+        .cloned()
+        .collect();
+    for cookie in ours {
+        if loaded.contains(cookie) {
+            // Not changed here
+            continue;
+        }
+        match merged.iter_mut().find(|merged| same(merged, cookie)) {
+            Some(merged) => *merged = cookie.clone(),
+            None => merged.push(cookie.clone()),
+        }
+    }
+    merged
+}
+
+/// Hold an advisory lock on the session at `path` while running `f`, so that
+/// processes updating the same session take turns.
+///
+/// The lock is taken on a separate file because the session file is replaced
+/// whenever it's written. That file is removed again before the lock is
+/// released, so whoever was waiting for it has to check that it's still the
+/// lock file and otherwise start over.
+fn with_lock<T>(path: &Path, f: impl FnOnce() -> Result<T>) -> Result<T> {
+    let lock_path = lock_path(path);
+    loop {
+        let lock_file = fs::OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(&lock_path)
+            .with_context(|| format!("couldn't open {}", lock_path.display()))?;
+        let mut lock = fd_lock::RwLock::new(lock_file);
+        log::debug!("Locking {lock_path:?}");
+        let guard = lock.write()?;
+        let is_current = match same_file::Handle::from_path(&lock_path) {
+            Ok(handle) => handle == same_file::Handle::from_file(guard.try_clone()?)?,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => false,
+            Err(err) => return Err(err.into()),
+        };
+        if !is_current {
+            continue;
+        }
+        let result = f();
+        if let Err(err) = fs::remove_file(&lock_path) {
+            log::debug!("Couldn't remove {lock_path:?}: {err}");
+        }
+        return result;
     }
 }
 
+/// The file that's locked while the session at `path` is written.
+fn lock_path(path: &Path) -> PathBuf {
+    let mut lock_path = path.as_os_str().to_owned();
+    lock_path.push(".lock");
+    lock_path.into()
+}
+
 fn xh_version() -> String {
     if test_mode() {
         "0.0.0".into()
@@ -367,6 +897,78 @@ fn xh_version() -> String {
     }
 }
 
+/// The directory that holds the sessions for each host.
+pub fn sessions_dir() -> Result<PathBuf> {
+    Ok(config_dir()
+        .context("couldn't get config directory")?
+        .join("sessions"))
+}
+
+/// Where the session called `name_or_path` is stored, or `name_or_path` itself
+/// if it's already a path.
+///
+/// A session scoped to a path that `url` is under takes precedence over the
+/// one for the whole host, the longest path first. New sessions are stored
+/// for the whole host.
+pub fn session_path(url: &Url, mut name_or_path: OsString) -> Result<PathBuf> {
+    if is_path(&name_or_path) {
+        return Ok(PathBuf::from(name_or_path));
+    }
+    name_or_path.push(".json");
+    let host_dir = sessions_dir()?.join(path_from_url(url)?);
+    let scope = path_scope(url);
+    for len in (1..=scope.len()).rev() {
+        let path: PathBuf = [host_dir.as_os_str()]
+            .into_iter()
+            .chain(scope[..len].iter().map(OsStr::new))
+            .chain([name_or_path.as_os_str()])
+            .collect();
+        if path.exists() {
+            return Ok(path);
+        }
+    }
+    Ok(host_dir.join(name_or_path))
+}
+
+/// Where the session called `name` for exactly the host and path of `url` is
+/// stored, without falling back to other paths like `session_path`.
+pub fn scoped_session_path(url: &Url, name: &str) -> Result<PathBuf> {
+    Ok(scope_dir(url)?.join(format!("{name}.json")))
+}
+
+/// The directory with the sessions for exactly the host and path of `url`.
+pub fn scope_dir(url: &Url) -> Result<PathBuf> {
+    let mut dir = sessions_dir()?.join(path_from_url(url)?);
+    dir.extend(path_scope(url));
+    Ok(dir)
+}
+
+/// The segments of `url`'s path that sessions can be scoped to. This stops at
+/// the first segment that isn't safe to use as a directory name.
+fn path_scope(url: &Url) -> Vec<&str> {
+    url.path_segments()
+        .into_iter()
+        .flatten()
+        .take_while(|segment| {
+            !segment.is_empty()
+                && *segment != "."
+                && *segment != ".."
+                && segment
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.' | '~'))
+        })
+        .collect()
+}
+
+fn is_sensitive_header(name: &str) -> bool {
+    let name = name.to_ascii_lowercase();
+    matches!(
+        name.as_str(),
+        "authorization" | "proxy-authorization" | "cookie" | "x-api-key"
+    ) || name.contains("token")
+        || name.contains("secret")
+}
+
 fn is_path(value: &OsString) -> bool {
     value.to_string_lossy().contains(std::path::is_separator)
 }
@@ -394,6 +996,9 @@ mod tests {
             content: serde_json::from_str::<Content>(s)?.migrate(),
             path: PathBuf::new(),
             read_only: false,
+            loaded_cookies: Vec::new(),
+            passphrase: None,
+            cipher: None,
         })
     }
 
@@ -569,4 +1174,54 @@ mod tests {
 
         Ok(())
     }
+
+    #[test]
+    fn cookies_are_merged() {
+        let cookie = |name: &str, value: &str| Cookie {
+            name: name.into(),
+            value: value.into(),
+            expires: None,
+            path: Some("/".into()),
+            secure: None,
+            domain: Some("example.com".into()),
+        };
+        let loaded = [
+            cookie("kept", "1"),
+            cookie("removed", "1"),
+            cookie("changed", "1"),
+        ];
+        let ours = [
+            cookie("kept", "1"),
+            cookie("changed", "2"),
+            cookie("added", "1"),
+        ];
+        let current = [
+            cookie("kept", "2"),
+            cookie("removed", "1"),
+            cookie("changed", "3"),
+            cookie("other", "1"),
+        ];
+        assert_eq!(
+            merge_cookies(&loaded, &ours, &current),
+            [
+                cookie("kept", "2"),
+                cookie("changed", "2"),
+                cookie("other", "1"),
+                cookie("added", "1"),
+            ]
+        );
+    }
+
+    #[test]
+    fn path_scope_stops_at_unsafe_segments() {
+        let scope = |url: &str| {
+            let url = Url::parse(url).unwrap();
+            path_scope(&url).join("/")
+        };
+        assert_eq!(scope("http://example.com"), "");
+        assert_eq!(scope("http://example.com/api/v2/"), "api/v2");
+        assert_eq!(scope("http://example.com/api/a%2Fb/c"), "api");
+        assert_eq!(scope("http://example.com/api/C:/x"), "api");
+        assert_eq!(scope("http://example.com/api//x"), "api");
+    }
 }
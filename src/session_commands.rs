@@ -0,0 +1,297 @@
+use std::env;
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result, anyhow};
+use cookie_store::CookieStore;
+use url::Url;
+
+use crate::cli::SessionsCommand;
+use crate::cookie_file;
+use crate::session::{Session, scope_dir, scoped_session_path, sessions_dir};
+use crate::utils::shell_command;
+
+pub fn run(command: SessionsCommand) -> Result<()> {
+    match command {
+        SessionsCommand::List { host } => list(host.as_deref()),
+        SessionsCommand::Show { host, name } => {
+            let session = open(&host, &name)?;
+            let mut stdout = io::stdout().lock();
+            let formatter = serde_json::ser::PrettyFormatter::with_indent(b"    ");
+            let mut ser = serde_json::Serializer::with_formatter(&mut stdout, formatter);
+            serde::Serialize::serialize(&session.redacted()?, &mut ser)?;
+            stdout.write_all(b"\n")?;
+            Ok(())
+        }
+        SessionsCommand::Edit {
+            host,
+            name,
+            rename,
+            remove_cookies,
+            remove_headers,
+            set_options,
+            unset_options,
+        } => {
+            let mut session = open(&host, &name)?;
+            if rename.is_none()
+                && remove_cookies.is_empty()
+                && remove_headers.is_empty()
+                && set_options.is_empty()
+                && unset_options.is_empty()
+            {
+                return edit_in_editor(&host, session);
+            }
+            for cookie in &remove_cookies {
+                if !session.remove_cookie(cookie) {
+                    return Err(anyhow!("Session {name:?} has no cookie named {cookie:?}"));
+                }
+            }
+            for header in &remove_headers {
+                if !session.remove_header(header) {
+                    return Err(anyhow!("Session {name:?} has no header named {header:?}"));
+                }
+            }
+            for option in &unset_options {
+                session.unset_option(option)?;
+            }
+            for option in &set_options {
+                let (option, value) = option
+                    .split_once('=')
+                    .ok_or_else(|| anyhow!("Expected NAME=VALUE, got {option:?}"))?;
+                session
+                    .set_option(option, value)
+                    .with_context(|| format!("Invalid value for {option:?}"))?;
+            }
+            let old_path = session.path.clone();
+            if let Some(new_name) = rename {
+                let url = parse_host(&host)?;
+                let new_path = scoped_session_path(&url, &new_name)?;
+                ensure_vacant(&new_path)?;
+                session.relocate(url, new_path);
+            }
+            session.persist()?;
+            if session.path != old_path {
+                remove(&old_path)?;
+            }
+            Ok(())
+        }
+        SessionsCommand::Rm { host, name } => {
+            let session = open(&host, &name)?;
+            remove(&session.path)
+        }
+        SessionsCommand::ImportCookies { host, name, file } => {
+            let url = parse_host(&host)?;
+            let path = scoped_session_path(&url, &name)?;
+            let mut session = Session::load_session(url, path.into_os_string(), false)?;
+            let mut store = CookieStore::from_cookies(session.cookies(), false)?;
+            cookie_file::load(&file, &mut store)?;
+            session.save_cookies(store.iter_unexpired());
+            session.persist()
+        }
+        SessionsCommand::ExportCookies { host, name, file } => {
+            let session = open(&host, &name)?;
+            let store = CookieStore::from_cookies(session.cookies(), false)?;
+            match file {
+                Some(file) => cookie_file::save(&file, store.iter_unexpired()),
+                None => Ok(cookie_file::write(
+                    &mut io::stdout().lock(),
+                    store.iter_unexpired(),
+                )?),
+            }
+        }
+        SessionsCommand::Cp {
+            host,
+            name,
+            target_host,
+            target_name,
+        } => {
+            let mut session = open(&host, &name)?;
+            let target_url = parse_host(&target_host)?;
+            let target_path = scoped_session_path(&target_url, &target_name.unwrap_or(name))?;
+            ensure_vacant(&target_path)?;
+            session.relocate(target_url, target_path);
+            session.persist()
+        }
+    }
+}
+
+/// Accept "example.org", "localhost:8000" and ":8000" as well as full URLs.
+fn parse_host(host: &str) -> Result<Url> {
+    let url = if host.contains("://") {
+        Url::parse(host)
+    } else if host.starts_with(':') {
+        Url::parse(&format!("http://localhost{host}"))
+    } else {
+        Url::parse(&format!("http://{host}"))
+    };
+    url.with_context(|| format!("Invalid host {host:?}"))
+}
+
+/// Undo `path_from_url`'s encoding of the port, so the output of `list`
+/// can be passed back as a HOST.
+fn host_from_dir_name(dir_name: &str) -> String {
+    match dir_name.rsplit_once('_') {
+        Some((host, port)) if port.parse::<u16>().is_ok() => format!("{host}:{port}"),
+        _ => dir_name.to_string(),
+    }
+}
+
+fn open(host: &str, name: &str) -> Result<Session> {
+    let url = parse_host(host)?;
+    let path = scoped_session_path(&url, name)?;
+    if !path.exists() {
+        return Err(anyhow!(
+            "Session {:?} for {} does not exist ({})",
+            name,
+            host,
+            path.display()
+        ));
+    }
+    Session::load_session(url, path.into_os_string(), false)
+        .with_context(|| format!("couldn't load session {name:?}"))
+}
+
+fn ensure_vacant(path: &Path) -> Result<()> {
+    if path.exists() {
+        return Err(anyhow!("{} already exists", path.display()));
+    }
+    Ok(())
+}
+
+fn remove(session_path: &Path) -> Result<()> {
+    fs::remove_file(session_path)
+        .with_context(|| format!("couldn't delete {}", session_path.display()))?;
+    remove_empty_dirs(session_path);
+    Ok(())
+}
+
+/// Clean up after removing the last session of a host or path.
+fn remove_empty_dirs(session_path: &Path) {
+    let Ok(sessions_dir) = sessions_dir() else {
+        return;
+    };
+    let mut dir = session_path.parent();
+    while let Some(parent) =
+        dir.filter(|dir| dir.starts_with(&sessions_dir) && *dir != sessions_dir)
+    {
+        // This fails if the directory isn't empty, which is fine
+        if fs::remove_dir(parent).is_err() {
+            break;
+        }
+        dir = parent.parent();
+    }
+}
+
+fn list(host: Option<&str>) -> Result<()> {
+    let sessions_dir = sessions_dir()?;
+    // The directories to list, with the HOST they're shown as
+    let dirs: Vec<(PathBuf, String)> = match host {
+        Some(host) => vec![(scope_dir(&parse_host(host)?)?, host.to_string())],
+        None => {
+            let mut dirs = Vec::new();
+            for entry in read_dir(&sessions_dir)? {
+                if entry.file_type()?.is_dir() {
+                    if let Ok(dir_name) = entry.file_name().into_string() {
+                        collect_dirs(entry.path(), host_from_dir_name(&dir_name), &mut dirs)?;
+                    }
+                }
+            }
+            dirs.sort();
+            dirs
+        }
+    };
+
+    let mut rows = Vec::new();
+    for (dir, host) in dirs {
+        let mut names = Vec::new();
+        for entry in read_dir(&dir)? {
+            let path = entry.path();
+            if path.extension().is_some_and(|ext| ext == "json") {
+                names.extend(
+                    path.file_stem()
+                        .and_then(|name| name.to_str())
+                        .map(String::from),
+                );
+            }
+        }
+        names.sort();
+        rows.extend(names.into_iter().map(|name| (host.clone(), name)));
+    }
+
+    let mut stdout = io::stdout().lock();
+    let width = rows.iter().map(|(host, _)| host.len()).max().unwrap_or(0);
+    for (host_name, name) in rows {
+        if host.is_some() {
+            writeln!(stdout, "{name}")?;
+        } else {
+            writeln!(stdout, "{host_name:width$}  {name}")?;
+        }
+    }
+    Ok(())
+}
+
+/// Add `dir` and the directories of the paths under it that have sessions.
+fn collect_dirs(dir: PathBuf, host: String, dirs: &mut Vec<(PathBuf, String)>) -> Result<()> {
+    for entry in read_dir(&dir)? {
+        if entry.file_type()?.is_dir() {
+            if let Ok(segment) = entry.file_name().into_string() {
+                collect_dirs(entry.path(), format!("{host}/{segment}"), dirs)?;
+            }
+        }
+    }
+    dirs.push((dir, host));
+    Ok(())
+}
+
+/// Like `fs::read_dir`, but a missing directory has no entries.
+fn read_dir(path: &Path) -> Result<Vec<fs::DirEntry>> {
+    match fs::read_dir(path) {
+        Ok(entries) => Ok(entries.collect::<io::Result<_>>()?),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(Vec::new()),
+        Err(err) => Err(err).with_context(|| format!("couldn't read {}", path.display())),
+    }
+}
+
+fn edit_in_editor(host: &str, session: Session) -> Result<()> {
+    if session.is_encrypted() {
+        // The editor would need a decrypted copy on disk
+        return Err(anyhow!(
+            "Encrypted sessions can't be edited in an editor, use the options \
+            of \"xh sessions edit\" instead"
+        ));
+    }
+
+    // Hand over the file in the current format
+    session.persist()?;
+
+    let editor = env::var("VISUAL")
+        .or_else(|_| env::var("EDITOR"))
+        .unwrap_or_else(|_| if cfg!(windows) { "notepad" } else { "vi" }.into());
+    if editor.trim().is_empty() {
+        return Err(anyhow!("$VISUAL or $EDITOR is empty"));
+    }
+    // The editor may be a quoted path or have arguments, so the shell runs it
+    let mut command = if cfg!(windows) {
+        shell_command(&format!("{editor} \"{}\"", session.path.display()))
+    } else {
+        // The path is passed as $1 so that it doesn't need quoting
+        let mut command = shell_command(&format!("{editor} \"$@\""));
+        command.arg("sh").arg(&session.path);
+        command
+    };
+    let status = command
+        .status()
+        .with_context(|| format!("couldn't run editor {editor:?}"))?;
+    if !status.success() {
+        return Err(anyhow!("Editor {editor:?} failed ({status})"));
+    }
+
+    Session::load_session(
+        parse_host(host)?,
+        session.path.clone().into_os_string(),
+        true,
+    )
+    .with_context(|| format!("{} is no longer a valid session", session.path.display()))?;
+    Ok(())
+}
@@ -5,7 +5,8 @@ use os_display::Quotable;
 use reqwest::{Method, tls};
 use std::ffi::OsString;
 
-use crate::cli::{AuthType, Cli, HttpVersion, Verify};
+use crate::cli::{AuthType, Cli, HttpVersion, ProxyAuthType, Verify};
+use crate::protobuf::{PROTOBUF_ACCEPT, PROTOBUF_CONTENT_TYPE};
 use crate::request_items::{Body, FORM_CONTENT_TYPE, JSON_ACCEPT, JSON_CONTENT_TYPE, RequestItem};
 use crate::utils::{HeaderValueExt, url_with_query};
 
@@ -82,6 +83,12 @@ impl std::fmt::Display for Command {
 
 pub fn translate(args: Cli) -> Result<Command> {
     let (headers, headers_to_unset) = args.request_items.headers()?;
+    // The content type and accept headers of --msgpack, --cbor and --proto-request
+    let binary_body = match (&args.proto_request, args.binary_body_format()) {
+        (Some(_), _) => Some((PROTOBUF_CONTENT_TYPE, PROTOBUF_ACCEPT)),
+        (None, Some(format)) => Some((format.content_type(), format.accept())),
+        (None, None) => None,
+    };
 
     let mut cmd = Command::new(args.curl_long);
 
@@ -103,6 +110,8 @@ pub fn translate(args: Cli) -> Result<Command> {
         // No equivalent
         (args.m_sig.m_sig_alg.is_some(), "--unstable-m-sig-alg"),
         // No equivalent
+        (args.m_sig.m_sig_scheme.is_some(), "--unstable-m-sig-scheme"),
+        // No equivalent
         (args.m_sig.has_components(), "--unstable-m-sig-comp"),
         // No equivalent
         (args.compress > 0, "-x/--compress"),
@@ -110,10 +119,28 @@ pub fn translate(args: Cli) -> Result<Command> {
         (args.response_charset.is_some(), "--response-charset"),
         // No equivalent
         (args.response_mime.is_some(), "--response-mime"),
+        // No equivalent
+        (args.proto_message.is_some(), "--proto-message"),
+        // No equivalent
+        (args.binary.is_some(), "--binary"),
+        // No equivalent
+        (args.binary_limit.is_some(), "--binary-limit"),
+        // No equivalent
+        (args.max_body_display.is_some(), "--max-body-display"),
+        // No equivalent
+        (args.head_lines.is_some(), "--head-lines"),
+        // No equivalent
+        (args.pager == Some(true), "--pager"),
         // Already the default
         (args.all, "--all"),
         // No (straightforward?) equivalent
         (args.history_print.is_some(), "-P/--history-print"),
+        // Could be emulated with command substitution, but that's shell-specific
+        (args.auth_cmd.is_some(), "--auth-cmd"),
+        // No equivalent
+        (args.segments.is_some(), "--segments"),
+        // No equivalent
+        (args.checksum.is_some(), "--checksum"),
         // Might be possible to emulate with --cookie-jar but tricky
         (args.session.is_some(), "--session"),
         // Already the default (usually, depends on compile time options)
@@ -179,10 +206,18 @@ pub fn translate(args: Cli) -> Result<Command> {
     } else if args.download {
         cmd.opt("-O", "--remote-name");
     }
+    if let Some(output_dir) = args.output_dir {
+        cmd.arg("--output-dir");
+        cmd.arg(output_dir);
+    }
     if args.resume {
         cmd.opt("-C", "--continue-at");
         cmd.arg("-"); // Tell curl to guess, like we do
     }
+    if let Some(retry) = args.retry {
+        cmd.arg("--retry");
+        cmd.arg(retry.to_string());
+    }
     match args.verify.unwrap_or(Verify::Yes) {
         Verify::CustomCaBundle(filename) => {
             cmd.arg("--cacert");
@@ -241,10 +276,49 @@ pub fn translate(args: Cli) -> Result<Command> {
             }
         }
     }
+    if let Some(proxy_auth) = args.proxy_auth {
+        if args.proxy_auth_type == Some(ProxyAuthType::Digest) {
+            cmd.arg("--proxy-digest");
+        }
+        // curl prompts for the password in the same way
+        cmd.opt("-U", "--proxy-user");
+        cmd.arg(proxy_auth);
+    }
+    if let Some(noproxy) = args.noproxy {
+        cmd.arg("--noproxy");
+        cmd.arg(noproxy);
+    }
+    if let Some(cookies) = args.cookies {
+        cmd.opt("-b", "--cookie");
+        cmd.arg(cookies);
+    }
+    if let Some(cookie_jar) = args.cookie_jar {
+        // curl doesn't read the jar by itself
+        cmd.opt("-b", "--cookie");
+        cmd.arg(cookie_jar.clone());
+        cmd.opt("-c", "--cookie-jar");
+        cmd.arg(cookie_jar);
+    }
     if let Some(timeout) = args.timeout.and_then(|t| t.as_duration()) {
         cmd.arg("--max-time");
         cmd.arg(timeout.as_secs_f64().to_string());
     }
+    if let Some(limit_rate) = args.limit_rate {
+        cmd.arg("--limit-rate");
+        cmd.arg(limit_rate.0.to_string());
+    }
+    if let Some(speed_limit) = args.speed_limit {
+        cmd.opt("-Y", "--speed-limit");
+        cmd.arg(speed_limit.0.to_string());
+    }
+    if let Some(speed_time) = args.speed_time {
+        cmd.opt("-y", "--speed-time");
+        cmd.arg(speed_time.to_string());
+    }
+    if let Some(max_filesize) = args.max_filesize {
+        cmd.arg("--max-filesize");
+        cmd.arg(max_filesize.0.to_string());
+    }
     if let Some(http_version) = args.http_version {
         match http_version {
             HttpVersion::Http10 => cmd.arg("--http1.0"),
@@ -343,6 +417,12 @@ pub fn translate(args: Cli) -> Result<Command> {
     if args.ignore_netrc {
         // Already the default, so a bit questionable
         cmd.arg("--no-netrc");
+    } else if let Some(netrc_file) = args.netrc_file {
+        if args.netrc_decrypt_cmd.is_some() {
+            cmd.warn("Ignored --netrc-decrypt-cmd, curl can only read plain .netrc files");
+        }
+        cmd.arg("--netrc-file");
+        cmd.arg(netrc_file);
     }
     if let Some(auth) = args.auth {
         match args.auth_type.unwrap_or_default() {
@@ -358,6 +438,16 @@ pub fn translate(args: Cli) -> Result<Command> {
                 cmd.opt("-u", "--user");
                 cmd.arg(auth);
             }
+            AuthType::Ntlm => {
+                cmd.arg("--ntlm");
+                cmd.opt("-u", "--user");
+                cmd.arg(auth);
+            }
+            AuthType::Any => {
+                cmd.arg("--anyauth");
+                cmd.opt("-u", "--user");
+                cmd.arg(auth);
+            }
             AuthType::Bearer => {
                 cmd.arg("--oauth2-bearer");
                 cmd.arg(auth);
@@ -368,6 +458,9 @@ pub fn translate(args: Cli) -> Result<Command> {
     if let Some(raw) = args.raw {
         if args.form {
             cmd.header("content-type", FORM_CONTENT_TYPE);
+        } else if let Some((content_type, accept)) = binary_body {
+            cmd.header("content-type", content_type);
+            cmd.header("accept", accept);
         } else {
             cmd.header("content-type", JSON_CONTENT_TYPE);
             cmd.header("accept", JSON_ACCEPT);
@@ -435,6 +528,12 @@ pub fn translate(args: Cli) -> Result<Command> {
                     cmd.arg(encoded);
                 }
             }
+            Body::Json(value) if !value.is_null() && binary_body.is_some() => {
+                // curl can only send binary data from a file
+                return Err(anyhow!(
+                    "MessagePack, CBOR and protobuf request bodies can't be converted to a curl command"
+                ));
+            }
             Body::Json(value) if !value.is_null() => {
                 cmd.header("content-type", JSON_CONTENT_TYPE);
                 cmd.header("accept", JSON_ACCEPT);
@@ -447,7 +546,12 @@ pub fn translate(args: Cli) -> Result<Command> {
                 cmd.header("content-type", JSON_CONTENT_TYPE);
                 cmd.header("accept", JSON_ACCEPT);
             }
-            Body::Json(..) => {}
+            Body::Json(..) => {
+                if let Some((content_type, accept)) = binary_body {
+                    cmd.header("content-type", content_type);
+                    cmd.header("accept", accept);
+                }
+            }
             Body::Multipart { .. } => unreachable!(),
             Body::Raw(..) => unreachable!(),
             Body::File {
@@ -539,6 +643,34 @@ mod tests {
                 "xh --form httpbin.org/post",
                 "curl http://httpbin.org/post -H 'content-type: application/x-www-form-urlencoded'",
             ),
+            (
+                "xh --proto-descriptor=api.pb --proto-request=api.Empty httpbin.org/post",
+                "curl http://httpbin.org/post -H 'content-type: application/x-protobuf' -H 'accept: application/x-protobuf, */*;q=0.5'",
+            ),
+            (
+                "xh --msgpack httpbin.org/post",
+                "curl http://httpbin.org/post -H 'content-type: application/msgpack' -H 'accept: application/msgpack, */*;q=0.5'",
+            ),
+            (
+                "xh --proxy=all:http://proxy.test:3128 --proxy-auth=user:pass --noproxy=localhost,.internal httpbin.org/get",
+                "curl -x http://proxy.test:3128/ -U user:pass --noproxy localhost,.internal http://httpbin.org/get",
+            ),
+            (
+                "xh --proxy-auth=user:pass --proxy-auth-type=digest httpbin.org/get",
+                "curl --proxy-digest -U user:pass http://httpbin.org/get",
+            ),
+            (
+                "xh --cookies=in.txt --cookie-jar=jar.txt httpbin.org/get",
+                "curl -b in.txt -b jar.txt -c jar.txt http://httpbin.org/get",
+            ),
+            (
+                "xh -A ntlm -a user:pass httpbin.org/get",
+                "curl http://httpbin.org/get --ntlm -u user:pass",
+            ),
+            (
+                "xh --auth-type=any --auth=user:pass httpbin.org/get",
+                "curl http://httpbin.org/get --anyauth -u user:pass",
+            ),
             (
                 "xh --bearer foobar post httpbin.org/post",
                 "curl -X POST http://httpbin.org/post --oauth2-bearer foobar",
@@ -551,10 +683,22 @@ mod tests {
                 "xh -d httpbin.org/get",
                 "curl -f -L -O http://httpbin.org/get",
             ),
+            (
+                "xh -d --output-dir dist httpbin.org/get",
+                "curl -f -L -O --output-dir dist http://httpbin.org/get",
+            ),
+            (
+                "xh -d --limit-rate 500K --speed-limit 1K --speed-time 10 --max-filesize 1M httpbin.org/get",
+                "curl -f -L -O --limit-rate 512000 -Y 1024 -y 10 --max-filesize 1048576 http://httpbin.org/get",
+            ),
             (
                 "xh -d -o foobar --continue httpbin.org/get",
                 "curl -f -L -o foobar -C - http://httpbin.org/get",
             ),
+            (
+                "xh -d -o foobar --continue --retry 3 httpbin.org/get",
+                "curl -f -L -o foobar -C - --retry 3 http://httpbin.org/get",
+            ),
             (
                 "xh --curl-long -d -o foobar --continue httpbin.org/get",
                 "curl --fail --location --output foobar --continue-at - http://httpbin.org/get",
@@ -570,6 +714,10 @@ mod tests {
                 "xh http://example.com/[1-100].png?q={80,90}",
                 "curl -g 'http://example.com/[1-100].png?q={80,90}'",
             ),
+            (
+                "xh --netrc-file=creds httpbin.org/get",
+                "curl http://httpbin.org/get --netrc-file creds",
+            ),
             (
                 "xh https://exmaple.com/ hello:你好",
                 "curl https://exmaple.com/ -H 'hello: 你好'",
@@ -0,0 +1,240 @@
+//! Limits on how fast and how much of a response body is read, for
+//! --limit-rate, --speed-limit/--speed-time and --max-filesize.
+
+use std::io::{self, Read};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use anyhow::{Result, anyhow};
+use indicatif::HumanBytes;
+
+/// curl's defaults for when only one of --speed-limit and --speed-time is given.
+const DEFAULT_SPEED_LIMIT: u64 = 1;
+const DEFAULT_SPEED_TIME: u64 = 30;
+
+#[derive(Debug, Clone, Default)]
+pub struct TransferLimits {
+    /// Shared between all bodies so that parallel downloads stay under the limit together.
+    rate: Option<Arc<RateLimiter>>,
+    /// Minimum bytes per second, and how long the speed may stay below it.
+    speed_limit: Option<(u64, Duration)>,
+    max_filesize: Option<u64>,
+}
+
+impl TransferLimits {
+    pub fn new(
+        limit_rate: Option<u64>,
+        speed_limit: Option<u64>,
+        speed_time: Option<u64>,
+        max_filesize: Option<u64>,
+    ) -> Self {
+        let speed_limit = match (speed_limit, speed_time) {
+            (None, None) => None,
+            (speed_limit, speed_time) => Some((
+                speed_limit.unwrap_or(DEFAULT_SPEED_LIMIT),
+                Duration::from_secs(speed_time.unwrap_or(DEFAULT_SPEED_TIME)),
+            )),
+        };
+        TransferLimits {
+            rate: limit_rate.map(|rate| Arc::new(RateLimiter::new(rate))),
+            speed_limit,
+            max_filesize,
+        }
+    }
+
+    /// How long a single read may block before the transfer counts as stalled.
+    pub fn stall_timeout(&self) -> Option<Duration> {
+        self.speed_limit.map(|(_, time)| time)
+    }
+
+    /// Reject a body whose announced length is over --max-filesize.
+    pub fn check_length(&self, length: Option<u64>) -> Result<()> {
+        match (length, self.max_filesize) {
+            (Some(length), Some(max_filesize)) if length > max_filesize => Err(anyhow!(
+                "Response body of {} is larger than --max-filesize ({})",
+                HumanBytes(length),
+                HumanBytes(max_filesize)
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    pub fn wrap<R: Read>(&self, inner: R) -> LimitedReader<R> {
+        LimitedReader {
+            inner,
+            limits: self.clone(),
+            total: 0,
+            window_start: Instant::now(),
+            window_bytes: 0,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct RateLimiter {
+    rate: u64,
+    /// When the first byte was read, and how many bytes were read since.
+    state: Mutex<Option<(Instant, u64)>>,
+}
+
+impl RateLimiter {
+    fn new(rate: u64) -> Self {
+        RateLimiter {
+            rate: rate.max(1),
+            state: Mutex::new(None),
+        }
+    }
+
+    /// Reads are kept small so that slow rates don't turn into long pauses.
+    fn max_read(&self) -> usize {
+        (self.rate / 8).clamp(1024, 64 * 1024) as usize
+    }
+
+    fn throttle(&self, bytes: usize) {
+        let delay = {
+            let mut state = self.state.lock().unwrap();
+            let (start, total) = state.get_or_insert_with(|| (Instant::now(), 0));
+            *total += bytes as u64;
+            let due = Duration::from_secs_f64(*total as f64 / self.rate as f64);
+            due.saturating_sub(start.elapsed())
+        };
+        if !delay.is_zero() {
+            thread::sleep(delay);
+        }
+    }
+}
+
+pub struct LimitedReader<R> {
+    inner: R,
+    limits: TransferLimits,
+    total: u64,
+    window_start: Instant,
+    window_bytes: u64,
+}
+
+impl<R> LimitedReader<R> {
+    fn too_slow(&self) -> io::Error {
+        let (speed_limit, speed_time) = self.limits.speed_limit.unwrap();
+        io::Error::new(
+            io::ErrorKind::TimedOut,
+            format!(
+                "Transfer speed was below {}/s for {} seconds",
+                HumanBytes(speed_limit),
+                speed_time.as_secs()
+            ),
+        )
+    }
+}
+
+fn is_timeout(err: &io::Error) -> bool {
+    err.kind() == io::ErrorKind::TimedOut
+        || err
+            .get_ref()
+            .and_then(|err| err.downcast_ref::<reqwest::Error>())
+            .is_some_and(reqwest::Error::is_timeout)
+}
+
+impl<R: Read> Read for LimitedReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let len = match &self.limits.rate {
+            Some(rate) => buf.len().min(rate.max_read()),
+            None => buf.len(),
+        };
+        let n = match self.inner.read(&mut buf[..len]) {
+            Err(err) if self.limits.speed_limit.is_some() && is_timeout(&err) => {
+                return Err(self.too_slow());
+            }
+            result => result?,
+        };
+
+        self.total += n as u64;
+        if let Some(max_filesize) = self.limits.max_filesize {
+            if self.total > max_filesize {
+                return Err(io::Error::other(format!(
+                    "Response body is larger than --max-filesize ({})",
+                    HumanBytes(max_filesize)
+                )));
+            }
+        }
+
+        if let Some((speed_limit, speed_time)) = self.limits.speed_limit {
+            self.window_bytes += n as u64;
+            let elapsed = self.window_start.elapsed();
+            if elapsed >= speed_time {
+                if (self.window_bytes as f64) < speed_limit as f64 * elapsed.as_secs_f64() {
+                    return Err(self.too_slow());
+                }
+                self.window_start = Instant::now();
+                self.window_bytes = 0;
+            }
+        }
+
+        if let Some(rate) = &self.limits.rate {
+            rate.throttle(n);
+        }
+        Ok(n)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Produces one byte at a time, slowly.
+    struct Trickle(Duration);
+
+    impl Read for Trickle {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            thread::sleep(self.0);
+            buf[0] = b'x';
+            Ok(1)
+        }
+    }
+
+    #[test]
+    fn max_filesize() {
+        let limits = TransferLimits::new(None, None, None, Some(5));
+        assert!(limits.check_length(Some(5)).is_ok());
+        assert!(limits.check_length(None).is_ok());
+        assert_eq!(
+            limits.check_length(Some(6)).unwrap_err().to_string(),
+            "Response body of 6 B is larger than --max-filesize (5 B)"
+        );
+
+        let mut out = Vec::new();
+        let err = limits
+            .wrap(&b"0123456789"[..])
+            .read_to_end(&mut out)
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Response body is larger than --max-filesize (5 B)"
+        );
+    }
+
+    #[test]
+    fn speed_limit() {
+        let mut limits = TransferLimits::new(None, Some(1000), None, None);
+        assert_eq!(limits.stall_timeout(), Some(Duration::from_secs(30)));
+        limits.speed_limit = Some((1000, Duration::from_millis(100)));
+
+        let mut reader = limits.wrap(Trickle(Duration::from_millis(20)));
+        let err = io::copy(&mut reader, &mut io::sink()).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+        assert_eq!(
+            err.to_string(),
+            "Transfer speed was below 1000 B/s for 0 seconds"
+        );
+    }
+
+    #[test]
+    fn limit_rate() {
+        let limits = TransferLimits::new(Some(8 * 1024), None, None, None);
+        let starting_time = Instant::now();
+        let mut out = Vec::new();
+        limits.wrap(&[0; 2048][..]).read_to_end(&mut out).unwrap();
+        assert_eq!(out.len(), 2048);
+        assert!(starting_time.elapsed() >= Duration::from_millis(200));
+    }
+}
@@ -124,6 +124,21 @@ pub fn expand_tilde(path: impl AsRef<Path>) -> PathBuf {
     }
 }
 
+/// Build a command that runs `command` through the platform's shell.
+pub fn shell_command(command: &str) -> std::process::Command {
+    let mut shell = if cfg!(windows) {
+        let mut shell = std::process::Command::new("cmd");
+        shell.arg("/C");
+        shell
+    } else {
+        let mut shell = std::process::Command::new("sh");
+        shell.arg("-c");
+        shell
+    };
+    shell.arg(command);
+    shell
+}
+
 pub fn url_with_query(mut url: Url, query: &[(&str, Cow<str>)]) -> Url {
     if !query.is_empty() {
         // If we run this even without adding pairs it adds a `?`, hence
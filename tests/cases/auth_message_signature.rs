@@ -112,6 +112,53 @@ fn message_signature_redirect_follow_re_signs_request() {
         .success();
 }
 
+#[test]
+fn message_signature_input_file_signs_each_url() {
+    let key = KEY_MATERIAL;
+    let key_id = "my-key";
+
+    let server = server::http(move |mut req| {
+        let key_inner = key.to_string();
+        let key_id_inner = key_id.to_string();
+        async move {
+            reconstruct_absolute_uri(&mut req);
+
+            use base64::Engine;
+            let key_base64 = STANDARD.encode(&key_inner);
+            let shared_key =
+                SharedKey::from_base64(&AlgorithmName::HmacSha256, &key_base64).unwrap();
+
+            use httpsig_hyper::MessageSignatureReq;
+            let result = req
+                .verify_message_signature(&shared_key, Some(&key_id_inner))
+                .await;
+            assert!(
+                result.is_ok(),
+                "Signature verification failed for {}: {:?}",
+                req.uri(),
+                result.err()
+            );
+
+            hyper::Response::new("data".into())
+        }
+    });
+
+    let dir = tempfile::tempdir().unwrap();
+    get_command()
+        .current_dir(&dir)
+        .arg("--unstable-m-sig-id=my-key")
+        .arg(format!("--unstable-m-sig-key={}", key))
+        .arg("--unstable-m-sig-comp=@method,@authority,@path")
+        .args(["--download", "--input-file=-"])
+        .write_stdin(format!(
+            "{}\n{}\n",
+            server.url("/one.txt"),
+            server.url("/two.txt")
+        ))
+        .assert()
+        .success();
+}
+
 #[test]
 fn message_signature_auth_defaults() {
     let key = KEY_MATERIAL;
@@ -706,3 +753,60 @@ fn message_signature_auth_rsa_pem_requires_explicit_algorithm() {
             "RSA private keys require an explicit algorithm",
         ));
 }
+
+#[test]
+fn message_signature_cavage_verification_on_server() {
+    let server = server::http(|req| async move {
+        let signature = req.headers()["Signature"].to_str().unwrap();
+        assert!(!req.headers().contains_key("Signature-Input"));
+        assert!(signature.starts_with(
+            r#"keyId="my-key",algorithm="hmac-sha256",headers="(request-target) host date digest",signature=""#
+        ));
+
+        let signing_string = format!(
+            "(request-target): post /pay?id=1\nhost: {}\ndate: {}\ndigest: {}",
+            req.headers()["Host"].to_str().unwrap(),
+            req.headers()["Date"].to_str().unwrap(),
+            req.headers()["Digest"].to_str().unwrap(),
+        );
+        use base64::Engine;
+        let shared_key =
+            SharedKey::from_base64(&AlgorithmName::HmacSha256, &STANDARD.encode(KEY_MATERIAL))
+                .unwrap();
+        let expected = STANDARD.encode(shared_key.sign(signing_string.as_bytes()).unwrap());
+        assert!(signature.ends_with(&format!(r#"signature="{expected}""#)));
+
+        hyper::Response::default()
+    });
+
+    get_command()
+        .arg("--unstable-m-sig-id=my-key")
+        .arg(format!("--unstable-m-sig-key={}", KEY_MATERIAL))
+        .arg("--unstable-m-sig-scheme=cavage")
+        .arg("--unstable-m-sig-comp=(request-target),host,date,digest")
+        .arg("-v")
+        .arg("post")
+        .arg(server.url("/pay?id=1"))
+        .arg("foo=bar")
+        .assert()
+        .success()
+        .stdout(predicates::str::contains("Signature: keyId=\"my-key\""))
+        .stdout(predicates::str::contains("Digest: SHA-256="));
+}
+
+#[test]
+fn message_signature_cavage_rejects_derived_components() {
+    get_command()
+        .arg("--unstable-m-sig-id=my-key")
+        .arg(format!("--unstable-m-sig-key={}", KEY_MATERIAL))
+        .arg("--unstable-m-sig-scheme=cavage")
+        .arg("--unstable-m-sig-comp=@method")
+        .arg("--offline")
+        .arg("get")
+        .arg("http://example.com")
+        .assert()
+        .failure()
+        .stderr(predicates::str::contains(
+            "is not supported by the cavage scheme",
+        ));
+}
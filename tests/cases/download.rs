@@ -1,6 +1,8 @@
 use std::{
     fs::{self, OpenOptions},
-    io::Write,
+    io::{Read, Write},
+    net::TcpListener,
+    thread,
 };
 
 use predicates::str::contains;
@@ -489,3 +491,670 @@ fn error_code_416_is_not_ignored_when_not_resuming_download() {
 
     assert_eq!(fs::exists(filename).unwrap(), false);
 }
+
+const SEGMENTED_BODY: &str = "0123456789abcdefghijklmnopqrstuvwxyz";
+
+/// Serve SEGMENTED_BODY, honoring single byte ranges (including open-ended ones).
+fn range_response<T>(
+    req: &hyper::Request<T>,
+) -> hyper::Response<http_body_util::Full<hyper::body::Bytes>> {
+    let builder = hyper::Response::builder()
+        .header(hyper::header::ACCEPT_RANGES, "bytes")
+        .header(hyper::header::ETAG, "\"v1\"");
+    match req.headers().get(hyper::header::RANGE) {
+        Some(range) => {
+            let (start, last) = range
+                .to_str()
+                .unwrap()
+                .strip_prefix("bytes=")
+                .unwrap()
+                .split_once('-')
+                .unwrap();
+            let start: usize = start.parse().unwrap();
+            let last = match last {
+                "" => SEGMENTED_BODY.len() - 1,
+                last => last.parse().unwrap(),
+            };
+            builder
+                .status(206)
+                .header(
+                    hyper::header::CONTENT_RANGE,
+                    format!("bytes {start}-{last}/{}", SEGMENTED_BODY.len()),
+                )
+                .body(SEGMENTED_BODY[start..=last].into())
+                .unwrap()
+        }
+        None => builder.body(SEGMENTED_BODY.into()).unwrap(),
+    }
+}
+
+#[test]
+fn segmented_download() {
+    let server = server::http(|req| async move {
+        if let Some(range) = req.headers().get(hyper::header::RANGE) {
+            assert!(
+                ["bytes=0-8", "bytes=9-17", "bytes=18-26", "bytes=27-35"]
+                    .contains(&range.to_str().unwrap())
+            );
+        }
+        range_response(&req)
+    });
+
+    let dir = tempdir().unwrap();
+    let outfile = dir.path().join("outfile");
+    get_command()
+        .args(["--download", "--segments=4", "--output"])
+        .arg(&outfile)
+        .arg(server.base_url())
+        .assert()
+        .success()
+        .stderr(contains("in 4 segments"));
+    server.assert_hits(5);
+
+    assert_eq!(fs::read_to_string(&outfile).unwrap(), SEGMENTED_BODY);
+    assert!(!dir.path().join("outfile.xh-segments").exists());
+}
+
+#[test]
+fn segmented_download_falls_back_without_range_support() {
+    let server = server::http(|req| async move {
+        assert!(req.headers().get(hyper::header::RANGE).is_none());
+        hyper::Response::builder()
+            .body("file contents\n".into())
+            .unwrap()
+    });
+
+    let dir = tempdir().unwrap();
+    let outfile = dir.path().join("outfile");
+    get_command()
+        .args(["--download", "--segments=4", "--output"])
+        .arg(&outfile)
+        .arg(server.base_url())
+        .assert()
+        .success()
+        .stderr(contains("doesn't support range requests"));
+    server.assert_hits(1);
+
+    assert_eq!(fs::read_to_string(&outfile).unwrap(), "file contents\n");
+}
+
+#[test]
+fn segmented_download_can_be_resumed() {
+    let server = server::http(|req| async move {
+        if let Some(range) = req.headers().get(hyper::header::RANGE) {
+            assert!(["bytes=5-17", "bytes=30-35"].contains(&range.to_str().unwrap()));
+        }
+        range_response(&req)
+    });
+
+    let dir = tempdir().unwrap();
+    let outfile = dir.path().join("outfile");
+    let mut partial = SEGMENTED_BODY.as_bytes().to_vec();
+    partial[5..18].fill(b'_');
+    partial[30..].fill(b'_');
+    fs::write(&outfile, partial).unwrap();
+    fs::write(
+        dir.path().join("outfile.xh-segments"),
+        r#"{
+            "length": 36,
+            "validator": "\"v1\"",
+            "segments": [
+                { "start": 0, "end": 18, "done": 5 },
+                { "start": 18, "end": 36, "done": 12 }
+            ]
+        }"#,
+    )
+    .unwrap();
+
+    get_command()
+        .args(["--download", "--continue", "--segments=4", "--output"])
+        .arg(&outfile)
+        .arg(server.base_url())
+        .assert()
+        .success()
+        .stderr(contains("in 2 segments"));
+    server.assert_hits(3);
+
+    assert_eq!(fs::read_to_string(&outfile).unwrap(), SEGMENTED_BODY);
+    assert!(!dir.path().join("outfile.xh-segments").exists());
+}
+
+#[test]
+fn segmented_download_continues_partial_file() {
+    use predicates::boolean::PredicateBooleanExt;
+
+    let server = server::http(|req| async move {
+        assert_eq!(req.headers()[hyper::header::RANGE], "bytes=10-");
+        range_response(&req)
+    });
+
+    // Without a state file this is a plain --continue
+    let dir = tempdir().unwrap();
+    let outfile = dir.path().join("outfile");
+    fs::write(&outfile, &SEGMENTED_BODY[..10]).unwrap();
+    get_command()
+        .args(["--download", "--continue", "--segments=4", "--output"])
+        .arg(&outfile)
+        .arg(server.base_url())
+        .assert()
+        .success()
+        .stderr(contains("range requests").not());
+    server.assert_hits(1);
+
+    assert_eq!(fs::read_to_string(&outfile).unwrap(), SEGMENTED_BODY);
+}
+
+const HELLO_SHA256: &str = "2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824";
+
+#[test]
+fn download_checksum() {
+    let server =
+        server::http(
+            |_req| async move { hyper::Response::builder().body("hello".into()).unwrap() },
+        );
+
+    let dir = tempdir().unwrap();
+    let outfile = dir.path().join("outfile");
+    get_command()
+        .args(["--download", "--output"])
+        .arg(&outfile)
+        .arg(format!("--checksum=sha256:{HELLO_SHA256}"))
+        .arg(server.base_url())
+        .assert()
+        .success();
+    assert_eq!(fs::read_to_string(&outfile).unwrap(), "hello");
+
+    get_command()
+        .args(["--download", "--output"])
+        .arg(&outfile)
+        .arg("--checksum=md5:00000000000000000000000000000000")
+        .arg(server.base_url())
+        .assert()
+        .failure()
+        .stderr(contains("is corrupt and was deleted"))
+        .stderr(contains(
+            "md5 checksum mismatch (from --checksum): expected 00000000000000000000000000000000, \
+            got 5d41402abc4b2a76b9719d911017c592",
+        ));
+    assert!(!outfile.exists());
+}
+
+#[test]
+fn download_checksum_from_headers() {
+    let server = server::http(|req| async move {
+        let builder = hyper::Response::builder();
+        match req.uri().path() {
+            "/good" => builder.header(
+                "Repr-Digest",
+                "sha-256=:LPJNul+wow4m6DsqxbninhsWHlwfp0JecwQzYpOLmCQ=:",
+            ),
+            _ => builder.header("Content-MD5", "AAAAAAAAAAAAAAAAAAAAAA=="),
+        }
+        .body("hello".into())
+        .unwrap()
+    });
+
+    let dir = tempdir().unwrap();
+    let outfile = dir.path().join("outfile");
+    get_command()
+        .args(["--download", "--output"])
+        .arg(&outfile)
+        .arg(server.url("/good"))
+        .assert()
+        .success();
+    assert_eq!(fs::read_to_string(&outfile).unwrap(), "hello");
+
+    get_command()
+        .args(["--download", "--output"])
+        .arg(&outfile)
+        .arg(server.url("/bad"))
+        .assert()
+        .failure()
+        .stderr(contains("md5 checksum mismatch (from Content-MD5)"));
+    assert!(!outfile.exists());
+}
+
+#[test]
+fn resumed_download_checksum_covers_whole_file() {
+    let server = server::http(|_req| async move {
+        hyper::Response::builder()
+            .status(206)
+            .header(hyper::header::CONTENT_RANGE, "bytes 2-4/5")
+            .body("llo".into())
+            .unwrap()
+    });
+
+    let dir = tempdir().unwrap();
+    let outfile = dir.path().join("outfile");
+    fs::write(&outfile, "he").unwrap();
+    get_command()
+        .args(["--download", "--continue", "--output"])
+        .arg(&outfile)
+        .arg(format!("--checksum=sha256:{HELLO_SHA256}"))
+        .arg(server.base_url())
+        .assert()
+        .success();
+    assert_eq!(fs::read_to_string(&outfile).unwrap(), "hello");
+}
+
+#[test]
+fn segmented_download_checksum() {
+    let server = server::http(|req| async move { range_response(&req) });
+
+    let dir = tempdir().unwrap();
+    let outfile = dir.path().join("outfile");
+    get_command()
+        .args(["--download", "--segments=3", "--output"])
+        .arg(&outfile)
+        .arg("--checksum=sha256:0000000000000000000000000000000000000000000000000000000000000000")
+        .arg(server.base_url())
+        .assert()
+        .failure()
+        .stderr(contains("sha256 checksum mismatch"));
+    server.assert_hits(4);
+    assert!(!outfile.exists());
+}
+
+#[test]
+fn download_to_output_dir() {
+    let server = server::http(|_req| async move {
+        hyper::Response::builder()
+            .body("file contents\n".into())
+            .unwrap()
+    });
+
+    let dir = tempdir().unwrap();
+    redirecting_command()
+        .args(["--download", "--output-dir"])
+        .arg(dir.path())
+        .arg(server.url("/foo.txt"))
+        .assert()
+        .success()
+        .stdout("");
+    assert_eq!(
+        fs::read_to_string(dir.path().join("foo.txt")).unwrap(),
+        "file contents\n"
+    );
+}
+
+#[test]
+fn download_input_file() {
+    let server = server::http(|req| async move {
+        match req.uri().path() {
+            "/missing" => hyper::Response::builder()
+                .status(404)
+                .body("".into())
+                .unwrap(),
+            "/named" => hyper::Response::builder()
+                .header("Content-Disposition", "attachment; filename=\"report.csv\"")
+                .body("a,b\n".into())
+                .unwrap(),
+            path => hyper::Response::builder()
+                .body(format!("contents of {path}").into())
+                .unwrap(),
+        }
+    });
+
+    let dir = tempdir().unwrap();
+    let list = dir.path().join("urls.txt");
+    fs::write(
+        &list,
+        format!(
+            "# Files to fetch\n{}\n\n{}\n{}\n{}\n",
+            server.url("/one.txt"),
+            server.url("/named"),
+            server.url("/missing"),
+            server.url("/dir/one.txt"),
+        ),
+    )
+    .unwrap();
+    let out = dir.path().join("dist");
+
+    get_command()
+        .args(["--download", "--parallel=2", "--input-file"])
+        .arg(&list)
+        .arg("--output-dir")
+        .arg(&out)
+        .assert()
+        .failure()
+        .stderr(contains("Failed"))
+        .stderr(contains("HTTP 404 Not Found"))
+        .stderr(contains("3 downloaded, 0 skipped, 1 failed"));
+
+    // Which of the two gets the suffix depends on timing
+    let mut contents = [
+        fs::read_to_string(out.join("one.txt")).unwrap(),
+        fs::read_to_string(out.join("one.txt-1")).unwrap(),
+    ];
+    contents.sort();
+    assert_eq!(
+        contents,
+        ["contents of /dir/one.txt", "contents of /one.txt"]
+    );
+    assert_eq!(fs::read_to_string(out.join("report.csv")).unwrap(), "a,b\n");
+    assert!(!out.join("missing").exists());
+}
+
+#[test]
+fn download_input_file_skips_complete_files() {
+    let server = server::http(|req| async move {
+        match req.uri().path() {
+            "/done.txt" => {
+                assert_eq!(req.headers()[hyper::header::RANGE], "bytes=5-");
+                hyper::Response::builder()
+                    .status(416)
+                    .body("".into())
+                    .unwrap()
+            }
+            _ => {
+                assert_eq!(req.headers()[hyper::header::RANGE], "bytes=5-");
+                hyper::Response::builder()
+                    .status(206)
+                    .header(hyper::header::CONTENT_RANGE, "bytes 5-11/12")
+                    .body(" world\n".into())
+                    .unwrap()
+            }
+        }
+    });
+
+    let dir = tempdir().unwrap();
+    fs::write(dir.path().join("done.txt"), "hello").unwrap();
+    fs::write(dir.path().join("partial.txt"), "Hello").unwrap();
+
+    get_command()
+        .current_dir(&dir)
+        .args(["--download", "--input-file=-"])
+        .write_stdin(format!(
+            "{}\n{}\n",
+            server.url("/done.txt"),
+            server.url("/partial.txt")
+        ))
+        .assert()
+        .success()
+        .stderr(contains("Skipped"))
+        .stderr(contains("1 downloaded, 1 skipped, 0 failed"));
+
+    assert_eq!(
+        fs::read_to_string(dir.path().join("done.txt")).unwrap(),
+        "hello"
+    );
+    assert_eq!(
+        fs::read_to_string(dir.path().join("partial.txt")).unwrap(),
+        "Hello world\n"
+    );
+}
+
+#[test]
+fn download_input_file_keeps_existing_files() {
+    let server = server::http(|req| async move {
+        if let Some(range) = req.headers().get(hyper::header::RANGE) {
+            assert_eq!(req.uri().path(), "/a/one.txt");
+            assert_eq!(range, "bytes=5-");
+            return hyper::Response::builder()
+                .status(206)
+                .header(hyper::header::CONTENT_RANGE, "bytes 5-11/12")
+                .body(" world\n".into())
+                .unwrap();
+        }
+        match req.uri().path() {
+            "/named" => hyper::Response::builder()
+                .header("Content-Disposition", "attachment; filename=\"report.csv\"")
+                .body("a,b\n".into())
+                .unwrap(),
+            path => hyper::Response::builder()
+                .body(format!("contents of {path}").into())
+                .unwrap(),
+        }
+    });
+
+    let dir = tempdir().unwrap();
+    fs::write(dir.path().join("one.txt"), "Hello").unwrap();
+    fs::write(dir.path().join("report.csv"), "old report").unwrap();
+
+    get_command()
+        .current_dir(&dir)
+        .args(["--download", "--input-file=-"])
+        .write_stdin(format!(
+            "{}\n{}\n{}\n",
+            server.url("/a/one.txt"),
+            server.url("/b/one.txt"),
+            server.url("/named"),
+        ))
+        .assert()
+        .success()
+        .stderr(contains("3 downloaded, 0 skipped, 0 failed"));
+
+    // Only the first URL resumes one.txt, the others get new files
+    let read = |name: &str| fs::read_to_string(dir.path().join(name)).unwrap();
+    assert_eq!(read("one.txt"), "Hello world\n");
+    assert_eq!(read("one.txt-1"), "contents of /b/one.txt");
+    assert_eq!(read("report.csv"), "old report");
+    assert_eq!(read("report.csv-1"), "a,b\n");
+}
+
+#[test]
+fn download_input_file_uses_netrc_for_each_url() {
+    let server = server::http(|req| async move {
+        assert_eq!(req.headers()["Authorization"], "Basic dXNlcjpwYXNz");
+        hyper::Response::builder().body("secret\n".into()).unwrap()
+    });
+
+    let dir = tempdir().unwrap();
+    let netrc = dir.path().join("netrc");
+    fs::write(
+        &netrc,
+        format!("machine {}\nlogin user\npassword pass\n", server.host()),
+    )
+    .unwrap();
+
+    get_command()
+        .current_dir(&dir)
+        .env("NETRC", &netrc)
+        .args(["--download", "--input-file=-"])
+        .write_stdin(format!("{}\n", server.url("/secret.txt")))
+        .assert()
+        .success()
+        .stderr(contains("1 downloaded, 0 skipped, 0 failed"));
+
+    assert_eq!(
+        fs::read_to_string(dir.path().join("secret.txt")).unwrap(),
+        "secret\n"
+    );
+}
+
+#[cfg(unix)]
+#[test]
+fn download_input_file_runs_passwordcmd_once() {
+    let server = server::http(|req| async move {
+        assert_eq!(req.headers()["Authorization"], "Basic dXNlcjpwYXNz");
+        hyper::Response::builder().body("secret\n".into()).unwrap()
+    });
+
+    let dir = tempdir().unwrap();
+    let netrc = dir.path().join("netrc");
+    fs::write(
+        &netrc,
+        format!(
+            "machine {}\nlogin user\npasswordcmd echo run >> runs; printf pass\n",
+            server.host()
+        ),
+    )
+    .unwrap();
+
+    get_command()
+        .current_dir(&dir)
+        .env("NETRC", &netrc)
+        .args(["--download", "--input-file=-"])
+        .write_stdin(format!(
+            "{}\n{}\n",
+            server.url("/one.txt"),
+            server.url("/two.txt")
+        ))
+        .assert()
+        .success()
+        .stderr(contains("2 downloaded, 0 skipped, 0 failed"));
+
+    assert_eq!(
+        fs::read_to_string(dir.path().join("runs")).unwrap(),
+        "run\n"
+    );
+}
+
+#[test]
+fn download_input_file_rejects_retry() {
+    get_command()
+        .args(["--download", "--input-file=-", "--retry=3"])
+        .assert()
+        .failure()
+        .stderr(contains("cannot be used with '--retry <N>'"));
+}
+
+#[test]
+fn download_max_filesize() {
+    let server = server::http(|_req| async move {
+        hyper::Response::builder()
+            .body("file contents\n".into())
+            .unwrap()
+    });
+
+    let dir = tempdir().unwrap();
+    let outfile = dir.path().join("outfile");
+    get_command()
+        .args(["--download", "--max-filesize=10", "--output"])
+        .arg(&outfile)
+        .arg(server.base_url())
+        .assert()
+        .failure()
+        .stderr(contains(
+            "Response body of 14 B is larger than --max-filesize (10 B)",
+        ));
+    assert!(!outfile.exists());
+}
+
+/// Serve each of `responses` on its own connection, which is closed right after.
+///
+/// Unlike the hyper server this can send less of the body than announced.
+/// Returns the URL and a handle that yields the request heads.
+fn raw_server(responses: Vec<String>) -> (String, thread::JoinHandle<Vec<String>>) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}/", listener.local_addr().unwrap());
+    let handle = thread::spawn(move || {
+        let mut requests = Vec::new();
+        for response in responses {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut head = Vec::new();
+            let mut byte = [0];
+            while !head.ends_with(b"\r\n\r\n") {
+                stream.read_exact(&mut byte).unwrap();
+                head.push(byte[0]);
+            }
+            requests.push(String::from_utf8(head).unwrap().to_lowercase());
+            stream.write_all(response.as_bytes()).unwrap();
+        }
+        requests
+    });
+    (url, handle)
+}
+
+/// The first 10 bytes of SEGMENTED_BODY, followed by a dropped connection.
+fn truncated_response(etag: &str) -> String {
+    format!(
+        "HTTP/1.1 200 OK\r\nETag: {etag}\r\nContent-Length: {}\r\n\r\n{}",
+        SEGMENTED_BODY.len(),
+        &SEGMENTED_BODY[..10]
+    )
+}
+
+#[test]
+fn download_retry_resumes_truncated_body() {
+    let (url, server) = raw_server(vec![
+        truncated_response("\"v1\""),
+        format!(
+            "HTTP/1.1 206 Partial Content\r\nConnection: close\r\nETag: \"v1\"\r\n\
+             Content-Range: bytes 10-35/36\r\nContent-Length: 26\r\n\r\n{}",
+            &SEGMENTED_BODY[10..]
+        ),
+    ]);
+
+    let dir = tempdir().unwrap();
+    let outfile = dir.path().join("outfile");
+    get_command()
+        .args(["--download", "--continue", "--retry=1", "--output"])
+        .arg(&outfile)
+        .arg(url)
+        .assert()
+        .success()
+        .stderr(contains("resuming download"));
+    let requests = server.join().unwrap();
+    assert!(!requests[0].contains("range:"));
+    assert!(requests[1].contains("range: bytes=10-\r\n"));
+    assert!(requests[1].contains("if-range: \"v1\"\r\n"));
+    assert_eq!(fs::read_to_string(&outfile).unwrap(), SEGMENTED_BODY);
+}
+
+#[test]
+fn download_retry_restarts_changed_file() {
+    let (url, server) = raw_server(vec![
+        truncated_response("\"v1\""),
+        // If-Range doesn't match, so the whole new file is sent
+        "HTTP/1.1 200 OK\r\nConnection: close\r\nETag: \"v2\"\r\n\
+         Content-Length: 12\r\n\r\nnew contents"
+            .to_string(),
+    ]);
+
+    let dir = tempdir().unwrap();
+    let outfile = dir.path().join("outfile");
+    get_command()
+        .args(["--download", "--continue", "--retry=3", "--output"])
+        .arg(&outfile)
+        .arg(url)
+        .assert()
+        .success()
+        .stderr(contains("The file changed on the server"));
+    server.join().unwrap();
+    assert_eq!(fs::read_to_string(&outfile).unwrap(), "new contents");
+}
+
+#[test]
+fn download_retry_restarts_with_shorter_file() {
+    let (url, server) = raw_server(vec![
+        format!(
+            "HTTP/1.1 206 Partial Content\r\nETag: \"v1\"\r\n\
+             Content-Range: bytes 20-35/36\r\nContent-Length: 16\r\n\r\n{}",
+            &SEGMENTED_BODY[20..25]
+        ),
+        "HTTP/1.1 200 OK\r\nConnection: close\r\nETag: \"v2\"\r\n\
+         Content-Length: 3\r\n\r\nnew"
+            .to_string(),
+    ]);
+
+    let dir = tempdir().unwrap();
+    let outfile = dir.path().join("outfile");
+    fs::write(&outfile, &SEGMENTED_BODY[..20]).unwrap();
+    get_command()
+        .args(["--download", "--continue", "--retry=3", "--output"])
+        .arg(&outfile)
+        .arg(url)
+        .assert()
+        .success()
+        .stderr(contains("The file changed on the server"));
+    let requests = server.join().unwrap();
+    assert!(requests[0].contains("range: bytes=20-\r\n"));
+    assert_eq!(fs::read_to_string(&outfile).unwrap(), "new");
+}
+
+#[test]
+fn download_retry_gives_up() {
+    let (url, server) = raw_server(vec![truncated_response("\"v1\""); 3]);
+
+    let dir = tempdir().unwrap();
+    let outfile = dir.path().join("outfile");
+    get_command()
+        .args(["--download", "--continue", "--retry=2", "--output"])
+        .arg(&outfile)
+        .arg(url)
+        .assert()
+        .failure();
+    assert_eq!(server.join().unwrap().len(), 3);
+    assert_eq!(fs::read_to_string(&outfile).unwrap(), &SEGMENTED_BODY[..10]);
+}
@@ -3,5 +3,6 @@ mod auth_message_signature;
 mod compress_request_body;
 mod download;
 mod logging;
+mod session_commands;
 mod unix_socket;
 mod xml;
@@ -0,0 +1,430 @@
+use std::fs;
+use std::path::Path;
+
+use predicates::str::contains;
+use serde_json::json;
+use tempfile::tempdir;
+
+use crate::prelude::*;
+
+fn write_session(config_dir: &Path, host_dir: &str, name: &str, content: &str) {
+    let dir = config_dir.join("sessions").join(host_dir);
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(dir.join(format!("{name}.json")), content).unwrap();
+}
+
+fn read_session(config_dir: &Path, host_dir: &str, name: &str) -> serde_json::Value {
+    let path = config_dir
+        .join("sessions")
+        .join(host_dir)
+        .join(format!("{name}.json"));
+    serde_json::from_str(&fs::read_to_string(path).unwrap()).unwrap()
+}
+
+const HTTPIE_SESSION: &str = r#"{
+    "__meta__": {
+        "about": "HTTPie session file",
+        "help": "https://httpie.org/doc#sessions",
+        "httpie": "2.3.0"
+    },
+    "auth": { "type": "basic", "raw_auth": "me:secret" },
+    "cookies": {
+        "sid": { "value": "abc", "path": "/" }
+    },
+    "headers": {
+        "X-Api-Key": "key",
+        "Accept": "application/json"
+    }
+}"#;
+
+#[test]
+fn list_sessions() {
+    let config_dir = tempdir().unwrap();
+    write_session(config_dir.path(), "localhost_8000", "dev", "{}");
+    write_session(config_dir.path(), "localhost_8000", "admin", "{}");
+    write_session(config_dir.path(), "example.org", "prod", "{}");
+
+    get_command()
+        .env("XH_CONFIG_DIR", config_dir.path())
+        .args(["sessions", "list"])
+        .assert()
+        .success()
+        .stdout(concat!(
+            "example.org     prod\n",
+            "localhost:8000  admin\n",
+            "localhost:8000  dev\n",
+        ));
+
+    get_command()
+        .env("XH_CONFIG_DIR", config_dir.path())
+        .args(["sessions", "list", ":8000"])
+        .assert()
+        .success()
+        .stdout("admin\ndev\n");
+}
+
+#[test]
+fn show_session_redacts_secrets() {
+    let config_dir = tempdir().unwrap();
+    write_session(config_dir.path(), "example.org", "prod", HTTPIE_SESSION);
+
+    let output = get_command()
+        .env("XH_CONFIG_DIR", config_dir.path())
+        .args(["sessions", "show", "example.org", "prod"])
+        .assert()
+        .success()
+        .get_output()
+        .stdout
+        .clone();
+
+    let shown: serde_json::Value = serde_json::from_slice(&output).unwrap();
+    assert_eq!(shown["auth"]["raw_auth"], "me:(redacted)");
+    assert_eq!(
+        shown["cookies"],
+        json!([{ "name": "sid", "value": "(redacted)", "path": "/" }])
+    );
+    let headers = shown["headers"].as_array().unwrap();
+    assert!(headers.contains(&json!({ "name": "X-Api-Key", "value": "(redacted)" })));
+    assert!(headers.contains(&json!({ "name": "Accept", "value": "application/json" })));
+
+    // Showing a session doesn't change it
+    assert_eq!(
+        read_session(config_dir.path(), "example.org", "prod")["__meta__"]["about"],
+        "HTTPie session file"
+    );
+}
+
+#[test]
+fn edit_session() {
+    let config_dir = tempdir().unwrap();
+    write_session(config_dir.path(), "example.org", "prod", HTTPIE_SESSION);
+
+    get_command()
+        .env("XH_CONFIG_DIR", config_dir.path())
+        .args(["sessions", "edit", "example.org", "prod"])
+        .args(["--remove-cookie=sid", "--remove-header=x-api-key"])
+        .args(["--rename", "staging"])
+        .assert()
+        .success();
+
+    assert!(
+        !config_dir
+            .path()
+            .join("sessions/example.org/prod.json")
+            .exists()
+    );
+    assert_eq!(
+        read_session(config_dir.path(), "example.org", "staging"),
+        json!({
+            "__meta__": { "about": "xh session file", "xh": "0.0.0" },
+            "auth": { "type": "basic", "raw_auth": "me:secret" },
+            "cookies": [],
+            "headers": [{ "name": "Accept", "value": "application/json" }]
+        })
+    );
+
+    get_command()
+        .env("XH_CONFIG_DIR", config_dir.path())
+        .args(["sessions", "edit", "example.org", "staging"])
+        .arg("--remove-cookie=sid")
+        .assert()
+        .failure()
+        .stderr(contains("Session \"staging\" has no cookie named \"sid\""));
+}
+
+#[cfg(unix)]
+#[test]
+fn edit_session_in_editor() {
+    let config_dir = tempdir().unwrap();
+    write_session(config_dir.path(), "example.org", "prod", HTTPIE_SESSION);
+
+    get_command()
+        .env("XH_CONFIG_DIR", config_dir.path())
+        .env_remove("VISUAL")
+        .env("EDITOR", "sed -i.bak s/application/text/")
+        .args(["sessions", "edit", "example.org", "prod"])
+        .assert()
+        .success();
+
+    let session = read_session(config_dir.path(), "example.org", "prod");
+    assert!(
+        session["headers"]
+            .as_array()
+            .unwrap()
+            .contains(&json!({ "name": "Accept", "value": "text/json" }))
+    );
+
+    // Quoted arguments are kept together
+    get_command()
+        .env("XH_CONFIG_DIR", config_dir.path())
+        .env_remove("VISUAL")
+        .env("EDITOR", "sed -i.bak 's/text\\/json/plain text/'")
+        .args(["sessions", "edit", "example.org", "prod"])
+        .assert()
+        .success();
+
+    let session = read_session(config_dir.path(), "example.org", "prod");
+    assert!(
+        session["headers"]
+            .as_array()
+            .unwrap()
+            .contains(&json!({ "name": "Accept", "value": "plain text" }))
+    );
+
+    get_command()
+        .env("XH_CONFIG_DIR", config_dir.path())
+        .env_remove("VISUAL")
+        .env("EDITOR", "sed -i.bak s/headers/oops/")
+        .args(["sessions", "edit", "example.org", "prod"])
+        .assert()
+        .failure()
+        .stderr(contains("is no longer a valid session"));
+}
+
+#[test]
+fn copy_and_remove_session() {
+    let config_dir = tempdir().unwrap();
+    write_session(
+        config_dir.path(),
+        "localhost_8000",
+        "dev",
+        r#"{
+            "__meta__": { "about": "xh session file", "xh": "0.0.0" },
+            "auth": {},
+            "cookies": [
+                { "name": "sid", "value": "abc", "path": "/", "domain": "localhost" },
+                { "name": "other", "value": "def", "path": "/", "domain": "example.com" }
+            ],
+            "headers": []
+        }"#,
+    );
+
+    get_command()
+        .env("XH_CONFIG_DIR", config_dir.path())
+        .args([
+            "sessions",
+            "cp",
+            "localhost:8000",
+            "dev",
+            "https://staging.test",
+        ])
+        .assert()
+        .success();
+
+    let copy = read_session(config_dir.path(), "staging.test", "dev");
+    assert_eq!(copy["cookies"][0]["domain"], "staging.test");
+    assert_eq!(copy["cookies"][1]["domain"], "example.com");
+
+    get_command()
+        .env("XH_CONFIG_DIR", config_dir.path())
+        .args(["sessions", "cp", "localhost:8000", "dev", "staging.test"])
+        .assert()
+        .failure()
+        .stderr(contains("already exists"));
+
+    get_command()
+        .env("XH_CONFIG_DIR", config_dir.path())
+        .args(["sessions", "rm", "localhost:8000", "dev"])
+        .assert()
+        .success();
+    assert!(!config_dir.path().join("sessions/localhost_8000").exists());
+
+    get_command()
+        .env("XH_CONFIG_DIR", config_dir.path())
+        .args(["sessions", "rm", "localhost:8000", "dev"])
+        .assert()
+        .failure()
+        .stderr(contains(
+            "Session \"dev\" for localhost:8000 does not exist",
+        ));
+}
+
+#[test]
+fn import_and_export_cookies() {
+    let config_dir = tempdir().unwrap();
+    let cookie_file = config_dir.path().join("cookies.txt");
+    fs::write(
+        &cookie_file,
+        ".example.org\tTRUE\t/\tTRUE\t4102444800\tsid\tabc\n",
+    )
+    .unwrap();
+
+    get_command()
+        .env("XH_CONFIG_DIR", config_dir.path())
+        .args(["sessions", "import-cookies", "example.org", "prod"])
+        .arg(&cookie_file)
+        .assert()
+        .success();
+
+    assert_eq!(
+        read_session(config_dir.path(), "example.org", "prod")["cookies"],
+        json!([{
+            "name": "sid",
+            "value": "abc",
+            "expires": 4102444800_i64,
+            "path": "/",
+            "secure": true,
+            "domain": "example.org"
+        }])
+    );
+
+    get_command()
+        .env("XH_CONFIG_DIR", config_dir.path())
+        .args(["sessions", "export-cookies", "example.org", "prod"])
+        .assert()
+        .success()
+        .stdout(contains(
+            ".example.org\tTRUE\t/\tTRUE\t4102444800\tsid\tabc\n",
+        ));
+}
+
+#[test]
+fn edit_session_options() {
+    let config_dir = tempdir().unwrap();
+    write_session(config_dir.path(), "example.org", "prod", HTTPIE_SESSION);
+
+    get_command()
+        .env("XH_CONFIG_DIR", config_dir.path())
+        .args(["sessions", "edit", "example.org", "prod"])
+        .args(["--set-option=verify=no", "--set-option=timeout=2.5"])
+        .args(["--set-option=query=page=1", "--set-option=query=limit=10"])
+        .assert()
+        .success();
+
+    assert_eq!(
+        read_session(config_dir.path(), "example.org", "prod")["options"],
+        json!({
+            "verify": "no",
+            "timeout": 2.5,
+            "query": [
+                { "name": "page", "value": "1" },
+                { "name": "limit", "value": "10" }
+            ]
+        })
+    );
+
+    get_command()
+        .env("XH_CONFIG_DIR", config_dir.path())
+        .args(["sessions", "edit", "example.org", "prod"])
+        .args([
+            "--unset-option=verify",
+            "--unset-option=timeout",
+            "--unset-option=query",
+        ])
+        .assert()
+        .success();
+    assert!(
+        read_session(config_dir.path(), "example.org", "prod")
+            .get("options")
+            .is_none()
+    );
+
+    get_command()
+        .env("XH_CONFIG_DIR", config_dir.path())
+        .args(["sessions", "edit", "example.org", "prod"])
+        .arg("--set-option=timeout=soon")
+        .assert()
+        .failure()
+        .stderr(contains("Connection timeout is not a valid number"));
+
+    get_command()
+        .env("XH_CONFIG_DIR", config_dir.path())
+        .args(["sessions", "edit", "example.org", "prod"])
+        .arg("--set-option=color=red")
+        .assert()
+        .failure()
+        .stderr(contains("Unknown session option \"color\""));
+}
+
+#[test]
+fn path_scoped_sessions() {
+    let server = server::http(|req| async move {
+        let expected = if req.uri().path().starts_with("/api/") {
+            "api"
+        } else {
+            "host"
+        };
+        assert_eq!(req.headers()["x-scope"], expected);
+        hyper::Response::default()
+    });
+    let host_dir = format!("127.0.0.1_{}", server.port());
+    let session = |scope: &str| {
+        json!({
+            "__meta__": { "about": "xh session file", "xh": "0.0.0" },
+            "auth": {},
+            "cookies": [],
+            "headers": [{ "name": "X-Scope", "value": scope }]
+        })
+        .to_string()
+    };
+
+    let config_dir = tempdir().unwrap();
+    write_session(config_dir.path(), &host_dir, "dev", &session("host"));
+    let api_host = format!("127.0.0.1:{}/api", server.port());
+    get_command()
+        .env("XH_CONFIG_DIR", config_dir.path())
+        .args([
+            "sessions",
+            "cp",
+            &api_host.replace("/api", ""),
+            "dev",
+            &api_host,
+        ])
+        .assert()
+        .success();
+    get_command()
+        .env("XH_CONFIG_DIR", config_dir.path())
+        .args([
+            "sessions",
+            "edit",
+            &api_host,
+            "dev",
+            "--remove-header=X-Scope",
+        ])
+        .assert()
+        .success();
+
+    get_command()
+        .env("XH_CONFIG_DIR", config_dir.path())
+        .args(["sessions", "list"])
+        .assert()
+        .success()
+        .stdout(format!(
+            "127.0.0.1:{port}      dev\n127.0.0.1:{port}/api  dev\n",
+            port = server.port()
+        ));
+
+    for (path, scope) in [("/api/users", "api"), ("/apiary", "host"), ("/", "host")] {
+        get_command()
+            .env("XH_CONFIG_DIR", config_dir.path())
+            .arg(server.url(path))
+            .arg(format!("x-scope:{scope}"))
+            .arg("--session=dev")
+            .assert()
+            .success();
+    }
+    // The header from the request was saved to the session for its path
+    assert_eq!(
+        read_session(config_dir.path(), &format!("{host_dir}/api"), "dev")["headers"][0]["value"],
+        "api"
+    );
+    assert_eq!(
+        read_session(config_dir.path(), &host_dir, "dev")["headers"][0]["value"],
+        "host"
+    );
+
+    get_command()
+        .env("XH_CONFIG_DIR", config_dir.path())
+        .args(["sessions", "rm", &api_host, "dev"])
+        .assert()
+        .success();
+    assert!(
+        !config_dir
+            .path()
+            .join("sessions")
+            .join(&host_dir)
+            .join("api")
+            .exists()
+    );
+    assert!(config_dir.path().join("sessions").join(&host_dir).exists());
+}
@@ -15,7 +15,7 @@ use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 use assert_cmd::cmd::Command;
 use http_body_util::BodyExt;
-use indoc::indoc;
+use indoc::{formatdoc, indoc};
 use predicates::function::function;
 use predicates::str::contains;
 use reqwest::header::HeaderValue;
@@ -90,6 +90,9 @@ fn get_base_command() -> Command {
     cmd.env("HOME", "");
     cmd.env("NETRC", "");
     cmd.env("XH_CONFIG_DIR", "");
+    cmd.env_remove("XH_SESSION_KEY");
+    cmd.env_remove("XH_SESSION_KEY_FILE");
+    cmd.env_remove("XH_SESSION_KEY_CMD");
     #[cfg(target_os = "windows")]
     cmd.env("XH_TEST_MODE_WIN_HOME_DIR", "");
     cmd.env("RUST_BACKTRACE", "0");
@@ -608,6 +611,21 @@ fn timeout_no_limit() {
         .success();
 }
 
+#[test]
+fn max_filesize() {
+    let server = server::http(|_req| async move {
+        hyper::Response::builder()
+            .body("0123456789".into())
+            .unwrap()
+    });
+
+    get_command()
+        .args(["--max-filesize=5", &server.base_url()])
+        .assert()
+        .failure()
+        .stderr(contains("is larger than --max-filesize (5 B)"));
+}
+
 #[test]
 fn timeout_invalid() {
     get_command()
@@ -784,6 +802,106 @@ fn digest_auth() {
     server.assert_hits(2);
 }
 
+#[test]
+fn ntlm_auth() {
+    let server = server::http(|req| async move {
+        let authorization = req
+            .headers()
+            .get("Authorization")
+            .map(|value| value.to_str().unwrap().to_string())
+            .unwrap_or_default();
+        if authorization.starts_with("NTLM TlRMTVNTUAAB") {
+            hyper::Response::builder()
+                .status(401)
+                .header(
+                    "WWW-Authenticate",
+                    "NTLM TlRMTVNTUAACAAAAAAAAAAAAAAAFgomiASNFZ4mrze8AAAAAAAAAAA==",
+                )
+                .body("".into())
+                .unwrap()
+        } else if authorization.starts_with("NTLM TlRMTVNTUAAD") {
+            hyper::Response::builder()
+                .body("authenticated".into())
+                .unwrap()
+        } else {
+            hyper::Response::builder()
+                .status(401)
+                .header("WWW-Authenticate", "Negotiate")
+                .header("WWW-Authenticate", "NTLM")
+                .body("".into())
+                .unwrap()
+        }
+    });
+
+    get_command()
+        .arg("--auth-type=ntlm")
+        .arg("--auth=DOMAIN\\user:password")
+        .arg(server.base_url())
+        .assert()
+        .stdout(contains("HTTP/1.1 200 OK"));
+
+    server.assert_hits(3);
+}
+
+#[test]
+fn any_auth_prefers_digest() {
+    let server = server::http(|req| async move {
+        match req.headers().get("Authorization") {
+            None => hyper::Response::builder()
+                .status(401)
+                .header("WWW-Authenticate", r#"Basic realm="me@xh.com""#)
+                // Could be Kerberos-only, so Digest is the safer pick
+                .header("WWW-Authenticate", "Negotiate")
+                .header("WWW-Authenticate", r#"Digest realm="me@xh.com", nonce="e5051361f053723a807674177fc7022f", qop="auth", algorithm=MD5"#)
+                .body("".into())
+                .unwrap(),
+            Some(authorization) => {
+                assert!(authorization.to_str().unwrap().starts_with("Digest "));
+                hyper::Response::builder()
+                    .body("authenticated".into())
+                    .unwrap()
+            }
+        }
+    });
+
+    get_command()
+        .arg("--auth-type=any")
+        .arg("--auth=ahmed:12345")
+        .arg(server.base_url())
+        .assert()
+        .stdout(contains("HTTP/1.1 200 OK"));
+
+    server.assert_hits(2);
+}
+
+#[test]
+fn any_auth_falls_back_to_basic() {
+    let server = server::http(|req| async move {
+        match req.headers().get("Authorization") {
+            None => hyper::Response::builder()
+                .status(401)
+                .header("WWW-Authenticate", r#"Basic realm="me@xh.com""#)
+                .body("".into())
+                .unwrap(),
+            Some(authorization) => {
+                assert_eq!(authorization, "Basic YWhtZWQ6MTIzNDU=");
+                hyper::Response::builder()
+                    .body("authenticated".into())
+                    .unwrap()
+            }
+        }
+    });
+
+    get_command()
+        .arg("--auth-type=any")
+        .arg("--auth=ahmed:12345")
+        .arg(server.base_url())
+        .assert()
+        .stdout(contains("HTTP/1.1 200 OK"));
+
+    server.assert_hits(2);
+}
+
 #[cfg(feature = "online-tests")]
 #[test]
 fn successful_digest_auth() {
@@ -1030,6 +1148,192 @@ fn netrc_file_user_password_auth() {
     }
 }
 
+#[cfg(unix)]
+#[test]
+fn netrc_password_command() {
+    let server = server::http(|req| async move {
+        assert_eq!(req.headers()["Authorization"], "Basic dXNlcjpwYXNz");
+        hyper::Response::default()
+    });
+
+    let mut netrc = NamedTempFile::new().unwrap();
+    writeln!(
+        netrc,
+        "machine {}\nlogin user\npasswordcmd printf 'pass\\nother'",
+        server.host()
+    )
+    .unwrap();
+
+    get_command()
+        .env("NETRC", netrc.path())
+        .arg(server.base_url())
+        .assert()
+        .success();
+}
+
+#[test]
+fn netrc_file_overrides_env() {
+    let server = server::http(|req| async move {
+        assert_eq!(req.headers()["Authorization"], "Basic dXNlcjpwYXNz");
+        hyper::Response::default()
+    });
+
+    let mut netrc = NamedTempFile::new().unwrap();
+    writeln!(
+        netrc,
+        "machine {}\nlogin user\npassword pass",
+        server.host()
+    )
+    .unwrap();
+    let mut other_netrc = NamedTempFile::new().unwrap();
+    writeln!(
+        other_netrc,
+        "machine {}\nlogin other\npassword other",
+        server.host()
+    )
+    .unwrap();
+
+    get_command()
+        .env("NETRC", other_netrc.path())
+        .arg(format!("--netrc-file={}", netrc.path().display()))
+        .arg(server.base_url())
+        .assert()
+        .success();
+}
+
+#[cfg(unix)]
+#[test]
+fn netrc_decrypt_cmd() {
+    let server = server::http(|req| async move {
+        assert_eq!(req.headers()["Authorization"], "Basic dXNlcjpwYXNz");
+        hyper::Response::default()
+    });
+
+    // "Encrypted" with ROT13
+    let homedir = TempDir::new().unwrap();
+    fs::write(
+        homedir.path().join(".netrc.age"),
+        format!("znpuvar {}\nybtva hfre\ncnffjbeq cnff\n", server.host()),
+    )
+    .unwrap();
+
+    get_command()
+        .env("HOME", homedir.path())
+        .env_remove("NETRC")
+        .arg("--netrc-decrypt-cmd=tr a-z n-za-m")
+        .arg(server.base_url())
+        .assert()
+        .success();
+}
+
+#[test]
+fn netrc_age_requires_decrypt_cmd() {
+    use predicates::boolean::PredicateBooleanExt;
+
+    let homedir = TempDir::new().unwrap();
+    let netrc_path = homedir.path().join(".netrc.age");
+    fs::write(&netrc_path, "encrypted").unwrap();
+
+    // Encrypted files in the default locations are skipped
+    get_command()
+        .env("HOME", homedir.path())
+        .env("XH_TEST_MODE_WIN_HOME_DIR", homedir.path())
+        .env_remove("NETRC")
+        .args(["--offline", "--print=H", ":"])
+        .assert()
+        .success()
+        .stdout(contains("Authorization").not());
+
+    get_command()
+        .env_remove("NETRC")
+        .arg(format!("--netrc-file={}", netrc_path.display()))
+        .arg("--offline")
+        .arg(":")
+        .assert()
+        .failure()
+        .stderr(contains("use --netrc-decrypt-cmd to decrypt it"));
+}
+
+#[test]
+fn netrc_file_must_exist() {
+    let homedir = TempDir::new().unwrap();
+    get_command()
+        .arg(format!(
+            "--netrc-file={}",
+            homedir.path().join("missing").display()
+        ))
+        .arg("--offline")
+        .arg(":")
+        .assert()
+        .failure()
+        .stderr(contains("Failed to open netrc file"));
+}
+
+#[test]
+fn netrc_env_may_be_missing() {
+    let homedir = TempDir::new().unwrap();
+    get_command()
+        .env("NETRC", homedir.path().join("missing"))
+        .arg("--offline")
+        .arg(":")
+        .assert()
+        .success();
+}
+
+#[cfg(unix)]
+#[test]
+fn auth_cmd_bearer() {
+    let server = server::http(|req| async move {
+        assert_eq!(req.headers()["Authorization"], "Bearer token-from-cmd");
+        hyper::Response::default()
+    });
+
+    get_command()
+        .arg(server.base_url())
+        .arg("--auth-type=bearer")
+        .arg("--auth-cmd=echo token-from-cmd")
+        .assert()
+        .success();
+}
+
+#[cfg(unix)]
+#[test]
+fn auth_cmd_is_not_persisted_in_session() {
+    let server = server::http(|req| async move {
+        assert_eq!(req.headers()["Authorization"], "Basic dXNlcjpwYXNz");
+        hyper::Response::default()
+    });
+
+    let mut path_to_session = std::env::temp_dir();
+    path_to_session.push(random_string());
+
+    get_command()
+        .arg(server.base_url())
+        .arg("--auth=user")
+        .arg("--auth-cmd=echo pass")
+        .arg(format!("--session={}", path_to_session.to_string_lossy()))
+        .assert()
+        .success();
+
+    let session_content = fs::read_to_string(path_to_session).unwrap();
+    assert_eq!(
+        serde_json::from_str::<serde_json::Value>(&session_content).unwrap()["auth"],
+        serde_json::json!({ "type": null, "raw_auth": null })
+    );
+}
+
+#[test]
+fn auth_cmd_failure() {
+    get_command()
+        .arg("--offline")
+        .arg(":")
+        .arg("--auth-type=bearer")
+        .arg("--auth-cmd=exit 3")
+        .assert()
+        .failure()
+        .stderr(contains("Credential command \"exit 3\" failed"));
+}
+
 fn get_proxy_command(
     protocol_to_request: &str,
     protocol_to_proxy: &str,
@@ -1150,6 +1454,59 @@ fn proxy_multiple_valid_proxies() {
     cmd.assert().success();
 }
 
+#[test]
+fn proxy_basic_auth() {
+    let server = server::http(|req| async move {
+        assert_eq!(req.headers()["proxy-authorization"], "Basic dXNlcjpwYXNz");
+        hyper::Response::default()
+    });
+
+    get_proxy_command("http", "http", &server.base_url())
+        .arg("--proxy-auth=user:pass")
+        .assert()
+        .success();
+}
+
+#[test]
+fn proxy_digest_auth() {
+    let server = server::http(|req| async move {
+        match req.headers().get("proxy-authorization") {
+            None => hyper::Response::builder()
+                .status(407)
+                .header("Proxy-Authenticate", r#"Digest realm="proxy", nonce="e5051361f053723a807674177fc7022f", qop="auth", algorithm=MD5"#)
+                .body("".into())
+                .unwrap(),
+            Some(authorization) => {
+                let authorization = authorization.to_str().unwrap();
+                assert!(authorization.starts_with(r#"Digest username="user""#));
+                assert!(authorization.contains(r#"uri="http://example.test/get""#));
+                hyper::Response::default()
+            }
+        }
+    });
+
+    get_proxy_command("http", "http", &server.base_url())
+        .arg("--proxy-auth=user:pass")
+        .arg("--proxy-auth-type=digest")
+        .assert()
+        .success();
+
+    server.assert_hits(2);
+}
+
+#[test]
+fn noproxy_bypasses_proxy() {
+    let server = server::http(|_req| async move { hyper::Response::default() });
+
+    get_command()
+        .arg("--check-status")
+        .arg("--proxy=all:http://127.0.0.1:1")
+        .arg(format!("--noproxy=example.test,{}", server.host()))
+        .arg(server.base_url())
+        .assert()
+        .success();
+}
+
 // temporarily disabled for builds not using rustls
 #[cfg(all(feature = "online-tests", feature = "rustls"))]
 #[test]
@@ -2001,14 +2358,79 @@ fn colored_body() {
 }
 
 #[test]
-fn force_color_pipe() {
-    redirecting_command()
-        .arg("--ignore-stdin")
-        .arg("--offline")
-        .arg("--pretty=colors")
-        .arg(":")
-        .arg("x:=3")
-        .assert()
+fn custom_theme() {
+    let config_dir = tempdir().unwrap();
+    fs::create_dir(config_dir.path().join("themes")).unwrap();
+    fs::write(
+        config_dir.path().join("themes/light.tmTheme"),
+        indoc! {r#"
+            <?xml version="1.0" encoding="UTF-8"?>
+            <!DOCTYPE plist PUBLIC "-//Apple//DTD PLIST 1.0//EN" "http://www.apple.com/DTDs/PropertyList-1.0.dtd">
+            <plist version="1.0">
+            <dict>
+                <key>name</key>
+                <string>Light</string>
+                <key>settings</key>
+                <array>
+                    <dict>
+                        <key>settings</key>
+                        <dict>
+                            <key>foreground</key>
+                            <string>#000000</string>
+                        </dict>
+                    </dict>
+                    <dict>
+                        <key>scope</key>
+                        <string>constant.numeric</string>
+                        <key>settings</key>
+                        <dict>
+                            <key>foreground</key>
+                            <string>#AA0000</string>
+                        </dict>
+                    </dict>
+                    <dict>
+                        <key>scope</key>
+                        <string>support.variable.http</string>
+                        <key>settings</key>
+                        <dict>
+                            <key>foreground</key>
+                            <string>#0000AA</string>
+                        </dict>
+                    </dict>
+                </array>
+            </dict>
+            </plist>
+        "#},
+    )
+    .unwrap();
+
+    color_command()
+        .env("XH_CONFIG_DIR", config_dir.path())
+        .args(["--style=light", "--offline", ":", "x:=3"])
+        .assert()
+        .success()
+        // Header names, from the header palette
+        .stdout(contains("\x1b[38;2;0;0;170mUser-Agent"))
+        // JSON numbers, from the highlighter
+        .stdout(contains("\x1b[38;2;170;0;0m3"));
+
+    color_command()
+        .env("XH_CONFIG_DIR", config_dir.path())
+        .args(["--style=dark", "--offline", ":"])
+        .assert()
+        .failure()
+        .stderr(contains("Unknown theme 'dark'"));
+}
+
+#[test]
+fn force_color_pipe() {
+    redirecting_command()
+        .arg("--ignore-stdin")
+        .arg("--offline")
+        .arg("--pretty=colors")
+        .arg(":")
+        .arg("x:=3")
+        .assert()
         .success()
         .stdout(contains("\x1b[34m3\x1b[0m"));
 }
@@ -2391,6 +2813,235 @@ fn named_read_only_session() {
     );
 }
 
+#[test]
+fn session_options() {
+    let server = server::http(|req| async move {
+        assert_eq!(req.headers()["host"], "example.test");
+        assert_eq!(req.uri().path_and_query().unwrap(), "/get?api=2&page=3");
+        hyper::Response::default()
+    });
+
+    let session_file = NamedTempFile::new().unwrap();
+    std::fs::write(
+        &session_file,
+        serde_json::json!({
+            "__meta__": { "about": "xh session file", "xh": "0.0.0" },
+            "auth": {},
+            "cookies": [],
+            "headers": [],
+            "options": {
+                "timeout": 5,
+                "proxy": [format!("http:{}", server.base_url())],
+                "query": [
+                    { "name": "api", "value": "2" },
+                    { "name": "page", "value": "1" }
+                ]
+            }
+        })
+        .to_string(),
+    )
+    .unwrap();
+
+    get_command()
+        .arg("http://example.test/get")
+        .arg("page==3")
+        .arg(format!(
+            "--session={}",
+            session_file.path().to_string_lossy()
+        ))
+        .assert()
+        .success();
+
+    // Options from the command line win. The session's proxy would answer
+    // right away, and with the session's timeout this one would get to answer
+    let mut slow_proxy = server::http(|_req| async move {
+        tokio::time::sleep(Duration::from_secs(2)).await;
+        hyper::Response::default()
+    });
+    slow_proxy.disable_hit_checks();
+    get_command()
+        .arg("http://example.test/get?page=3")
+        .arg(format!("--proxy=http:{}", slow_proxy.base_url()))
+        .arg("--timeout=0.5")
+        .arg(format!(
+            "--session={}",
+            session_file.path().to_string_lossy()
+        ))
+        .assert()
+        .failure()
+        .stderr(contains("operation timed out"));
+
+    server.assert_hits(1);
+}
+
+#[test]
+fn concurrent_session_updates_are_merged() {
+    let server = server::http(|req| async move {
+        let name = &req.query_params()["name"];
+        hyper::Response::builder()
+            .header("set-cookie", format!("{name}=value"))
+            .body("".into())
+            .unwrap()
+    });
+
+    let session_dir = tempdir().unwrap();
+    let session_path = session_dir.path().join("session.json");
+    let session_arg = format!("--session={}", session_path.to_string_lossy());
+
+    let base_url = server.base_url();
+    std::thread::scope(|scope| {
+        for i in 0..8 {
+            let (base_url, session_arg) = (&base_url, &session_arg);
+            scope.spawn(move || {
+                get_command()
+                    .arg(base_url)
+                    .arg(format!("name==cookie{i}"))
+                    .arg(session_arg)
+                    .assert()
+                    .success();
+            });
+        }
+    });
+
+    let session_content = fs::read_to_string(&session_path).unwrap();
+    let session = serde_json::from_str::<serde_json::Value>(&session_content).unwrap();
+    let mut names: Vec<_> = session["cookies"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|cookie| cookie["name"].as_str().unwrap())
+        .collect();
+    names.sort();
+    assert_eq!(
+        names,
+        (0..8).map(|i| format!("cookie{i}")).collect::<Vec<_>>()
+    );
+
+    // The lock file doesn't stay behind
+    let files: Vec<_> = fs::read_dir(session_dir.path())
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name())
+        .collect();
+    assert_eq!(files, ["session.json"]);
+}
+
+#[test]
+fn cookie_jar() {
+    let server = server::http(|req| async move {
+        assert_eq!(req.headers()["cookie"], "lang=en");
+        hyper::Response::builder()
+            .header("set-cookie", "sid=abc; HttpOnly")
+            .body("".into())
+            .unwrap()
+    });
+
+    let cookie_jar = NamedTempFile::new().unwrap();
+    fs::write(
+        &cookie_jar,
+        "# Netscape HTTP Cookie File\n127.0.0.1\tFALSE\t/\tFALSE\t0\tlang\ten\n",
+    )
+    .unwrap();
+
+    get_command()
+        .arg(server.base_url())
+        .arg(format!(
+            "--cookie-jar={}",
+            cookie_jar.path().to_string_lossy()
+        ))
+        .assert()
+        .success();
+
+    let mut lines: Vec<_> = fs::read_to_string(&cookie_jar)
+        .unwrap()
+        .lines()
+        .filter(|line| !line.is_empty() && !line.starts_with("# "))
+        .map(String::from)
+        .collect();
+    lines.sort();
+    assert_eq!(
+        lines,
+        [
+            "#HttpOnly_127.0.0.1\tFALSE\t/\tFALSE\t0\tsid\tabc",
+            "127.0.0.1\tFALSE\t/\tFALSE\t0\tlang\ten",
+        ]
+    );
+
+    // --cookies only reads the file
+    fs::write(&cookie_jar, "127.0.0.1\tFALSE\t/\tFALSE\t0\tlang\ten\n").unwrap();
+    get_command()
+        .arg(server.base_url())
+        .arg(format!("--cookies={}", cookie_jar.path().to_string_lossy()))
+        .assert()
+        .success();
+    assert_eq!(
+        fs::read_to_string(&cookie_jar).unwrap(),
+        "127.0.0.1\tFALSE\t/\tFALSE\t0\tlang\ten\n"
+    );
+}
+
+#[test]
+fn cookie_jar_is_independent_of_session() {
+    let server = server::http(|req| async move {
+        let cookie = req.headers()["cookie"].to_str().unwrap().to_string();
+        let mut cookies: Vec<_> = cookie.split("; ").collect();
+        cookies.sort();
+        assert_eq!(cookies, ["lang=en", "user=ahmed"]);
+        hyper::Response::builder()
+            .header("set-cookie", "sid=abc")
+            .body("".into())
+            .unwrap()
+    });
+
+    let session_dir = TempDir::new().unwrap();
+    let session_path = session_dir.path().join("session.json");
+    fs::write(
+        &session_path,
+        serde_json::json!({
+            "__meta__": {},
+            "auth": {},
+            "cookies": [{ "name": "user", "value": "ahmed", "domain": "127.0.0.1", "path": "/" }],
+            "headers": []
+        })
+        .to_string(),
+    )
+    .unwrap();
+    let cookie_jar = session_dir.path().join("cookies.txt");
+    fs::write(&cookie_jar, "127.0.0.1\tFALSE\t/\tFALSE\t0\tlang\ten\n").unwrap();
+
+    get_command()
+        .arg(server.base_url())
+        .arg(format!("--session={}", session_path.to_string_lossy()))
+        .arg(format!("--cookie-jar={}", cookie_jar.to_string_lossy()))
+        .assert()
+        .success();
+
+    let session_content = fs::read_to_string(&session_path).unwrap();
+    let session = serde_json::from_str::<serde_json::Value>(&session_content).unwrap();
+    let mut names: Vec<_> = session["cookies"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|cookie| cookie["name"].as_str().unwrap())
+        .collect();
+    names.sort();
+    assert_eq!(names, ["sid", "user"]);
+
+    let mut lines: Vec<_> = fs::read_to_string(&cookie_jar)
+        .unwrap()
+        .lines()
+        .filter(|line| !line.is_empty() && !line.starts_with("# "))
+        .map(String::from)
+        .collect();
+    lines.sort();
+    assert_eq!(
+        lines,
+        [
+            "127.0.0.1\tFALSE\t/\tFALSE\t0\tlang\ten",
+            "127.0.0.1\tFALSE\t/\tFALSE\t0\tsid\tabc",
+        ]
+    );
+}
+
 #[test]
 fn expired_cookies_are_removed_from_session() {
     let future_timestamp = SystemTime::now()
@@ -2715,6 +3366,70 @@ fn bearer_auth_from_session_is_used() {
         .success();
 }
 
+#[test]
+fn encrypted_session() {
+    let server = server::http(|req| async move {
+        assert_eq!(req.headers()["authorization"], "Basic dXNlcjpwYXNz");
+        assert_eq!(req.headers()["hello"], "world");
+        hyper::Response::default()
+    });
+
+    let session_file = NamedTempFile::new().unwrap();
+    fs::remove_file(&session_file).unwrap();
+    let session_arg = format!("--session={}", session_file.path().to_string_lossy());
+
+    get_command()
+        .env("XH_SESSION_KEY", "hunter2")
+        .arg(server.base_url())
+        .args(["--auth=user:pass", "hello:world", &session_arg])
+        .assert()
+        .success();
+
+    let session_content = fs::read_to_string(&session_file).unwrap();
+    assert!(!session_content.contains("user:pass"));
+    assert!(!session_content.contains("world"));
+    let mut session = serde_json::from_str::<serde_json::Value>(&session_content).unwrap();
+    assert_eq!(
+        session["__meta__"],
+        serde_json::json!({ "about": "xh session file", "xh": "0.0.0" })
+    );
+    assert_eq!(session["encrypted"]["cipher"], "chacha20-poly1305");
+
+    // Auth and headers are decrypted and sent again
+    get_command()
+        .env("XH_SESSION_KEY_CMD", "echo hunter2")
+        .arg(server.base_url())
+        .arg(&session_arg)
+        .assert()
+        .success();
+    server.assert_hits(2);
+
+    get_command()
+        .arg(server.base_url())
+        .arg(&session_arg)
+        .assert()
+        .failure()
+        .stderr(contains("is encrypted, set XH_SESSION_KEY"));
+
+    get_command()
+        .env("XH_SESSION_KEY", "hunter3")
+        .arg(server.base_url())
+        .arg(&session_arg)
+        .assert()
+        .failure()
+        .stderr(contains("Wrong session key"));
+
+    session["encrypted"]["iterations"] = 1.into();
+    fs::write(&session_file, session.to_string()).unwrap();
+    get_command()
+        .env("XH_SESSION_KEY", "hunter2")
+        .arg(server.base_url())
+        .arg(&session_arg)
+        .assert()
+        .failure()
+        .stderr(contains("Invalid number of pbkdf2-sha256 iterations: 1"));
+}
+
 #[test]
 fn auth_netrc_is_not_persisted_in_session() {
     let server = server::http(|req| async move {
@@ -3838,6 +4553,85 @@ fn multiple_format_options_are_merged() {
         "#});
 }
 
+#[test]
+fn sorted_json_keys() {
+    let server = server::http(|_req| async move {
+        hyper::Response::builder()
+            .header("content-type", "application/json")
+            .body(r#"{"b": 1, "a": [{"d": 2, "c": 3}]}"#.into())
+            .unwrap()
+    });
+    for stream in ["--no-stream", "--stream"] {
+        get_command()
+            .args([
+                "--print=b",
+                stream,
+                "--format-options=json.sort_keys:true",
+                &server.base_url(),
+            ])
+            .assert()
+            .stdout(indoc! {r#"
+                {
+                    "a": [
+                        {
+                            "c": 3,
+                            "d": 2
+                        }
+                    ],
+                    "b": 1
+                }
+
+
+            "#});
+    }
+}
+
+#[test]
+fn sorted_and_unsorted() {
+    let server = server::http(|_req| async move {
+        hyper::Response::builder()
+            .header("X-Foo", "Bar")
+            .header("Date", "N/A")
+            .header("Content-Type", "application/json")
+            .body(r#"{"b":1,"a":2}"#.into())
+            .unwrap()
+    });
+    get_command()
+        .args(["--sorted", &server.base_url()])
+        .assert()
+        .stdout(indoc! {r#"
+            HTTP/1.1 200 OK
+            Content-Length: 13
+            Content-Type: application/json
+            Date: N/A
+            X-Foo: Bar
+
+            {
+                "a": 2,
+                "b": 1
+            }
+
+
+        "#});
+    get_command()
+        .args(["--sorted", "--unsorted", &server.base_url()])
+        .assert()
+        .stdout(indoc! {r#"
+            HTTP/1.1 200 OK
+            X-Foo: Bar
+            Date: N/A
+            Content-Type: application/json
+            Content-Length: 13
+
+            {
+                "b": 1,
+                "a": 2
+            }
+
+
+        "#});
+}
+
 #[test]
 fn reason_phrase_is_preserved() {
     let server = server::http(|_req| async move {
@@ -3859,3 +4653,607 @@ fn reason_phrase_is_preserved() {
 
         "#});
 }
+
+#[test]
+fn format_yaml() {
+    let server = server::http(|_req| async move {
+        hyper::Response::builder()
+            .header("content-type", "application/yaml")
+            .body("name: xh\nlist:\n- a\n- b: 1\n".into())
+            .unwrap()
+    });
+    get_command()
+        .args([
+            "--print=b",
+            "--format-options=yaml.indent:4",
+            &server.base_url(),
+        ])
+        .assert()
+        .stdout(indoc! {r#"
+            name: xh
+            list:
+                - a
+                - b: 1
+
+
+        "#});
+}
+
+#[test]
+fn csv_table() {
+    let server = server::http(|_req| async move {
+        hyper::Response::builder()
+            .header("content-type", "text/csv")
+            .body("name,size\nxh,5\n\"httpie, the original\",10\n".into())
+            .unwrap()
+    });
+    get_command()
+        .args(["--print=b", &server.base_url()])
+        .assert()
+        .stdout(indoc! {r#"
+            name                  size
+            xh                    5
+            httpie, the original  10
+
+
+        "#});
+    redirecting_command()
+        .args(["--print=b", &server.base_url()])
+        .assert()
+        .stdout("name,size\nxh,5\n\"httpie, the original\",10\n");
+}
+
+#[test]
+fn highlight_yaml_toml_csv_markdown() {
+    for (content_type, body) in [
+        ("application/yaml", "a: [1, 'two', {b: null}] # comment\n"),
+        ("application/toml", "[table]\nkey = \"value\"\nnumber = 1\n"),
+        ("text/csv", "a,b\n1,\"two\"\n"),
+        ("text/markdown", "# Title\n\nSome **bold** `code`\n"),
+    ] {
+        let server = server::http(move |_req| async move {
+            hyper::Response::builder()
+                .header("content-type", content_type)
+                .body(body.into())
+                .unwrap()
+        });
+        color_command()
+            .args(["--print=b", "--pretty=colors", &server.base_url()])
+            .assert()
+            .stdout(contains("\x1b["));
+    }
+}
+
+#[test]
+fn msgpack_response() {
+    let server = server::http(|_req| async move {
+        hyper::Response::builder()
+            .header("content-type", "application/msgpack")
+            // {"a": 1, "b": [true, nil]}
+            .body(b"\x82\xa1a\x01\xa1b\x92\xc3\xc0".as_slice().into())
+            .unwrap()
+    });
+    get_command()
+        .args(["--print=b", &server.base_url()])
+        .assert()
+        .stdout(indoc! {r#"
+            {
+                "a": 1,
+                "b": [
+                    true,
+                    null
+                ]
+            }
+
+
+        "#});
+    redirecting_command()
+        .args(["--print=b", &server.base_url()])
+        .assert()
+        .stdout(b"\x82\xa1a\x01\xa1b\x92\xc3\xc0".as_slice());
+}
+
+#[test]
+fn undecodable_cbor_response() {
+    let server = server::http(|_req| async move {
+        hyper::Response::builder()
+            .header("content-type", "application/cbor")
+            .body(b"\xa1\x61".as_slice().into())
+            .unwrap()
+    });
+    get_command()
+        .args(["--print=b", &server.base_url()])
+        .assert()
+        .stdout(BINARY_SUPPRESSOR);
+}
+
+#[test]
+fn msgpack_and_cbor_requests() {
+    for (flag, content_type, body) in [
+        (
+            "--msgpack",
+            "application/msgpack",
+            b"\x81\xa1a\x01".as_slice(),
+        ),
+        ("--cbor", "application/cbor", b"\xa1\x61a\x01".as_slice()),
+    ] {
+        let server = server::http(move |req| async move {
+            assert_eq!(req.headers()["content-type"], content_type);
+            assert_eq!(req.body().await, body);
+            hyper::Response::default()
+        });
+        get_command()
+            .args([flag, "post", &server.base_url(), "a:=1"])
+            .assert()
+            .success();
+    }
+
+    get_command()
+        .args(["--cbor", "--offline", "--print=B", ":", "a:=1"])
+        .assert()
+        .stdout(indoc! {r#"
+            {
+                "a": 1
+            }
+
+
+
+        "#});
+}
+
+const PERSON_PROTOBUF: &[u8] = b"\x0a\x03Ada\x10\x07\x1a\x03a@b\x22\x06\x0a\x04Oslo";
+
+#[test]
+fn protobuf_response() {
+    let server = server::http(|_req| async move {
+        hyper::Response::builder()
+            .header("content-type", "application/x-protobuf")
+            .body(PERSON_PROTOBUF.into())
+            .unwrap()
+    });
+    get_command()
+        .args([
+            "--print=b",
+            "--proto-descriptor=tests/fixtures/protobuf/person.pb",
+            "--proto-message=xh.test.Person",
+            &server.base_url(),
+        ])
+        .assert()
+        .stdout(indoc! {r#"
+            {
+                "name": "Ada",
+                "id": 7,
+                "emails": [
+                    "a@b"
+                ],
+                "address": {
+                    "city": "Oslo"
+                }
+            }
+
+
+        "#});
+    get_command()
+        .args(["--print=b", &server.base_url()])
+        .assert()
+        .stdout(indoc! {r#"
+            1: "Ada"
+            2: 7
+            3: "a@b"
+            4 {
+              1: "Oslo"
+            }
+
+        "#});
+}
+
+#[test]
+fn protobuf_request() {
+    let server = server::http(|req| async move {
+        assert_eq!(req.headers()["content-type"], "application/x-protobuf");
+        assert_eq!(req.body().await, b"\x0a\x03Ada\x10\x07");
+        hyper::Response::default()
+    });
+    get_command()
+        .args([
+            "--proto-descriptor=tests/fixtures/protobuf/person.pb",
+            "--proto-request=xh.test.Person",
+            "post",
+            &server.base_url(),
+            "name=Ada",
+            "id:=7",
+        ])
+        .assert()
+        .success();
+
+    get_command()
+        .args([
+            "--proto-descriptor=tests/fixtures/protobuf/person.pb",
+            "--proto-request=xh.test.Person",
+            "--offline",
+            ":",
+            "age:=7",
+        ])
+        .assert()
+        .failure()
+        .stderr(contains("Request body doesn't match xh.test.Person"));
+
+    get_command()
+        .args([
+            "--proto-descriptor=tests/fixtures/protobuf/person.pb",
+            "--proto-message=xh.test.Nobody",
+            ":",
+        ])
+        .assert()
+        .failure()
+        .stderr(contains("Message type 'xh.test.Nobody' not found"));
+}
+
+#[test]
+fn binary_hexdump() {
+    let server = server::http(|_req| async move {
+        hyper::Response::builder()
+            .header("content-type", "application/octet-stream")
+            .body(b"\x00\x01hello\xff".as_slice().into())
+            .unwrap()
+    });
+    for stream in ["--no-stream", "--stream"] {
+        get_command()
+            .args(["--print=b", "--binary=hexdump", stream, &server.base_url()])
+            .assert()
+            .stdout(indoc! {"
+                00000000: 0001 6865 6c6c 6fff                      ..hello.
+
+            "});
+    }
+    get_command()
+        .args([
+            "--print=b",
+            "--binary=base64",
+            "--binary-limit=4",
+            &server.base_url(),
+        ])
+        .assert()
+        .stdout(indoc! {"
+            AAFoZQ==
+            [more data not shown, see --binary-limit]
+
+        "});
+    get_command()
+        .args(["--print=b", &server.base_url()])
+        .assert()
+        .stdout(BINARY_SUPPRESSOR);
+}
+
+#[test]
+fn image_response() {
+    use base64::{Engine as _, engine::general_purpose::STANDARD};
+
+    // A 1x1 PNG
+    const PNG: &[u8] = b"\x89PNG\r\n\x1a\n\x00\x00\x00\rIHDR\x00\x00\x00\x01\x00\x00\x00\x01\x08\x06\x00\x00\x00\x1f\x15\xc4\x89\x00\x00\x00\rIDATx\x9cc\x00\x01\x00\x00\x05\x00\x01\r\n-\xb4\x00\x00\x00\x00IEND\xaeB`\x82";
+    let server = server::http(|req| async move {
+        let body = match req.uri().path() {
+            "/broken" => b"\x00not an image".to_vec(),
+            // Too big to be shown inline
+            "/huge" => [PNG, &[0; 10 * 1024 * 1024]].concat(),
+            _ => PNG.to_vec(),
+        };
+        hyper::Response::builder()
+            .header("content-type", "image/png")
+            .body(body.into())
+            .unwrap()
+    });
+    let image_command = |path: &str, vars: &[(&str, &str)]| {
+        let mut cmd = get_command();
+        for var in [
+            "TERM",
+            "TERM_PROGRAM",
+            "LC_TERMINAL",
+            "KITTY_WINDOW_ID",
+            "TMUX",
+            "STY",
+        ] {
+            cmd.env_remove(var);
+        }
+        cmd.envs(vars.iter().copied());
+        cmd.args(["--print=b", &server.url(path)]);
+        cmd
+    };
+
+    for stream in ["--no-stream", "--stream"] {
+        image_command("/", &[])
+            .arg(stream)
+            .assert()
+            .stdout(indoc! {"
+                +----------------------------+
+                | NOTE: PNG image, 1x1, 67 B |
+                +----------------------------+
+
+            "});
+    }
+    image_command("/", &[("TERM_PROGRAM", "iTerm.app")])
+        .arg("--pretty=all")
+        .assert()
+        .stdout(format!(
+            "\x1b]1337;File=inline=1;size=67:{}\x07\n\n",
+            STANDARD.encode(PNG)
+        ));
+    image_command("/", &[("KITTY_WINDOW_ID", "1")])
+        .arg("--pretty=all")
+        .assert()
+        .stdout(format!(
+            "\x1b_Ga=T,f=100,q=2,m=0;{}\x1b\\\n\n",
+            STANDARD.encode(PNG)
+        ));
+    // The escape sequences wouldn't make it through tmux
+    image_command("/", &[("KITTY_WINDOW_ID", "1"), ("TMUX", "1")])
+        .arg("--pretty=all")
+        .assert()
+        .stdout(contains("NOTE: PNG image"));
+    // Nor would they be expected without colors
+    for pretty in ["--pretty=format", "--pretty=none"] {
+        image_command("/", &[("TERM_PROGRAM", "iTerm.app")])
+            .arg(pretty)
+            .assert()
+            .stdout(contains("NOTE: PNG image"));
+    }
+    image_command("/huge", &[("TERM_PROGRAM", "iTerm.app")])
+        .arg("--pretty=all")
+        .assert()
+        .stdout(contains("NOTE: PNG image, 1x1, 10.00 MiB"));
+    image_command("/", &[])
+        .arg("--binary=hexdump")
+        .assert()
+        .stdout(contains("00000000: 8950 4e47"));
+    image_command("/broken", &[])
+        .assert()
+        .stdout(BINARY_SUPPRESSOR);
+    redirecting_command()
+        .args(["--print=b", &server.base_url()])
+        .assert()
+        .stdout(PNG);
+}
+
+#[cfg(unix)]
+#[test]
+fn pager() {
+    let server = server::http(|req| async move {
+        let body = match req.uri().path() {
+            "/long" => "line\n".repeat(10),
+            "/huge" => "line\n".repeat(200_000),
+            _ => "line\n".to_string(),
+        };
+        hyper::Response::new(body.into())
+    });
+    let pager_command = |pager: &str| {
+        let mut cmd = get_command();
+        cmd.env("XH_PAGER", pager)
+            .env("LINES", "5")
+            .env_remove("PAGER")
+            .arg("--print=b");
+        cmd
+    };
+    let paged = format!("{}paged:\n", "paged:line\n".repeat(10));
+
+    pager_command("sed s/^/paged:/")
+        .arg(server.url("/long"))
+        .assert()
+        .stdout(paged.clone());
+    // Output that fits on the screen is shown directly
+    pager_command("sed s/^/paged:/")
+        .arg(server.base_url())
+        .assert()
+        .stdout("line\n\n");
+    pager_command("sed s/^/paged:/")
+        .args(["--no-pager", &server.url("/long")])
+        .assert()
+        .stdout(format!("{}\n", "line\n".repeat(10)));
+    // Streamed output is shown as it arrives
+    pager_command("sed s/^/paged:/")
+        .args(["--stream", &server.url("/long")])
+        .assert()
+        .stdout(format!("{}\n", "line\n".repeat(10)));
+    get_command()
+        .env_remove("XH_PAGER")
+        .env("PAGER", "sed s/^/paged:/")
+        .env("LINES", "5")
+        .args(["--print=b", "--pager", &server.url("/long")])
+        .assert()
+        .stdout(paged);
+    redirecting_command()
+        .env("XH_PAGER", "sed s/^/paged:/")
+        .env("LINES", "5")
+        .args(["--print=b", &server.url("/long")])
+        .assert()
+        .stdout("line\n".repeat(10));
+    // Quitting the pager early isn't an error
+    pager_command("head -n 1")
+        .arg(server.url("/huge"))
+        .assert()
+        .success()
+        .stdout("line\n")
+        .stderr("");
+}
+
+#[test]
+fn body_display_limits() {
+    let server = server::http(|req| async move {
+        match req.uri().path() {
+            "/json" => hyper::Response::builder()
+                .header("content-type", "application/json")
+                .body(r#"{"a": [1, 2, 3]}"#.into())
+                .unwrap(),
+            _ => hyper::Response::new("line\n".repeat(10).into()),
+        }
+    });
+
+    for stream in ["--no-stream", "--stream"] {
+        get_command()
+            .args(["--print=b", "--head-lines=3", stream, &server.base_url()])
+            .assert()
+            .stdout(indoc! {"
+                line
+                line
+                line
+                … the rest isn't shown, use --download to get the whole body
+
+            "});
+    }
+    get_command()
+        .args(["--print=b", "--max-body-display=12", &server.base_url()])
+        .assert()
+        .stdout(indoc! {"
+            line
+            line
+            li
+            … 38 B more, use --download to get the whole body
+
+        "});
+    get_command()
+        .args(["--print=b", "--head-lines=2", &server.url("/json")])
+        .assert()
+        .stdout(indoc! {r#"
+            {
+                "a": [
+            … the rest isn't shown, use --download to get the whole body
+
+        "#});
+    // Nothing was cut off
+    get_command()
+        .args(["--print=b", "--head-lines=10", &server.base_url()])
+        .assert()
+        .stdout(format!("{}\n", "line\n".repeat(10)));
+    redirecting_command()
+        .args(["--print=b", "--head-lines=3", &server.base_url()])
+        .assert()
+        .stdout("line\n".repeat(10));
+}
+
+#[test]
+fn diff_responses() {
+    let server = server::http(|req| async move {
+        match req.uri().path() {
+            "/staging" => hyper::Response::builder()
+                .header("content-type", "application/json")
+                .header("x-env", "staging")
+                .body(r#"{"a":1,"b":[1,2]}"#.into())
+                .unwrap(),
+            "/prod" => hyper::Response::builder()
+                .header("content-type", "application/json")
+                .header("x-env", "prod")
+                .body(r#"{"b":[1,3],"a":2}"#.into())
+                .unwrap(),
+            "/text" => hyper::Response::new("a\nb\nc\n".into()),
+            _ => hyper::Response::new("a\nB\nc\n".into()),
+        }
+    });
+    let (staging, prod) = (server.url("/staging"), server.url("/prod"));
+    let expected = formatdoc! {"
+        --- {staging}
+        +++ {prod}
+        -x-env: staging
+        +x-env: prod
+        -.a: 1
+        +.a: 2
+        -.b[1]: 2
+        +.b[1]: 3
+    "};
+
+    get_command()
+        .args([&staging, "--diff-url", &prod])
+        .assert()
+        .code(1)
+        .stdout(expected.clone());
+    get_command()
+        .args(["diff", &staging, &prod])
+        .assert()
+        .code(1)
+        .stdout(expected);
+    get_command()
+        .args(["diff", &prod, &prod])
+        .assert()
+        .success()
+        .stdout("");
+    // Errors have their own exit code, so they can't be mistaken for a difference
+    get_command()
+        .args(["diff", &prod, "http://127.0.0.1:1/"])
+        .assert()
+        .code(2)
+        .stderr(contains("error"));
+    get_command()
+        .args(["diff", &server.url("/text"), &server.base_url()])
+        .assert()
+        .code(1)
+        .stdout(formatdoc! {"
+            --- {}
+            +++ {}
+            @@ -1,3 +1,3 @@
+             a
+            -b
+            +B
+             c
+        ", server.url("/text"), server.url("/")});
+
+    // Only the order of the keys is different
+    let dir = tempdir().unwrap();
+    let saved = dir.path().join("saved.json");
+    std::fs::write(&saved, r#"{"a": 2, "b": [1, 3]}"#).unwrap();
+    get_command()
+        .args([&prod, "--diff-file"])
+        .arg(&saved)
+        .assert()
+        .success()
+        .stdout("");
+    get_command()
+        .args(["diff", &staging])
+        .arg(format!("@{}", saved.display()))
+        .assert()
+        .code(1)
+        .stdout(formatdoc! {"
+            --- {staging}
+            +++ {}
+            -.a: 1
+            +.a: 2
+            -.b[1]: 2
+            +.b[1]: 3
+        ", saved.display()});
+}
+
+#[test]
+fn diff_session_is_read_only() {
+    let server = server::http(|req| async move {
+        let hello = req.headers().get("hello").cloned();
+        let mut response = hyper::Response::builder().header("set-cookie", "lang=en");
+        if let Some(hello) = hello {
+            response = response.header("hello", hello);
+        }
+        response.body("".into()).unwrap()
+    });
+
+    let session_file = NamedTempFile::new().unwrap();
+    let session_content = serde_json::json!({
+        "__meta__": { "about": "xh session file", "xh": "0.0.0" },
+        "auth": { "type": null, "raw_auth": null },
+        "cookies": [],
+        "headers": [
+            { "name": "hello", "value": "world" }
+        ]
+    });
+    std::fs::write(&session_file, session_content.to_string()).unwrap();
+
+    get_command()
+        .arg(server.base_url())
+        .arg("--diff-session")
+        .arg(session_file.path())
+        .assert()
+        .code(1)
+        .stdout(contains("+hello: world"));
+
+    assert_eq!(
+        serde_json::from_str::<serde_json::Value>(
+            &fs::read_to_string(session_file.path()).unwrap()
+        )
+        .unwrap(),
+        session_content
+    );
+}
@@ -0,0 +1,11 @@
+
+
+person.protoxh.test"U
+Person
+name (	
+
+id (
+emails (	!
+address (2.xh.test.Address"
+Address
+city (	bproto3
\ No newline at end of file